@@ -0,0 +1,40 @@
+-- Biomes are picked by the climate at each position. Temperature and humidity range from -1 to 1
+-- and the biome registered closest to the climate is used.
+-- register_biome(biome) takes a table with
+--   name, surface and filler (block names, filler is used below the surface block),
+--   and optionally temperature, humidity, filler_depth, min_height and max_height (surface height
+--   range, blended with neighbouring biomes at the borders), flower_density (chance of a flower on
+--   each surface block) and flowers (block names)
+-- Trees and other structures are registered in structures.lua
+-- Registering a biome with an existing name, e.g. from a mod, replaces it
+
+register_biome({
+    name = "plains",
+    temperature = 0.0, humidity = 0.0,
+    surface = "Grass", filler = "Dirt", filler_depth = 6,
+    min_height = 60, max_height = 80,
+    flower_density = 0.04, flowers = { "TopGrass", "Rose" },
+})
+
+register_biome({
+    name = "forest",
+    temperature = 0.3, humidity = 0.6,
+    surface = "Grass", filler = "Dirt", filler_depth = 6,
+    min_height = 62, max_height = 86,
+    flower_density = 0.02, flowers = { "TopGrass" },
+})
+
+register_biome({
+    name = "meadow",
+    temperature = 0.5, humidity = -0.3,
+    surface = "Grass", filler = "Dirt", filler_depth = 5,
+    min_height = 60, max_height = 70,
+    flower_density = 0.15, flowers = { "Rose", "TopGrass" },
+})
+
+register_biome({
+    name = "hills",
+    temperature = -0.6, humidity = -0.4,
+    surface = "Stone", filler = "Stone", filler_depth = 3,
+    min_height = 70, max_height = 110,
+})
@@ -0,0 +1,41 @@
+-- Gameplay event handlers
+-- Every script in this directory besides loadAssetInfo.lua, generateChunkColumn.lua, biomes.lua,
+-- structures.lua and underground.lua is loaded as an event script
+--
+-- register_event_handler(event, handler) registers a function for one of these events:
+--   on_player_join   { player }
+--   on_player_leave  { player }
+--   on_block_place   { player, x, y, z, id, state } return false to cancel, state is a table of
+--                                                   the block's state property values
+--   on_block_placed  { player, x, y, z, id, state } runs once the block is in the world, such as
+--                                                   to give it a block entity
+--   on_block_break   { player, x, y, z, id }        return false to cancel
+--   on_chat          { player, message }            return false to cancel
+--   on_tick          { tick }
+--
+-- Handlers may use the world API:
+--   world.get_block(x, y, z)
+--   world.set_block(x, y, z, id, state)           state is optional, e.g. { axis = "x" }
+--   world.get_state(x, y, z)                       table of the block's state property values
+--   world.get_block_entity(x, y, z)                table attached to the block, such as chest contents, or nil
+--   world.set_block_entity(x, y, z, entity)        entity = nil removes it, replacing the block removes it too
+--   world.send_message(player, message)            player = nil sends to everyone
+--   world.teleport(player, x, y, z)
+--   world.get_id_by_name(name)
+--   world.get_biome(x, z)                          name of the biome
+--   world.has_tag(id, tag)                         whether the item has the tag, see loadAssetInfo.lua
+
+register_event_handler("on_player_join", function(event)
+    world.send_message(nil, event.player .. " joined the game")
+end)
+
+register_event_handler("on_player_leave", function(event)
+    world.send_message(nil, event.player .. " left the game")
+end)
+
+-- Example: stop players from cutting trees near the spawn
+-- register_event_handler("on_block_break", function(event)
+--     if world.has_tag(event.id, "wood") and math.abs(event.x) < 32 and math.abs(event.z) < 32 then
+--         return false
+--     end
+-- end)
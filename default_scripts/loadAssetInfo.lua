@@ -1,46 +1,86 @@
+-- define_item(item) adds an item, items get ids in the order they are defined. It takes a table with
+--   name and optionally
+--   type ("Air", "BlockCube", "BlockCross", "BlockSlab", "BlockStairs", "BlockFence", "BlockPane",
+--     "BlockModel" or "UserItem", the default), model (asset path of the model, only for BlockModel),
+--   transparent (false by default), show_in_inventory (true by default),
+--   textures ({ top = { x, y }, bottom = { x, y }, side = { x, y } } in the atlas set with setAtlas,
+--     bottom and side use the top texture if they are not given, and north (-z), south, east (+x) and
+--     west may be set separately, otherwise they use the side texture),
+--   hardness (seconds to break the block, negative if it cannot be broken, 1 by default),
+--   light (emitted light, 0 to 15), stack_size (64 by default), solid (whether players collide with it,
+--   true for every block but BlockCross by default), drops (item names, or { name = "Item", count = n }
+--   tables, given when the block is broken, the item itself by default) and tags (names for scripts, see
+--   events.lua), states (a list of { name = "property", values = { "a", "b" } } tables, the properties kept
+--   with every block of the item, such as the direction it faces, the first value is the default)
+-- Unknown keys, types and values of the wrong kind are errors.
+-- setInfo(name, type, isTransparent, showInInventory) or setInfo(item), setCoords(topX, topY, sideX, sideY,
+-- bottomX, bottomY) and pushItem() still work and define the same items.
+
 setAtlas("textureAtlas.png")
 
-setInfo("Air", "Air", true, false)
-setCoords()
-pushItem()
+define_item({ name = "Air", type = "Air", transparent = true, show_in_inventory = false })
+
+define_item({
+    name = "Dirt", type = "BlockCube", hardness = 0.5, tags = { "soil" },
+    textures = { top = { 1, 9 } },
+})
+
+define_item({
+    name = "Grass", type = "BlockCube", hardness = 0.6, drops = { "Dirt" }, tags = { "soil" },
+    textures = { top = { 3, 9 }, side = { 2, 9 }, bottom = { 1, 9 } },
+})
 
-setInfo("Dirt", "BlockCube")
-setCoords(1, 9)
-pushItem()
+define_item({
+    name = "Stone", type = "BlockCube", hardness = 1.5, drops = { "Cobblestone" }, tags = { "stone" },
+    textures = { top = { 4, 9 } },
+})
 
-setInfo("Grass", "BlockCube")
-setCoords(3, 9, 2, 9, 1, 9)
-pushItem()
+define_item({
+    name = "Cobblestone", type = "BlockCube", hardness = 2, tags = { "stone" },
+    textures = { top = { 5, 9 } },
+})
 
-setInfo("Stone", "BlockCube")
-setCoords(4, 9)
-pushItem()
+define_item({
+    name = "Log", type = "BlockCube", hardness = 2, tags = { "wood" },
+    textures = { top = { 6, 9 }, side = { 7, 9 } },
+    states = { { name = "axis", values = { "y", "x", "z" } } },
+})
 
-setInfo("Cobblestone", "BlockCube")
-setCoords(5, 9)
-pushItem()
+define_item({
+    name = "Leaves", type = "BlockCube", transparent = true, hardness = 0.2, drops = {}, tags = { "plant" },
+    textures = { top = { 8, 9 } },
+})
 
-setInfo("Log", "BlockCube")
-setCoords(6, 9, 7, 9, 6, 9)
-pushItem()
+define_item({
+    name = "Bedrock", type = "BlockCube", hardness = -1, drops = {},
+    textures = { top = { 9, 9 } },
+})
 
-setInfo("Leaves", "BlockCube", true)
-setCoords(8, 9)
-pushItem()
+define_item({
+    name = "Glass", type = "BlockCube", transparent = true, hardness = 0.3, drops = {},
+    textures = { top = { 0, 8 } },
+})
 
-setInfo("Bedrock", "BlockCube")
-setCoords(9, 9)
-pushItem()
+define_item({
+    name = "TopGrass", type = "BlockCross", transparent = true, hardness = 0, drops = {}, tags = { "plant" },
+    textures = { top = { 9, 0 } },
+})
 
-setInfo("Glass", "BlockCube", true)
-setCoords(0, 8)
-pushItem()
+define_item({
+    name = "Rose", type = "BlockCross", transparent = true, hardness = 0, tags = { "plant" },
+    textures = { top = { 8, 0 } },
+})
 
+define_item({
+    name = "CoalOre", type = "BlockCube", hardness = 3, tags = { "stone", "ore" },
+    textures = { top = { 1, 8 } },
+})
 
-setInfo("TopGrass", "BlockCross", true)
-setCoords(9, 0)
-pushItem()
+define_item({
+    name = "IronOre", type = "BlockCube", hardness = 3, tags = { "stone", "ore" },
+    textures = { top = { 2, 8 } },
+})
 
-setInfo("Rose", "BlockCross", true)
-setCoords(8, 0)
-pushItem()
+-- Replaces blocks of saved worlds whose item no longer exists, the server adds an untextured one
+-- if no script defines it
+define_item({ name = "Unknown", type = "BlockCube", textures = { top = { 3, 8 } } })
@@ -0,0 +1,72 @@
+-- Structures are placed on top of the highest block of a column after it is generated.
+-- register_structure(structure) takes a table with
+--   name and either blocks, a list of { x, y, z, "Block name" } relative to the origin, or
+--   generate, a function called with a structure table to build the structure with
+--   structure.place(x, y, z, id) relative to the origin and structure.random(min, max)
+--   and optionally radius (furthest block from the origin along x or z, worked out from blocks for
+--   templates), spacing (size of the square cells the world is divided into, each holding at most one
+--   instance, at least 2 * radius + 1), chance (of a cell holding an instance), biomes (names of the
+--   biomes the structure is placed in) and surfaces (block names it may be placed on)
+-- The origin of an instance only depends on the world seed, so structures can cross column borders.
+-- Structures never overlap instances of structures registered before them
+-- Registering a structure with an existing name, e.g. from a mod, replaces it
+
+local cobblestone_id = get_id_by_name("Cobblestone")
+local dirt_id = get_id_by_name("Dirt")
+local log_id = get_id_by_name("Log")
+local leaves_id = get_id_by_name("Leaves")
+
+local function tree(structure)
+    local height = structure.random(4, 7)
+    structure.place(0, -1, 0, dirt_id)
+    for x = -2, 2 do
+        for z = -2, 2 do
+            structure.place(x, height, z, leaves_id)
+        end
+    end
+    structure.place(0, height + 1, 0, leaves_id)
+    for y = 0, height do
+        structure.place(0, y, 0, log_id)
+    end
+end
+
+register_structure({
+    name = "ruin",
+    radius = 4, spacing = 96, chance = 0.3,
+    biomes = { "plains", "meadow", "hills" }, surfaces = { "Grass", "Stone" },
+    generate = function(structure)
+        for x = -4, 4 do
+            for z = -4, 4 do
+                if math.abs(x) == 4 or math.abs(z) == 4 then
+                    for y = -1, structure.random(-1, 3) do
+                        structure.place(x, y, z, cobblestone_id)
+                    end
+                end
+            end
+        end
+    end,
+})
+
+register_structure({
+    name = "boulder",
+    spacing = 24, chance = 0.2,
+    biomes = { "plains", "hills" }, surfaces = { "Grass", "Stone" },
+    blocks = {
+        { 0, 0, 0, "Cobblestone" }, { 1, 0, 0, "Cobblestone" }, { 0, 0, 1, "Cobblestone" },
+        { -1, 0, 0, "Cobblestone" }, { 0, 0, -1, "Cobblestone" }, { 0, 1, 0, "Cobblestone" },
+    },
+})
+
+register_structure({
+    name = "tree",
+    radius = 2, spacing = 7, chance = 0.05,
+    biomes = { "plains", "meadow" }, surfaces = { "Grass" },
+    generate = tree,
+})
+
+register_structure({
+    name = "forest_tree",
+    radius = 2, spacing = 5, chance = 0.35,
+    biomes = { "forest" }, surfaces = { "Grass" },
+    generate = tree,
+})
@@ -0,0 +1,33 @@
+-- Caves and ores, added to every column after it is generated and before its structures are built.
+-- Caves are carved first, then the ores are placed in the order they are registered.
+-- set_caves(caves) takes a table with
+--   noise (settings as for create_noise in generateChunkColumn.lua, sampled in 3D) and optionally
+--   threshold (blocks where the noise is above it are carved), min_height, max_height and replaces
+--   (block names that are carved, only Stone by default)
+-- set_caves(nil) turns caves off, the last call is used
+-- register_ore(ore) takes a table with
+--   name and block (block name), and optionally min_height, max_height, vein_size (blocks in a vein),
+--   frequency (veins in a column on average) and replaces (block names the veins replace, only Stone by
+--   default)
+-- Registering an ore with an existing name, e.g. from a mod, replaces it
+
+set_caves({
+    noise = { type = "OpenSimplex2", seed_offset = 10, frequency = 0.02, fractal = "Ridged", octaves = 2 },
+    threshold = 0.6,
+    min_height = 5, max_height = 100,
+    replaces = { "Stone", "Dirt" },
+})
+
+register_ore({
+    name = "coal",
+    block = "CoalOre",
+    min_height = 5, max_height = 90,
+    vein_size = 12, frequency = 8,
+})
+
+register_ore({
+    name = "iron",
+    block = "IronOre",
+    min_height = 5, max_height = 50,
+    vein_size = 6, frequency = 4,
+})
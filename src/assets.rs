@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fs, io};
+
+/// Most bytes of an asset sent in a single packet
+pub const ASSET_CHUNK_SIZE: usize = 32 * 1024;
+/// Largest asset file sent to clients, larger files are skipped
+const MAX_ASSET_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A file sent to clients, such as a texture atlas
+pub struct Asset {
+    /// Path relative to the asset directory, with `/` separators
+    pub name: String,
+    /// Hash of the contents, lets clients keep assets they already have
+    pub hash: u64,
+    pub data: Vec<u8>,
+}
+
+impl Asset {
+    pub fn new(name: String, data: Vec<u8>) -> Asset {
+        Asset {
+            name,
+            hash: content_hash(&data),
+            data,
+        }
+    }
+
+    /// Gets the part of the asset starting at `offset`, at most `ASSET_CHUNK_SIZE` bytes
+    ///
+    /// Returns None if `offset` is past the end, the chunk at the end is empty
+    pub fn chunk(&self, offset: usize) -> Option<&[u8]> {
+        let end = offset.saturating_add(ASSET_CHUNK_SIZE).min(self.data.len());
+        self.data.get(offset..end)
+    }
+}
+
+/// The assets of a world, from its asset directory and its mods
+pub struct AssetStore {
+    /// Assets by name
+    assets: BTreeMap<String, Asset>,
+}
+
+impl AssetStore {
+    /// Reads every file in `directories`, an asset replaces assets with the same name in earlier directories
+    ///
+    /// Directories that do not exist are skipped
+    pub fn load(directories: &[String]) -> io::Result<AssetStore> {
+        let mut assets = BTreeMap::new();
+        for directory in directories {
+            let directory = Path::new(directory);
+            if directory.is_dir() {
+                AssetStore::load_directory(directory, directory, &mut assets)?;
+            }
+        }
+
+        Ok(AssetStore { assets })
+    }
+
+    fn load_directory(
+        root: &Path,
+        directory: &Path,
+        assets: &mut BTreeMap<String, Asset>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                AssetStore::load_directory(root, &path, assets)?;
+                continue;
+            }
+            if fs::metadata(&path)?.len() > MAX_ASSET_SIZE {
+                eprintln!("Asset {:?} is larger than the limit, skipping it", path);
+                continue;
+            }
+
+            let name = path
+                .strip_prefix(root)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            assets.insert(name.clone(), Asset::new(name, fs::read(&path)?));
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Asset> {
+        self.assets.get(name)
+    }
+
+    /// Gets every asset, sorted by name
+    pub fn get_assets(&self) -> impl Iterator<Item = &Asset> {
+        self.assets.values()
+    }
+}
+
+/// Hashes `data` with 64 bit FNV-1a, which is stable between builds and platforms
+///
+/// Only meant to tell versions of an asset apart, not to guard against tampering
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assets_from_directories() {
+        let root =
+            std::env::temp_dir().join(format!("voxelbuilder_assets_{}", rand::random::<u32>()));
+        let files = [
+            ("base/atlas.png", "base atlas"),
+            ("base/sounds/step.ogg", "step"),
+            ("pack/atlas.png", "pack atlas"),
+        ];
+        for (file, contents) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let directories = ["base", "pack", "missing"]
+            .map(|directory| root.join(directory).to_string_lossy().to_string());
+        let assets = AssetStore::load(&directories).unwrap();
+        let names: Vec<_> = assets
+            .get_assets()
+            .map(|asset| asset.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["atlas.png", "sounds/step.ogg"]);
+
+        // Later directories replace assets, so texture packs can be added as mods
+        let atlas = assets.get("atlas.png").unwrap();
+        assert_eq!(atlas.data, b"pack atlas");
+        assert_eq!(
+            atlas.hash,
+            Asset::new(String::new(), b"pack atlas".to_vec()).hash
+        );
+        assert_ne!(atlas.hash, assets.get("sounds/step.ogg").unwrap().hash);
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn test_asset_chunks() {
+        let asset = Asset::new("big".to_string(), vec![7; ASSET_CHUNK_SIZE * 2 + 10]);
+
+        assert_eq!(asset.chunk(0).unwrap().len(), ASSET_CHUNK_SIZE);
+        assert_eq!(asset.chunk(ASSET_CHUNK_SIZE * 2).unwrap().len(), 10);
+        assert_eq!(asset.chunk(ASSET_CHUNK_SIZE * 2 + 5).unwrap().len(), 5);
+        assert_eq!(asset.chunk(asset.data.len()).unwrap().len(), 0);
+        assert!(asset.chunk(asset.data.len() + 1).is_none());
+        assert!(asset.chunk(usize::MAX).is_none());
+    }
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+
+use crate::events::{EventScripts, GameEvent, ScriptAction};
+use crate::vector_types::Vec3;
+use crate::world::{World, WorldError};
+
+/// A change to a single block requested by a client
+pub enum BlockEdit {
+    /// Places the block id with a state
+    Place(i32, u16),
+    Destroy,
+}
+
+/// Reason a client's block edit was refused
+#[derive(Debug, PartialEq)]
+pub enum EditRejection {
+    UnknownPosition,
+    OutOfReach(f32),
+    OutOfBounds,
+    NotPlaceable(i32),
+    InvalidState(i32, u16),
+    Occupied(i32),
+    AlreadyEmpty,
+    Unbreakable(i32),
+    /// An event script cancelled the edit
+    Cancelled,
+    /// The world refused the write after the edit was accepted
+    WriteFailed(WorldError),
+}
+
+impl fmt::Display for EditRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditRejection::UnknownPosition => write!(f, "player position is not known yet"),
+            EditRejection::OutOfReach(distance) => {
+                write!(f, "block is {:.1} blocks away from the player", distance)
+            }
+            EditRejection::OutOfBounds => write!(f, "block is outside the world height"),
+            EditRejection::NotPlaceable(id) => write!(f, "item id {} cannot be placed", id),
+            EditRejection::InvalidState(id, state) => {
+                write!(f, "item id {} has no state {}", id, state)
+            }
+            EditRejection::Occupied(id) => write!(f, "cannot place block over id {}", id),
+            EditRejection::AlreadyEmpty => write!(f, "cannot destroy an empty block"),
+            EditRejection::Unbreakable(id) => write!(f, "block id {} cannot be broken", id),
+            EditRejection::Cancelled => write!(f, "cancelled by an event script"),
+            EditRejection::WriteFailed(e) => write!(f, "could not write the block: {}", e),
+        }
+    }
+}
+
+/// Checks whether `edit` at `position` may be applied by a player standing at `player_position`
+pub fn validate_edit(
+    world: &mut World,
+    player_position: Option<Vec3<f32>>,
+    reach_distance: f32,
+    position: &Vec3<i32>,
+    edit: &BlockEdit,
+) -> Result<(), EditRejection> {
+    let player_position = player_position.ok_or(EditRejection::UnknownPosition)?;
+    let distance = distance_to_block(&player_position, position);
+    if distance > reach_distance {
+        return Err(EditRejection::OutOfReach(distance));
+    }
+
+    if !world.is_height_in_bounds(position.y) {
+        return Err(EditRejection::OutOfBounds);
+    }
+
+    let existing_id = world.get_block(position);
+    match edit {
+        BlockEdit::Place(id, state) => {
+            let item = world.get_item_manager().get_item_by_id(*id);
+            let item = match item {
+                Some(item) if item.is_placeable() => item,
+                _ => return Err(EditRejection::NotPlaceable(*id)),
+            };
+            if *state as u32 >= item.state_count() {
+                return Err(EditRejection::InvalidState(*id, *state));
+            }
+            if existing_id > 0 {
+                return Err(EditRejection::Occupied(existing_id));
+            }
+        }
+        BlockEdit::Destroy => {
+            if existing_id < 1 {
+                return Err(EditRejection::AlreadyEmpty);
+            }
+            let breakable = world
+                .get_item_manager()
+                .get_item_by_id(existing_id)
+                .is_none_or(|item| item.is_breakable());
+            if !breakable {
+                return Err(EditRejection::Unbreakable(existing_id));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates `edit` by `player`, lets the event handlers cancel it and writes it into `world`
+///
+/// Returns the actions requested by the handlers along with the result, also when the edit was
+/// rejected
+pub fn apply_edit(
+    world: &mut World,
+    events: &EventScripts,
+    player: &str,
+    player_position: Option<Vec3<f32>>,
+    reach_distance: f32,
+    position: &Vec3<i32>,
+    edit: &BlockEdit,
+) -> (Result<(), EditRejection>, Vec<ScriptAction>) {
+    let mut actions = Vec::new();
+    if let Err(rejection) = validate_edit(world, player_position, reach_distance, position, edit) {
+        return (Err(rejection), actions);
+    }
+
+    let event = match edit {
+        BlockEdit::Place(id, state) => GameEvent::BlockPlace {
+            player,
+            position: *position,
+            id: *id,
+            state: *state,
+        },
+        BlockEdit::Destroy => GameEvent::BlockBreak {
+            player,
+            position: *position,
+            id: world.get_block(position),
+        },
+    };
+    let outcome = events.dispatch(world, &event);
+    actions.extend(outcome.actions);
+    if outcome.cancelled {
+        return (Err(EditRejection::Cancelled), actions);
+    }
+
+    if let Err(rejection) = write_edit(world, position, edit) {
+        return (Err(rejection), actions);
+    }
+    if let BlockEdit::Place(id, state) = edit {
+        // Runs after the write so handlers can give the new block a block entity
+        let event = GameEvent::BlockPlaced {
+            player,
+            position: *position,
+            id: *id,
+            state: *state,
+        };
+        actions.extend(events.dispatch(world, &event).actions);
+    }
+
+    (Ok(()), actions)
+}
+
+/// Writes `edit` into `world` without validating it
+fn write_edit(
+    world: &mut World,
+    position: &Vec3<i32>,
+    edit: &BlockEdit,
+) -> Result<(), EditRejection> {
+    let result = match edit {
+        BlockEdit::Place(id, state) => world.set_block_with_state(position, *id, *state),
+        BlockEdit::Destroy => world.set_block(position, 0),
+    };
+    result.map_err(EditRejection::WriteFailed)
+}
+
+/// Distance from `player_position` to the center of the block at `position`
+fn distance_to_block(player_position: &Vec3<f32>, position: &Vec3<i32>) -> f32 {
+    let dx = position.x as f32 + 0.5 - player_position.x;
+    let dy = position.y as f32 + 0.5 - player_position.y;
+    let dz = position.z as f32 + 0.5 - player_position.z;
+    (dx * dx + dy * dy + dz * dz).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::ScriptSources;
+    use crate::save_file::SaveFile;
+    use crate::scripting::{with_script_file, ScriptLimits};
+    use crate::world::EntityValue;
+
+    fn test_world() -> World {
+        let save = SaveFile::new(None);
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        World::new(save, &sources, ScriptLimits::default()).unwrap()
+    }
+
+    #[test]
+    fn test_edit_rejections() {
+        let mut world = test_world();
+        let id = |world: &World, name: &str| {
+            world
+                .get_item_manager()
+                .get_id_by_name(name.to_string())
+                .unwrap()
+        };
+        let stone = id(&world, "Stone");
+        let log = id(&world, "Log");
+        let bedrock = id(&world, "Bedrock");
+
+        let empty = Vec3::new(0, 100, 0);
+        let solid = Vec3::new(1, 100, 0);
+        let unbreakable = Vec3::new(0, 100, 1);
+        world.set_block(&empty, 0).unwrap();
+        world.set_block(&solid, stone).unwrap();
+        world.set_block(&unbreakable, bedrock).unwrap();
+
+        let player = Some(Vec3::new(0.5, 101.0, 0.5));
+        let top = Some(Vec3::new(0.5, 256.0, 0.5));
+        let mut validate = |player: Option<Vec3<f32>>, position: &Vec3<i32>, edit: BlockEdit| {
+            validate_edit(&mut world, player, 8.0, position, &edit)
+        };
+
+        assert_eq!(validate(player, &empty, BlockEdit::Place(stone, 0)), Ok(()));
+        assert_eq!(validate(player, &empty, BlockEdit::Place(log, 2)), Ok(()));
+        assert_eq!(validate(player, &solid, BlockEdit::Destroy), Ok(()));
+        assert!(matches!(
+            validate(player, &Vec3::new(0, 100, 20), BlockEdit::Destroy),
+            Err(EditRejection::OutOfReach(distance)) if distance > 8.0
+        ));
+
+        let cases = [
+            (
+                None,
+                empty,
+                BlockEdit::Place(stone, 0),
+                EditRejection::UnknownPosition,
+            ),
+            (
+                top,
+                Vec3::new(0, 256, 0),
+                BlockEdit::Place(stone, 0),
+                EditRejection::OutOfBounds,
+            ),
+            (
+                player,
+                empty,
+                BlockEdit::Place(0, 0),
+                EditRejection::NotPlaceable(0),
+            ),
+            (
+                player,
+                empty,
+                BlockEdit::Place(999, 0),
+                EditRejection::NotPlaceable(999),
+            ),
+            (
+                player,
+                empty,
+                BlockEdit::Place(log, 3),
+                EditRejection::InvalidState(log, 3),
+            ),
+            (
+                player,
+                empty,
+                BlockEdit::Place(stone, 1),
+                EditRejection::InvalidState(stone, 1),
+            ),
+            (
+                player,
+                solid,
+                BlockEdit::Place(stone, 0),
+                EditRejection::Occupied(stone),
+            ),
+            (
+                player,
+                empty,
+                BlockEdit::Destroy,
+                EditRejection::AlreadyEmpty,
+            ),
+            (
+                player,
+                unbreakable,
+                BlockEdit::Destroy,
+                EditRejection::Unbreakable(bedrock),
+            ),
+        ];
+        for (player, position, edit, expected) in cases {
+            assert_eq!(validate(player, &position, edit), Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_placed_blocks_get_block_entities() {
+        let events = with_script_file(
+            r#"
+            register_event_handler("on_block_place", function(event)
+                if event.y > 110 then
+                    return false
+                end
+                -- The block is not placed yet
+                world.set_block_entity(event.x, event.y, event.z, { text = "Too early" })
+            end)
+
+            register_event_handler("on_block_placed", function(event)
+                world.set_block_entity(event.x, event.y, event.z, { text = "Welcome" })
+            end)
+            "#,
+            |paths| EventScripts::load(paths, &ScriptLimits::default()),
+        )
+        .unwrap();
+        let mut world = test_world();
+        let log = world.get_item_manager().get_id_by_name("Log".to_string());
+        let log = log.unwrap();
+        let player = Some(Vec3::new(0.5, 111.0, 0.5));
+        let apply = |world: &mut World, position: &Vec3<i32>| {
+            let edit = BlockEdit::Place(log, 0);
+            apply_edit(world, &events, "alice", player, 8.0, position, &edit)
+        };
+
+        let sign = Vec3::new(0, 105, 0);
+        world.set_block(&sign, 0).unwrap();
+        let (result, actions) = apply(&mut world, &sign);
+        assert_eq!(result, Ok(()));
+        assert_eq!(actions, vec![ScriptAction::UpdateBlockEntity(sign)]);
+        assert_eq!(world.get_block(&sign), log);
+        let text = EntityValue::Table(vec![(
+            EntityValue::String("text".to_string()),
+            EntityValue::String("Welcome".to_string()),
+        )]);
+        assert_eq!(world.get_block_entity(&sign), Some(&text));
+
+        // Cancelled places are not written and do not run on_block_placed
+        let cancelled = Vec3::new(0, 112, 0);
+        world.set_block(&cancelled, 0).unwrap();
+        let (result, actions) = apply(&mut world, &cancelled);
+        assert_eq!(result, Err(EditRejection::Cancelled));
+        assert!(actions.is_empty());
+        assert_eq!(world.get_block(&cancelled), 0);
+        assert_eq!(world.get_block_entity(&cancelled), None);
+    }
+
+    #[test]
+    fn test_failed_writes_are_rejected() {
+        let mut world = test_world();
+        let above = Vec3::new(0, 256, 0);
+        assert_eq!(
+            write_edit(&mut world, &above, &BlockEdit::Destroy),
+            Err(EditRejection::WriteFailed(WorldError::OutOfBounds(above)))
+        );
+
+        let position = Vec3::new(0, 100, 0);
+        assert_eq!(
+            write_edit(&mut world, &position, &BlockEdit::Place(999, 0)),
+            Err(EditRejection::WriteFailed(WorldError::UnknownBlock(999)))
+        );
+        assert_eq!(
+            write_edit(&mut world, &position, &BlockEdit::Destroy),
+            Ok(())
+        );
+        assert_eq!(world.get_block(&position), 0);
+    }
+}
@@ -0,0 +1,127 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::vector_types::Vec2;
+
+/// Largest radius accepted by the regenerate command
+const MAX_REGENERATE_RADIUS: i32 = 64;
+
+/// Command typed into the server console
+#[derive(Debug, PartialEq)]
+pub enum ConsoleCommand {
+    Help,
+    /// Reload the item and column generation scripts
+    Reload,
+    /// Regenerate unmodified columns within `radius` columns of `center`
+    Regenerate {
+        center: Vec2<i32>,
+        radius: i32,
+    },
+}
+
+impl ConsoleCommand {
+    pub const USAGE: &'static str = "Commands:
+    help                      Show this message
+    reload                    Reload the item and column generation scripts
+    regenerate <x> <z> [r]    Regenerate unmodified columns within r (default 0) columns of column x,z";
+
+    /// Parses a line of console input
+    pub fn parse(line: &str) -> Result<ConsoleCommand, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        match (command, args.as_slice()) {
+            ("help", []) => Ok(ConsoleCommand::Help),
+            ("reload", []) => Ok(ConsoleCommand::Reload),
+            ("regenerate", [x, z]) | ("regenerate", [x, z, _]) => {
+                let parse = |value: &str| {
+                    value
+                        .parse::<i32>()
+                        .map_err(|_| format!("\"{}\" is not a valid number", value))
+                };
+                let radius = match args.get(2) {
+                    Some(radius) => parse(radius)?,
+                    None => 0,
+                };
+                if !(0..=MAX_REGENERATE_RADIUS).contains(&radius) {
+                    return Err(format!(
+                        "Radius must be between 0 and {}",
+                        MAX_REGENERATE_RADIUS
+                    ));
+                }
+                Ok(ConsoleCommand::Regenerate {
+                    center: Vec2::new(parse(x)?, parse(z)?),
+                    radius,
+                })
+            }
+            _ => Err(format!("Unknown command \"{}\"", line.trim())),
+        }
+    }
+}
+
+/// Reads lines from stdin on a background thread so the server loop never blocks on input
+pub struct Console {
+    receiver: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Console {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                // Stop once stdin closes or the server has shut down
+                let sent = line.map(|line| sender.send(line).is_ok());
+                if !matches!(sent, Ok(true)) {
+                    break;
+                }
+            }
+        });
+
+        Console { receiver }
+    }
+
+    /// Gets the next non empty line typed into the console, if any
+    pub fn try_read_line(&self) -> Option<String> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => return Some(line),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(ConsoleCommand::parse("reload"), Ok(ConsoleCommand::Reload));
+        assert_eq!(ConsoleCommand::parse(" help "), Ok(ConsoleCommand::Help));
+        assert_eq!(
+            ConsoleCommand::parse("regenerate -3 4"),
+            Ok(ConsoleCommand::Regenerate {
+                center: Vec2::new(-3, 4),
+                radius: 0
+            })
+        );
+        assert_eq!(
+            ConsoleCommand::parse("regenerate 0 0 5"),
+            Ok(ConsoleCommand::Regenerate {
+                center: Vec2::new(0, 0),
+                radius: 5
+            })
+        );
+
+        assert!(ConsoleCommand::parse("regenerate 0").is_err());
+        assert!(ConsoleCommand::parse("regenerate 0 0 -1").is_err());
+        assert!(ConsoleCommand::parse("regenerate 0 0 100000").is_err());
+        assert!(ConsoleCommand::parse("regenerate a 0").is_err());
+        assert!(ConsoleCommand::parse("reload now").is_err());
+        assert!(ConsoleCommand::parse("stop").is_err());
+    }
+}
@@ -0,0 +1,608 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+use rlua::{Context, Function, Lua, Table, Value};
+
+use crate::items::ItemManager;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+use crate::vector_types::Vec3;
+use crate::world::{EntityValue, World};
+
+/// Registry key of the table mapping event names to their handlers
+const HANDLERS_KEY: &str = "event_handlers";
+/// Registry key of the script currently being loaded, recorded with each handler
+const LOADING_SCRIPT_KEY: &str = "loading_event_script";
+
+const EVENT_NAMES: [&str; 7] = [
+    "on_player_join",
+    "on_player_leave",
+    "on_block_place",
+    "on_block_placed",
+    "on_block_break",
+    "on_chat",
+    "on_tick",
+];
+
+/// Something that happened in the game that scripts may react to
+pub enum GameEvent<'a> {
+    PlayerJoin {
+        player: &'a str,
+    },
+    PlayerLeave {
+        player: &'a str,
+    },
+    BlockPlace {
+        player: &'a str,
+        position: Vec3<i32>,
+        id: i32,
+        state: u16,
+    },
+    /// A `BlockPlace` that was not cancelled, after the block was written into the world
+    BlockPlaced {
+        player: &'a str,
+        position: Vec3<i32>,
+        id: i32,
+        state: u16,
+    },
+    /// `id` is the block being destroyed
+    BlockBreak {
+        player: &'a str,
+        position: Vec3<i32>,
+        id: i32,
+    },
+    Chat {
+        player: &'a str,
+        message: &'a str,
+    },
+    Tick {
+        tick: u64,
+    },
+}
+
+impl GameEvent<'_> {
+    /// Name handlers are registered under
+    fn handler_name(&self) -> &'static str {
+        match self {
+            GameEvent::PlayerJoin { .. } => "on_player_join",
+            GameEvent::PlayerLeave { .. } => "on_player_leave",
+            GameEvent::BlockPlace { .. } => "on_block_place",
+            GameEvent::BlockPlaced { .. } => "on_block_placed",
+            GameEvent::BlockBreak { .. } => "on_block_break",
+            GameEvent::Chat { .. } => "on_chat",
+            GameEvent::Tick { .. } => "on_tick",
+        }
+    }
+
+    /// Returns whether a handler may stop the event from happening
+    fn is_cancellable(&self) -> bool {
+        matches!(
+            self,
+            GameEvent::BlockPlace { .. } | GameEvent::BlockBreak { .. } | GameEvent::Chat { .. }
+        )
+    }
+
+    /// Creates the table passed to the handlers, `items` names the states of placed blocks
+    fn to_table<'lua>(
+        &self,
+        lua_ctx: Context<'lua>,
+        items: &ItemManager,
+    ) -> rlua::Result<Table<'lua>> {
+        let table = lua_ctx.create_table()?;
+        match self {
+            GameEvent::PlayerJoin { player } | GameEvent::PlayerLeave { player } => {
+                table.set("player", *player)?;
+            }
+            GameEvent::BlockPlace {
+                player,
+                position,
+                id,
+                ..
+            }
+            | GameEvent::BlockPlaced {
+                player,
+                position,
+                id,
+                ..
+            }
+            | GameEvent::BlockBreak {
+                player,
+                position,
+                id,
+            } => {
+                table.set("player", *player)?;
+                table.set("x", position.x)?;
+                table.set("y", position.y)?;
+                table.set("z", position.z)?;
+                table.set("id", *id)?;
+                if let GameEvent::BlockPlace { state, .. } | GameEvent::BlockPlaced { state, .. } =
+                    self
+                {
+                    table.set("state", state_table(lua_ctx, items, *id, *state)?)?;
+                }
+            }
+            GameEvent::Chat { player, message } => {
+                table.set("player", *player)?;
+                table.set("message", *message)?;
+            }
+            GameEvent::Tick { tick } => {
+                table.set("tick", *tick)?;
+            }
+        }
+        Ok(table)
+    }
+}
+
+/// Creates a table of the state property values of `state` of block `id`, empty for unknown ids
+fn state_table<'lua>(
+    lua_ctx: Context<'lua>,
+    items: &ItemManager,
+    id: i32,
+    state: u16,
+) -> rlua::Result<Table<'lua>> {
+    let table = lua_ctx.create_table()?;
+    if let Some(item) = items.get_item_by_id(id) {
+        for (name, value) in item.decode_state(state) {
+            table.set(name, value)?;
+        }
+    }
+    Ok(table)
+}
+
+/// A request from an event handler that the server has to carry out over the network
+#[derive(Debug, PartialEq)]
+pub enum ScriptAction {
+    /// The block at the position was changed and clients need to be told
+    UpdateBlock(Vec3<i32>),
+    /// The block entity at the position was set or removed
+    UpdateBlockEntity(Vec3<i32>),
+    /// Sends `message` to `player`, or to everyone if `player` is None
+    SendMessage {
+        player: Option<String>,
+        message: String,
+    },
+    Teleport {
+        player: String,
+        position: Vec3<f32>,
+    },
+}
+
+/// Result of running the handlers of an event
+#[derive(Default)]
+pub struct EventOutcome {
+    pub cancelled: bool,
+    pub actions: Vec<ScriptAction>,
+}
+
+/// Lua state holding the handlers registered by the event scripts
+pub struct EventScripts {
+    lua: Lua,
+    limits: ScriptLimits,
+}
+
+impl EventScripts {
+    /// Runs the event scripts at `paths` in order, letting them register their handlers
+    pub fn load(paths: &[String], limits: &ScriptLimits) -> Result<EventScripts, ScriptError> {
+        let lua = new_sandboxed_lua(limits);
+
+        lua.context(|lua_ctx| -> rlua::Result<()> {
+            let handlers = lua_ctx.create_table()?;
+            for name in EVENT_NAMES {
+                handlers.set(name, lua_ctx.create_table()?)?;
+            }
+            lua_ctx.set_named_registry_value(HANDLERS_KEY, handlers)?;
+
+            let register_event_handler =
+                lua_ctx.create_function(|lua_ctx, (event, handler): (String, Function)| {
+                    let handlers: Table = lua_ctx.named_registry_value(HANDLERS_KEY)?;
+                    let list: Option<Table> = handlers.get(event.as_str())?;
+                    let list = list.ok_or_else(|| {
+                        rlua::Error::RuntimeError(format!("unknown event {}", event))
+                    })?;
+
+                    let entry = lua_ctx.create_table()?;
+                    let script: String = lua_ctx.named_registry_value(LOADING_SCRIPT_KEY)?;
+                    entry.set("script", script)?;
+                    entry.set("handler", handler)?;
+                    list.set(list.len()? + 1, entry)
+                })?;
+            lua_ctx
+                .globals()
+                .set("register_event_handler", register_event_handler)
+        })
+        .map_err(|e| ScriptError::new("event scripts", e.to_string()))?;
+
+        for path in paths {
+            let script = fs::read_to_string(path)
+                .map_err(|e| ScriptError::new(path, format!("unable to read script: {}", e)))?;
+
+            run_limited(&lua, limits, |lua_ctx| {
+                lua_ctx.set_named_registry_value(LOADING_SCRIPT_KEY, path.as_str())?;
+                lua_ctx.load(&script).set_name(&chunk_name(path))?.exec()
+            })
+            .map_err(|e| ScriptError::from_lua(path, &e))?;
+        }
+
+        Ok(EventScripts {
+            lua,
+            limits: *limits,
+        })
+    }
+
+    /// Returns whether any handler is registered for the event named `name`
+    fn has_handlers(&self, name: &str) -> bool {
+        self.lua.context(|lua_ctx| {
+            let handlers: rlua::Result<Table> = lua_ctx.named_registry_value(HANDLERS_KEY);
+            handlers
+                .and_then(|handlers| handlers.get::<_, Table>(name))
+                .and_then(|list| list.len())
+                .is_ok_and(|count| count > 0)
+        })
+    }
+
+    /// Runs every handler registered for `event` in order
+    ///
+    /// A handler returning `false` cancels a cancellable event and skips the remaining handlers.
+    /// Handlers that fail are reported and do not cancel the event.
+    pub fn dispatch(&self, world: &mut World, event: &GameEvent) -> EventOutcome {
+        let name = event.handler_name();
+        if !self.has_handlers(name) {
+            return EventOutcome::default();
+        }
+
+        let world = RefCell::new(world);
+        let actions = RefCell::new(Vec::new());
+        let mut cancelled = false;
+        let mut errors = Vec::new();
+
+        let result = run_limited(&self.lua, &self.limits, |lua_ctx| {
+            lua_ctx.scope(|scope| {
+                let api = lua_ctx.create_table()?;
+                api.set(
+                    "get_block",
+                    scope.create_function(|_, (x, y, z): (i32, i32, i32)| {
+                        Ok(world.borrow_mut().get_block(&Vec3::new(x, y, z)))
+                    })?,
+                )?;
+                api.set(
+                    "get_state",
+                    scope.create_function(|lua_ctx, (x, y, z): (i32, i32, i32)| {
+                        let mut world = world.borrow_mut();
+                        let (id, state) = world
+                            .get_block_with_state(&Vec3::new(x, y, z))
+                            .unwrap_or((-1, 0));
+                        state_table(lua_ctx, world.get_item_manager(), id, state)
+                    })?,
+                )?;
+                api.set(
+                    "set_block",
+                    scope.create_function(
+                        |_,
+                         (x, y, z, id, values): (
+                            i32,
+                            i32,
+                            i32,
+                            i32,
+                            Option<HashMap<String, String>>,
+                        )| {
+                            let position = Vec3::new(x, y, z);
+                            let mut world = world.borrow_mut();
+                            let state = match (values, world.get_item_manager().get_item_by_id(id))
+                            {
+                                (Some(values), Some(item)) => item
+                                    .encode_state(
+                                        values.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+                                    )
+                                    .map_err(rlua::Error::RuntimeError)?,
+                                _ => 0,
+                            };
+                            world
+                                .set_block_with_state(&position, id, state)
+                                .map_err(rlua::Error::external)?;
+                            actions
+                                .borrow_mut()
+                                .push(ScriptAction::UpdateBlock(position));
+                            Ok(())
+                        },
+                    )?,
+                )?;
+                api.set(
+                    "get_block_entity",
+                    scope.create_function(|lua_ctx, (x, y, z): (i32, i32, i32)| {
+                        let mut world = world.borrow_mut();
+                        match world.get_block_entity(&Vec3::new(x, y, z)) {
+                            Some(entity) => entity.to_lua(lua_ctx),
+                            None => Ok(Value::Nil),
+                        }
+                    })?,
+                )?;
+                api.set(
+                    "set_block_entity",
+                    scope.create_function(|_, (x, y, z, entity): (i32, i32, i32, Value)| {
+                        let position = Vec3::new(x, y, z);
+                        let entity = match entity {
+                            Value::Nil => None,
+                            entity => Some(EntityValue::from_lua(entity)?),
+                        };
+                        world
+                            .borrow_mut()
+                            .set_block_entity(&position, entity)
+                            .map_err(rlua::Error::external)?;
+                        actions
+                            .borrow_mut()
+                            .push(ScriptAction::UpdateBlockEntity(position));
+                        Ok(())
+                    })?,
+                )?;
+                api.set(
+                    "get_id_by_name",
+                    scope.create_function(|_, name: String| {
+                        Ok(world.borrow().get_item_manager().get_id_by_name(name))
+                    })?,
+                )?;
+                api.set(
+                    "has_tag",
+                    scope.create_function(|_, (id, tag): (i32, String)| {
+                        let world = world.borrow();
+                        let item = world.get_item_manager().get_item_by_id(id);
+                        Ok(item.is_some_and(|item| item.has_tag(&tag)))
+                    })?,
+                )?;
+                api.set(
+                    "get_biome",
+                    scope.create_function(|_, (x, z): (i32, i32)| {
+                        Ok(world.borrow().get_biome(x, z).name.clone())
+                    })?,
+                )?;
+                api.set(
+                    "send_message",
+                    scope.create_function(|_, (player, message): (Option<String>, String)| {
+                        actions
+                            .borrow_mut()
+                            .push(ScriptAction::SendMessage { player, message });
+                        Ok(())
+                    })?,
+                )?;
+                api.set(
+                    "teleport",
+                    scope.create_function(|_, (player, x, y, z): (String, f32, f32, f32)| {
+                        actions.borrow_mut().push(ScriptAction::Teleport {
+                            player,
+                            position: Vec3::new(x, y, z),
+                        });
+                        Ok(())
+                    })?,
+                )?;
+                lua_ctx.globals().set("world", api)?;
+
+                let event_table = event.to_table(lua_ctx, world.borrow().get_item_manager())?;
+                let handlers: Table = lua_ctx.named_registry_value(HANDLERS_KEY)?;
+                let list: Table = handlers.get(name)?;
+                for entry in list.sequence_values::<Table>() {
+                    let entry = entry?;
+                    let script: String = entry.get("script")?;
+                    let handler: Function = entry.get("handler")?;
+
+                    match handler.call::<_, Value>(event_table.clone()) {
+                        Ok(Value::Boolean(false)) if event.is_cancellable() => {
+                            cancelled = true;
+                            break;
+                        }
+                        Ok(_) => (),
+                        Err(e) => errors.push(ScriptError::from_lua(&script, &e)),
+                    }
+                }
+                Ok(())
+            })
+        });
+
+        if let Err(e) = result {
+            errors.push(ScriptError::new("event scripts", e.to_string()));
+        }
+        for error in errors {
+            eprintln!("Event handler for {} failed: {}", name, error);
+        }
+
+        EventOutcome {
+            cancelled,
+            actions: actions.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mods::ScriptSources;
+    use crate::save_file::SaveFile;
+    use crate::scripting::with_script_file;
+
+    fn test_world() -> World {
+        let save = SaveFile::new(None);
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        World::new(save, &sources, ScriptLimits::default()).unwrap()
+    }
+
+    fn load_source(source: &str) -> Result<EventScripts, ScriptError> {
+        with_script_file(source, |paths| {
+            EventScripts::load(paths, &ScriptLimits::default())
+        })
+    }
+
+    #[test]
+    fn test_default_event_scripts_load() {
+        let paths = SaveFile::new(None).get_event_script_paths().unwrap();
+        assert!(paths.iter().any(|path| path.ends_with("events.lua")));
+
+        let scripts = EventScripts::load(&paths, &ScriptLimits::default()).unwrap();
+        let mut world = test_world();
+        let outcome = scripts.dispatch(&mut world, &GameEvent::PlayerJoin { player: "alice" });
+        assert_eq!(
+            outcome.actions,
+            vec![ScriptAction::SendMessage {
+                player: None,
+                message: "alice joined the game".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_cancel_and_world_api() {
+        let scripts = load_source(
+            r#"
+            register_event_handler("on_block_break", function(event)
+                if event.y < 5 then
+                    world.send_message(event.player, "Too deep")
+                    return false
+                end
+            end)
+
+            register_event_handler("on_block_break", function(event)
+                world.set_block(event.x, event.y + 1, event.z, world.get_id_by_name("Stone"))
+                world.teleport(event.player, 0, world.get_block(event.x, event.y + 1, event.z), 0)
+            end)
+
+            register_event_handler("on_player_join", function(event)
+                return false
+            end)
+            "#,
+        )
+        .unwrap();
+        let mut world = test_world();
+
+        let outcome = scripts.dispatch(
+            &mut world,
+            &GameEvent::BlockBreak {
+                player: "bob",
+                position: Vec3::new(0, 2, 0),
+                id: 1,
+            },
+        );
+        assert!(outcome.cancelled);
+        assert_eq!(outcome.actions.len(), 1);
+
+        let outcome = scripts.dispatch(
+            &mut world,
+            &GameEvent::BlockBreak {
+                player: "bob",
+                position: Vec3::new(0, 100, 0),
+                id: 1,
+            },
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        assert!(!outcome.cancelled);
+        assert_eq!(world.get_block(&Vec3::new(0, 101, 0)), stone.unwrap());
+        assert_eq!(
+            outcome.actions,
+            vec![
+                ScriptAction::UpdateBlock(Vec3::new(0, 101, 0)),
+                ScriptAction::Teleport {
+                    player: "bob".to_string(),
+                    position: Vec3::new(0.0, stone.unwrap() as f32, 0.0)
+                }
+            ]
+        );
+
+        // Joining can't be cancelled
+        let outcome = scripts.dispatch(&mut world, &GameEvent::PlayerJoin { player: "bob" });
+        assert!(!outcome.cancelled);
+    }
+
+    #[test]
+    fn test_states_and_block_entities() {
+        let scripts = load_source(
+            r#"
+            register_event_handler("on_block_place", function(event)
+                local text = "facing " .. event.state.axis
+                world.set_block_entity(event.x, event.y, event.z, { text = text, lines = { 1, 2 } })
+            end)
+
+            register_event_handler("on_block_break", function(event)
+                local entity = world.get_block_entity(event.x, event.y, event.z)
+                world.send_message(nil, entity.text .. " " .. world.get_state(event.x, event.y, event.z).axis)
+                world.set_block(event.x, event.y + 1, event.z, event.id, { axis = "z" })
+            end)
+            "#,
+        )
+        .unwrap();
+        let mut world = test_world();
+        let log = world.get_item_manager().get_id_by_name("Log".to_string());
+        let log = log.unwrap();
+        let position = Vec3::new(0, 100, 0);
+        world.set_block_with_state(&position, log, 1).unwrap();
+
+        let outcome = scripts.dispatch(
+            &mut world,
+            &GameEvent::BlockPlace {
+                player: "bob",
+                position,
+                id: log,
+                state: 1,
+            },
+        );
+        assert_eq!(
+            outcome.actions,
+            vec![ScriptAction::UpdateBlockEntity(position)]
+        );
+
+        let outcome = scripts.dispatch(
+            &mut world,
+            &GameEvent::BlockBreak {
+                player: "bob",
+                position,
+                id: log,
+            },
+        );
+        assert_eq!(
+            outcome.actions[0],
+            ScriptAction::SendMessage {
+                player: None,
+                message: "facing x x".to_string()
+            }
+        );
+        assert_eq!(
+            world.get_block_with_state(&Vec3::new(0, 101, 0)),
+            Some((log, 2))
+        );
+    }
+
+    #[test]
+    fn test_failing_handler_does_not_cancel() {
+        let scripts = load_source(
+            r#"
+            register_event_handler("on_chat", function(event)
+                error("broken handler")
+            end)
+
+            register_event_handler("on_chat", function(event)
+                world.send_message(nil, event.message)
+            end)
+            "#,
+        )
+        .unwrap();
+        let mut world = test_world();
+
+        let outcome = scripts.dispatch(
+            &mut world,
+            &GameEvent::Chat {
+                player: "carol",
+                message: "hi",
+            },
+        );
+        assert!(!outcome.cancelled);
+        assert_eq!(outcome.actions.len(), 1);
+
+        // Events without handlers do nothing
+        let outcome = scripts.dispatch(&mut world, &GameEvent::Tick { tick: 1 });
+        assert!(outcome.actions.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_event_is_a_load_error() {
+        let error = load_source("register_event_handler(\"on_explode\", function() end)")
+            .err()
+            .unwrap();
+        assert_eq!(error.line, Some(1));
+        assert_eq!(error.message, "runtime error: unknown event on_explode");
+    }
+}
@@ -1,13 +1,74 @@
 use crate::vector_types::Vec2;
 
-use rlua::Lua;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+
+use rlua::{FromLua, Table, Value};
+
+use std::cell::{Cell, RefCell};
 use std::fs;
 
+/// Shape of an item, sent to clients as its index so new shapes are added at the end
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ItemType {
     Air,
     BlockCube,
     BlockCross,
     UserItem,
+    /// Lower half of a block
+    BlockSlab,
+    BlockStairs,
+    /// A post joining the fences and solid blocks next to it
+    BlockFence,
+    /// A thin wall joining the panes and solid blocks next to it, such as a glass pane
+    BlockPane,
+    /// A model from the assets, named by `ItemData::model`
+    BlockModel,
+}
+
+impl ItemType {
+    const NAMES: [(&'static str, ItemType); 9] = [
+        ("Air", ItemType::Air),
+        ("BlockCube", ItemType::BlockCube),
+        ("BlockCross", ItemType::BlockCross),
+        ("UserItem", ItemType::UserItem),
+        ("BlockSlab", ItemType::BlockSlab),
+        ("BlockStairs", ItemType::BlockStairs),
+        ("BlockFence", ItemType::BlockFence),
+        ("BlockPane", ItemType::BlockPane),
+        ("BlockModel", ItemType::BlockModel),
+    ];
+
+    /// Gets the type named `name` in item scripts
+    pub fn from_name(name: &str) -> Option<ItemType> {
+        ItemType::NAMES
+            .iter()
+            .find(|(type_name, _)| *type_name == name)
+            .map(|(_, item_type)| *item_type)
+    }
+}
+
+/// Faces of a block, in the order of `ItemData::textures`
+///
+/// North is towards -z and east towards +x
+#[derive(Clone, Copy)]
+pub enum Face {
+    Top,
+    Bottom,
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::Top,
+        Face::Bottom,
+        Face::North,
+        Face::South,
+        Face::East,
+        Face::West,
+    ];
 }
 
 pub struct ItemData {
@@ -15,19 +76,150 @@ pub struct ItemData {
     pub is_transparent: bool,
     pub show_in_inventory: bool,
     pub name: String,
-    pub top_tex_coords: Vec2<u16>,
-    pub side_tex_coords: Vec2<u16>,
-    pub bottom_tex_coords: Vec2<u16>,
+    /// Texture coords of each face, indexed by `Face`
+    pub textures: [Vec2<u16>; 6],
+    /// Asset path of the model of a `BlockModel`
+    pub model: Option<String>,
+    /// Index of the atlas holding the textures of the item, None if its script set no atlas
+    pub atlas: Option<usize>,
+    /// Seconds it takes to break the block by hand, negative if it cannot be broken
+    pub hardness: f32,
+    /// Light emitted by the block, from 0 to `MAX_LIGHT`
+    pub light: u8,
+    /// Most items of this kind in one inventory slot
+    pub max_stack: u16,
+    /// Whether players collide with the block
+    pub is_solid: bool,
+    /// Items given when the block is broken
+    pub drops: Vec<ItemDrop>,
+    /// Free-form labels for gameplay scripts, such as "wood" or "soil"
+    pub tags: Vec<String>,
+    /// Properties kept in the state of every block of the item, such as the direction it faces
+    pub states: Vec<StateProperty>,
+}
+
+/// Most states a block can have, states are stored in 16 bits
+const MAX_STATES: u32 = 1 << 16;
+
+/// A property of a block kept in its state
+///
+/// A state holds the index of the value of every property of the item, the first property
+/// changing fastest, so state 0 has the first value of every property
+pub struct StateProperty {
+    pub name: String,
+    /// Values the property can have, the first is the default
+    pub values: Vec<String>,
+}
+
+/// Brightest light a block may emit
+pub const MAX_LIGHT: u8 = 15;
+
+/// Item replacing saved blocks whose item no longer exists
+pub const UNKNOWN_ITEM: &str = "Unknown";
+
+/// An item given when a block is broken
+pub struct ItemDrop {
+    /// Full name of the dropped item
+    pub name: String,
+    pub count: u16,
+}
+
+/// A texture atlas set with `setAtlas`, the tex coords of the items pushed after it are in it
+pub struct TextureAtlas {
+    pub path: String,
+    /// Size of the atlas in textures
+    pub width: u16,
+    pub height: u16,
+}
+
+impl ItemData {
+    /// Returns whether the item can be placed in the world as a block
+    pub fn is_placeable(&self) -> bool {
+        !matches!(self.item_type, ItemType::Air | ItemType::UserItem)
+    }
+
+    pub fn get_texture(&self, face: Face) -> &Vec2<u16> {
+        &self.textures[face as usize]
+    }
+
+    /// Returns whether players can break the block
+    pub fn is_breakable(&self) -> bool {
+        self.hardness >= 0.0
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|item_tag| item_tag == tag)
+    }
+
+    /// Number of states a block of the item can be in, 1 if it has no state properties
+    pub fn state_count(&self) -> u32 {
+        self.states
+            .iter()
+            .map(|property| property.values.len() as u32)
+            .product()
+    }
+
+    /// Gets the state with the given (property, value) pairs, other properties get their default
+    pub fn encode_state<'a>(
+        &self,
+        values: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<u16, String> {
+        let mut state = 0;
+        for (name, value) in values {
+            let mut stride = 1;
+            let mut found = false;
+            for property in &self.states {
+                if property.name == name {
+                    let index = property.values.iter().position(|v| v == value);
+                    let index = index.ok_or_else(|| {
+                        format!("{} is not a value of {} of item {}", value, name, self.name)
+                    })?;
+                    state += index as u32 * stride;
+                    found = true;
+                    break;
+                }
+                stride *= property.values.len() as u32;
+            }
+            if !found {
+                return Err(format!("item {} has no state property {}", self.name, name));
+            }
+        }
+        Ok(state as u16)
+    }
+
+    /// Gets the (property, value) pairs of `state`, in the order the properties are declared
+    pub fn decode_state(&self, state: u16) -> Vec<(&str, &str)> {
+        let mut state = state as usize;
+        self.states
+            .iter()
+            .map(|property| {
+                let value = &property.values[state % property.values.len()];
+                state /= property.values.len();
+                (property.name.as_str(), value.as_str())
+            })
+            .collect()
+    }
+}
+
+/// An item definition script and the namespace its items are registered under
+pub struct ItemScript {
+    pub path: String,
+    /// Prefix of the item names (`namespace:name`), None for the base game items
+    pub namespace: Option<String>,
 }
 
 pub struct ItemManager {
     items: Vec<ItemData>,
+    atlases: Vec<TextureAtlas>,
 }
 
 impl ItemManager {
     /// Creates an empty ItemManager
     pub fn new() -> ItemManager {
-        ItemManager { items: Vec::new() }
+        ItemManager {
+            items: Vec::new(),
+            atlases: Vec::new(),
+        }
     }
 
     /// Inserts a new item into the ItemManager
@@ -37,11 +229,7 @@ impl ItemManager {
 
     /// Gets item info from id
     pub fn get_item_by_id(&self, id: i32) -> Option<&ItemData> {
-        if id < self.items.len() as i32 {
-            return Option::Some(self.items.get(id as usize).unwrap());
-        }
-
-        Option::None
+        usize::try_from(id).ok().and_then(|i| self.items.get(i))
     }
 
     /// Gets item id from name
@@ -54,68 +242,203 @@ impl ItemManager {
         None
     }
 
+    /// Gets every item, indexed by id
+    pub fn get_items(&self) -> &[ItemData] {
+        &self.items
+    }
+
+    pub fn get_atlases(&self) -> &[TextureAtlas] {
+        &self.atlases
+    }
+
+    /// Gets the names of every item, indexed by id
+    pub fn get_item_names(&self) -> Vec<String> {
+        self.items.iter().map(|item| item.name.clone()).collect()
+    }
+
+    /// Checks that `reloaded` still gives every existing item the same id
+    ///
+    /// New items may be appended, but ids already stored in the world must keep their meaning
+    pub fn validate_reload(&self, reloaded: &ItemManager) -> Result<(), String> {
+        for (id, item) in self.items.iter().enumerate() {
+            match reloaded.items.get(id) {
+                Some(new_item) if new_item.name == item.name => (),
+                Some(new_item) => {
+                    return Err(format!(
+                        "item id {} changed from {} to {}",
+                        id, item.name, new_item.name
+                    ))
+                }
+                None => return Err(format!("item {} (id {}) was removed", item.name, id)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the Unknown item if no script defined it
+    ///
+    /// It gets the id it had in `previous` when reloading, so blocks already replaced by it keep
+    /// their meaning, and is added after the other items otherwise
+    pub fn add_unknown_item(&mut self, previous: Option<&ItemManager>) {
+        if self.get_id_by_name(UNKNOWN_ITEM.to_string()).is_some() {
+            return;
+        }
+
+        let item = ItemData {
+            item_type: ItemType::BlockCube,
+            is_transparent: false,
+            show_in_inventory: false,
+            name: UNKNOWN_ITEM.to_string(),
+            textures: [Vec2::new(0, 0); 6],
+            model: None,
+            atlas: None,
+            hardness: 1.0,
+            light: 0,
+            max_stack: 64,
+            is_solid: true,
+            drops: Vec::new(),
+            tags: Vec::new(),
+            states: Vec::new(),
+        };
+        let previous_id = previous
+            .and_then(|previous| previous.get_id_by_name(UNKNOWN_ITEM.to_string()))
+            .map(|id| id as usize)
+            .filter(|id| *id <= self.items.len());
+        match previous_id {
+            Some(id) => self.items.insert(id, item),
+            None => self.items.push(item),
+        }
+    }
+
+    /// Creates an ItemManager with the items of every script in `scripts`, in order
+    pub fn from_scripts(
+        scripts: &[ItemScript],
+        limits: &ScriptLimits,
+    ) -> Result<ItemManager, ScriptError> {
+        let mut item_manager = ItemManager::new();
+        for script in scripts {
+            item_manager.load_items(script.path.clone(), script.namespace.as_deref(), limits)?;
+        }
+        Ok(item_manager)
+    }
+
     /// Runs the lua script at the `path` and inserts the new items into the `item_manager`
-    pub fn load_items(&mut self, path: String) {
-        let asset_script = fs::read_to_string(path).expect("Unable to load loadAssetInfo script");
+    ///
+    /// Item names are prefixed with `namespace:` if given, redefining an existing item is an error
+    pub fn load_items(
+        &mut self,
+        path: String,
+        namespace: Option<&str>,
+        limits: &ScriptLimits,
+    ) -> Result<(), ScriptError> {
+        let asset_script = fs::read_to_string(&path)
+            .map_err(|e| ScriptError::new(&path, format!("unable to read script: {}", e)))?;
+
+        let lua = new_sandboxed_lua(limits);
 
-        let lua = Lua::new();
+        // Atlases set by this script, each applies to the items pushed after it
+        let first_atlas = self.atlases.len();
+        let atlases = RefCell::new(Vec::<TextureAtlas>::new());
+        let current_atlas = Cell::new(None);
 
-        lua.context(|lua_ctx| {
+        let result = run_limited(&lua, limits, |lua_ctx| {
             let globals = lua_ctx.globals(); // Get globals from lua
 
             lua_ctx.scope(|scope| {
 
-                let add_asset = // Create a function that takes in all info and compiles it into a ItemData struct
-                    scope.create_function_mut(|_, (item_name, item_type_str, is_transparent, show_in_inventory, coords): (String, String, bool, bool, Vec<u16>)| {
-
-                        let item_type: ItemType;
-                        match item_type_str.as_str() {
-                            "Air" => item_type = ItemType::Air,
-                            "BlockCube" => item_type = ItemType::BlockCube,
-                            "BlockCross" => item_type = ItemType::BlockCross,
-                            "UserItem" => item_type = ItemType::UserItem,
-                            _ => item_type = ItemType::UserItem,
+                let define_item = // Validates an item table and compiles it into a ItemData struct
+                    scope.create_function_mut(|_, info: Table| {
+                        let item_name: String = info.get::<_, Option<String>>("name")
+                            .ok()
+                            .flatten()
+                            .ok_or_else(|| rlua::Error::RuntimeError("define_item needs a name".to_string()))?;
+                        if item_name.is_empty() || item_name.contains(':') {
+                            return Err(rlua::Error::RuntimeError(format!("item name \"{}\" must not be empty or contain ':'", item_name)));
                         }
-
-                        let new_item = ItemData {
-                            item_type,
-                            is_transparent,
-                            show_in_inventory,
-                            name: item_name,
-                            top_tex_coords: Vec2::new(coords[0] as u16, coords[1] as u16),
-                            side_tex_coords: Vec2::new(coords[2] as u16, coords[3] as u16),
-                            bottom_tex_coords: Vec2::new(coords[4] as u16, coords[5] as u16),
+                        let item_name = match namespace {
+                            Some(namespace) => format!("{}:{}", namespace, item_name),
+                            None => item_name,
                         };
+                        if self.get_id_by_name(item_name.clone()).is_some() {
+                            return Err(rlua::Error::RuntimeError(format!("item {} is already defined", item_name)));
+                        }
 
+                        let atlas = current_atlas.get();
+                        let atlas_size = atlas.map(|atlas| {
+                            let atlas: &TextureAtlas = &atlases.borrow()[atlas - first_atlas];
+                            Vec2::new(atlas.width, atlas.height)
+                        });
+                        let new_item = item_from_table(&info, item_name, atlas, atlas_size)?;
                         self.put_new_item(new_item);
 
                         Ok(())
-                    }).unwrap();
-                globals.set("add_asset", add_asset).unwrap();
+                    })?;
+                globals.set("define_item", define_item)?;
 
                 let set_atlas = // Sets which atlas the texture is in
-                    lua_ctx.create_function(|_, (atlas_path, width, height): (String, u16, u16)| {
+                    scope.create_function(|_, (atlas_path, width, height): (String, u16, u16)| {
                         println!("Set Atlas: {}, {}, {}", atlas_path, width, height);
 
+                        let mut atlases = atlases.borrow_mut();
+                        current_atlas.set(Some(first_atlas + atlases.len()));
+                        atlases.push(TextureAtlas {
+                            path: atlas_path,
+                            width,
+                            height,
+                        });
                         Ok(())
-                    }).unwrap();
-                globals.set("set_atlas", set_atlas).unwrap();
+                    })?;
+                globals.set("set_atlas", set_atlas)?;
 
                 lua_ctx.load(
                     r#"
+                        function add_asset(name, itemType, isTransparent, showInInventory, coords, properties)
+                            if type(coords) ~= "table" or #coords ~= 6 then
+                                error("add_asset needs 6 texture coordinates: top, side and bottom x and y", 0)
+                            end
+                            local info = {}
+                            for key, value in pairs(properties or {}) do
+                                info[key] = value
+                            end
+                            info.name = name
+                            info.type = itemType
+                            info.transparent = isTransparent
+                            info.show_in_inventory = showInInventory
+                            -- Textures given in the item table win over the setCoords ones
+                            if info.textures == nil then
+                                info.textures = {
+                                    top = { coords[1], coords[2] },
+                                    side = { coords[3], coords[4] },
+                                    bottom = { coords[5], coords[6] },
+                                }
+                            end
+                            define_item(info)
+                        end
+
                         item_name = "UNKNOWN"
                         item_type = "UserItem"
                         is_transparent = false
                         show_in_inventory = true
+                        item_properties = nil
                         top_coord_x, top_coord_y = 0, 0
                         side_coord_x, side_coord_y = 0, 0
                         bottom_coord_x, bottom_coord_y = 0, 0
 
                         function setInfo(name, itemType, isTransparent, showInInventory)
+                            if type(name) == "table" then
+                                local info = name
+                                item_name = info.name or "UNKNOWN"
+                                item_type = info.type or "UserItem"
+                                is_transparent = info.transparent or false
+                                show_in_inventory = info.show_in_inventory ~= false
+                                item_properties = info
+                                return
+                            end
                             item_name = name or "UNKNOWN"
                             item_type = itemType or "UserItem"
                             is_transparent = isTransparent or false
-                            show_in_inventory = showInInventory or true
+                            show_in_inventory = showInInventory ~= false
+                            item_properties = nil
                         end
 
                         function setCoords(topX, topY, sideX, sideY, bottomX, bottomY)
@@ -128,7 +451,7 @@ impl ItemManager {
                         end
 
                         function pushItem()
-                            add_asset(item_name, item_type, is_transparent, show_in_inventory, {top_coord_x, top_coord_y, side_coord_x, side_coord_y, bottom_coord_x, bottom_coord_y}) -- Change to pull from global variables
+                            add_asset(item_name, item_type, is_transparent, show_in_inventory, {top_coord_x, top_coord_y, side_coord_x, side_coord_y, bottom_coord_x, bottom_coord_y}, item_properties)
                         end
 
                         function setAtlas(path, width, height)
@@ -138,17 +461,685 @@ impl ItemManager {
                         end
                     "#
                 )
-                .set_name("Load Asset Functions").unwrap()
-                .exec()
-                .expect("Load asset utility functions failed to load");
+                .set_name("Load Asset Functions")?
+                .exec()?;
 
                 lua_ctx
                 .load(&asset_script)
-                .set_name("Load Asset Info").unwrap()
+                .set_name(&chunk_name(&path))?
                 .exec()
-                .expect("Lua asset script failed!");
+            })
+        });
+
+        self.atlases.append(&mut atlases.into_inner());
+        result.map_err(|e| ScriptError::from_lua(&path, &e))?;
 
+        for item in &self.items {
+            for drop in &item.drops {
+                if self.get_id_by_name(drop.name.clone()).is_none() {
+                    return Err(ScriptError::new(
+                        &path,
+                        format!("item {} drops unknown item {}", item.name, drop.name),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keys of the table given to `define_item`, other keys are reported as mistakes in the script
+const ITEM_KEYS: [&str; 13] = [
+    "name",
+    "type",
+    "model",
+    "transparent",
+    "show_in_inventory",
+    "textures",
+    "hardness",
+    "light",
+    "stack_size",
+    "solid",
+    "drops",
+    "tags",
+    "states",
+];
+
+fn item_error(name: &str, message: String) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("item {}: {}", name, message))
+}
+
+/// Gets the optional field `key` of an item table, reporting a value that is not `expected`
+fn get_field<'lua, T: FromLua<'lua>>(
+    info: &Table<'lua>,
+    name: &str,
+    key: &str,
+    expected: &str,
+) -> rlua::Result<Option<T>> {
+    info.get::<_, Option<T>>(key)
+        .map_err(|_| item_error(name, format!("{} must be {}", key, expected)))
+}
+
+/// Gets the optional boolean field `key` of an item table, lua would convert any value to a boolean
+fn get_flag(info: &Table, name: &str, key: &str) -> rlua::Result<Option<bool>> {
+    match info.get::<_, Value>(key)? {
+        Value::Nil => Ok(None),
+        Value::Boolean(flag) => Ok(Some(flag)),
+        _ => Err(item_error(name, format!("{} must be a boolean", key))),
+    }
+}
+
+/// Keys of the textures table, in the order their defaults are resolved
+const TEXTURE_KEYS: [&str; 7] = ["top", "bottom", "side", "north", "south", "east", "west"];
+
+/// Gets the texture coords of every face from a `textures` table
+///
+/// Faces that are not given fall back to `side`, and `side` and `bottom` fall back to `top`
+fn face_textures(
+    table: &Table,
+    name: &str,
+    atlas_size: Option<Vec2<u16>>,
+) -> rlua::Result<[Vec2<u16>; 6]> {
+    for pair in table.clone().pairs::<Value, Value>() {
+        let is_face = match pair?.0 {
+            Value::String(key) => TEXTURE_KEYS.contains(&key.to_str()?),
+            _ => false,
+        };
+        if !is_face {
+            return Err(item_error(
+                name,
+                format!("textures only takes {}", TEXTURE_KEYS.join(", ")),
+            ));
+        }
+    }
+
+    let get = |face: &str| -> rlua::Result<Option<Vec2<u16>>> {
+        let key = format!("textures.{}", face);
+        let not_coords = || item_error(name, format!("{} must be a list of x and y", key));
+        let coords = table
+            .get::<_, Option<Vec<u16>>>(face)
+            .map_err(|_| not_coords())?;
+        let coords = match coords.as_deref() {
+            Some([x, y]) => Vec2::new(*x, *y),
+            Some(_) => return Err(not_coords()),
+            None => return Ok(None),
+        };
+        if let Some(size) = &atlas_size {
+            if coords.x >= size.x || coords.y >= size.y {
+                return Err(item_error(
+                    name,
+                    format!(
+                        "{} {}, {} is outside the {}x{} atlas",
+                        key, coords.x, coords.y, size.x, size.y
+                    ),
+                ));
+            }
+        }
+        Ok(Some(coords))
+    };
+
+    let top = get("top")?.unwrap_or(Vec2::new(0, 0));
+    let bottom = get("bottom")?.unwrap_or(top);
+    let side = get("side")?.unwrap_or(top);
+    Ok([
+        top,
+        bottom,
+        get("north")?.unwrap_or(side),
+        get("south")?.unwrap_or(side),
+        get("east")?.unwrap_or(side),
+        get("west")?.unwrap_or(side),
+    ])
+}
+
+/// Gets the state properties from a `states` list of { name, values } tables
+fn state_properties(table: &Table, name: &str) -> rlua::Result<Vec<StateProperty>> {
+    let mut properties: Vec<StateProperty> = Vec::new();
+    let mut state_count: u32 = 1;
+    for property in table.clone().sequence_values::<Value>() {
+        let not_property =
+            || item_error(name, "states must be { name, values } tables".to_string());
+        let property = match property? {
+            Value::Table(property) => property,
+            _ => return Err(not_property()),
+        };
+        let property_name = property
+            .get::<_, String>("name")
+            .map_err(|_| not_property())?;
+        let values = property
+            .get::<_, Vec<String>>("values")
+            .map_err(|_| not_property())?;
+
+        if properties.iter().any(|other| other.name == property_name) {
+            return Err(item_error(
+                name,
+                format!("state property {} is declared twice", property_name),
+            ));
+        }
+        let unique = values
+            .iter()
+            .enumerate()
+            .all(|(i, value)| !values[..i].contains(value));
+        if values.len() < 2 || !unique {
+            return Err(item_error(
+                name,
+                format!(
+                    "state property {} needs at least two different values",
+                    property_name
+                ),
+            ));
+        }
+        state_count = state_count.saturating_mul(values.len() as u32);
+        if state_count > MAX_STATES {
+            return Err(item_error(
+                name,
+                format!("state properties have more than {} states", MAX_STATES),
+            ));
+        }
+
+        properties.push(StateProperty {
+            name: property_name,
+            values,
+        });
+    }
+    Ok(properties)
+}
+
+/// Creates the item `name` from a `define_item` table
+///
+/// Keys that are not set get the defaults of the item type. `atlas_size` is the size of the atlas
+/// the textures are in, in textures, None if the script set no atlas.
+fn item_from_table(
+    info: &Table,
+    name: String,
+    atlas: Option<usize>,
+    atlas_size: Option<Vec2<u16>>,
+) -> rlua::Result<ItemData> {
+    for pair in info.clone().pairs::<Value, Value>() {
+        let key = match pair?.0 {
+            Value::String(key) => key.to_str()?.to_string(),
+            key => {
+                return Err(item_error(
+                    &name,
+                    format!("unexpected {} key", key.type_name()),
+                ))
+            }
+        };
+        if !ITEM_KEYS.contains(&key.as_str()) {
+            return Err(item_error(
+                &name,
+                format!(
+                    "unknown key {}, expected one of {}",
+                    key,
+                    ITEM_KEYS.join(", ")
+                ),
+            ));
+        }
+    }
+
+    let type_name = get_field::<String>(info, &name, "type", "a string")?;
+    let type_name = type_name.as_deref().unwrap_or("UserItem");
+    let item_type = ItemType::from_name(type_name).ok_or_else(|| {
+        let names: Vec<_> = ItemType::NAMES.iter().map(|(name, _)| *name).collect();
+        item_error(
+            &name,
+            format!(
+                "unknown type {}, expected one of {}",
+                type_name,
+                names.join(", ")
+            ),
+        )
+    })?;
+
+    let model = get_field::<String>(info, &name, "model", "a string")?;
+    if model.is_some() != (item_type == ItemType::BlockModel) {
+        return Err(item_error(
+            &name,
+            "model must be given for BlockModel items and only for them".to_string(),
+        ));
+    }
+
+    let textures = match get_field::<Table>(info, &name, "textures", "a table")? {
+        Some(textures) => face_textures(&textures, &name, atlas_size)?,
+        None => [Vec2::new(0, 0); 6],
+    };
+
+    // Air cannot be broken into anything, other items drop themselves
+    let is_air = matches!(item_type, ItemType::Air);
+    let mut item = ItemData {
+        item_type,
+        is_transparent: get_flag(info, &name, "transparent")?.unwrap_or(false),
+        show_in_inventory: get_flag(info, &name, "show_in_inventory")?.unwrap_or(true),
+        drops: if is_air {
+            Vec::new()
+        } else {
+            vec![ItemDrop {
+                name: name.clone(),
+                count: 1,
+            }]
+        },
+        textures,
+        model,
+        atlas,
+        hardness: get_field(info, &name, "hardness", "a number")?.unwrap_or(if is_air {
+            0.0
+        } else {
+            1.0
+        }),
+        light: 0,
+        max_stack: 64,
+        is_solid: get_flag(info, &name, "solid")?.unwrap_or(!matches!(
+            item_type,
+            ItemType::Air | ItemType::BlockCross | ItemType::UserItem
+        )),
+        tags: get_field(info, &name, "tags", "a list of strings")?.unwrap_or_default(),
+        states: match get_field::<Table>(info, &name, "states", "a table")? {
+            Some(states) => state_properties(&states, &name)?,
+            None => Vec::new(),
+        },
+        name,
+    };
+
+    let light_range = format!("a number from 0 to {}", MAX_LIGHT);
+    if let Some(light) = get_field::<u8>(info, &item.name, "light", &light_range)? {
+        if light > MAX_LIGHT {
+            return Err(item_error(
+                &item.name,
+                format!("light must be {}", light_range),
+            ));
+        }
+        item.light = light;
+    }
+    let stack_range = "a number from 1 to 65535";
+    if let Some(max_stack) = get_field::<u16>(info, &item.name, "stack_size", stack_range)? {
+        if max_stack == 0 {
+            return Err(item_error(
+                &item.name,
+                format!("stack_size must be {}", stack_range),
+            ));
+        }
+        item.max_stack = max_stack;
+    }
+    // Either names, or { name, count } tables for several of an item
+    if let Some(drops) = get_field::<Table>(info, &item.name, "drops", "a table")? {
+        item.drops.clear();
+        for drop in drops.sequence_values::<Value>() {
+            let drop = match drop? {
+                Value::String(drop_name) => Some(ItemDrop {
+                    name: drop_name.to_str()?.to_string(),
+                    count: 1,
+                }),
+                Value::Table(drop) => drop
+                    .get::<_, String>("name")
+                    .and_then(|drop_name| {
+                        let count = drop.get::<_, Option<u16>>("count")?;
+                        Ok(ItemDrop {
+                            name: drop_name,
+                            count: count.unwrap_or(1),
+                        })
+                    })
+                    .ok(),
+                _ => None,
+            };
+            let drop = drop.ok_or_else(|| {
+                item_error(
+                    &item.name,
+                    "drops must be names or { name, count } tables".to_string(),
+                )
+            })?;
+            item.drops.push(drop);
+        }
+    }
+    Ok(item)
+}
+
+/// Loads the items of the default item script, used by the tests of scripts that refer to items
+#[cfg(test)]
+pub fn default_items() -> ItemManager {
+    let script = ItemScript {
+        path: "./default_scripts/loadAssetInfo.lua".to_string(),
+        namespace: None,
+    };
+    ItemManager::from_scripts(&[script], &ScriptLimits::default()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scripting::with_script_file;
+
+    fn item_manager(names: &[&str]) -> ItemManager {
+        let mut item_manager = ItemManager::new();
+        for name in names {
+            item_manager.put_new_item(ItemData {
+                item_type: ItemType::BlockCube,
+                is_transparent: false,
+                show_in_inventory: true,
+                name: name.to_string(),
+                textures: [Vec2::new(0, 0); 6],
+                model: None,
+                atlas: None,
+                hardness: 1.0,
+                light: 0,
+                max_stack: 64,
+                is_solid: true,
+                drops: Vec::new(),
+                tags: Vec::new(),
+                states: Vec::new(),
             });
+        }
+        item_manager
+    }
+
+    #[test]
+    fn test_validate_reload() {
+        let current = item_manager(&["Air", "Dirt", "Stone"]);
+
+        assert!(current
+            .validate_reload(&item_manager(&["Air", "Dirt", "Stone", "Sand"]))
+            .is_ok());
+        assert!(current
+            .validate_reload(&item_manager(&["Air", "Stone", "Dirt"]))
+            .is_err());
+        assert!(current
+            .validate_reload(&item_manager(&["Air", "Dirt"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_item_is_always_added() {
+        let mut current = item_manager(&["Air", "Dirt"]);
+        current.add_unknown_item(None);
+        assert_eq!(current.get_id_by_name(UNKNOWN_ITEM.to_string()), Some(2));
+
+        // Reloads that add items keep the id of the Unknown item
+        let mut reloaded = item_manager(&["Air", "Dirt", "Sand"]);
+        reloaded.add_unknown_item(Some(&current));
+        assert_eq!(
+            reloaded.get_item_names(),
+            ["Air", "Dirt", "Unknown", "Sand"]
+        );
+        assert!(current.validate_reload(&reloaded).is_ok());
+
+        // An Unknown item defined by a script is kept
+        let mut defined = item_manager(&["Air", "Unknown", "Dirt"]);
+        defined.add_unknown_item(Some(&current));
+        assert_eq!(defined.get_item_names(), ["Air", "Unknown", "Dirt"]);
+    }
+
+    #[test]
+    fn test_items_keep_their_atlas() {
+        let item_manager = load_source(
+            r#"
+            setInfo("Dirt", "BlockCube") pushItem()
+            setAtlas("terrain.png", 16, 8)
+            setInfo("Grass", "BlockCube") pushItem()
+            setAtlas("plants.png")
+            setInfo("Rose", "BlockCross") pushItem()
+        "#,
+        )
+        .unwrap();
+        let atlases: Vec<_> = item_manager
+            .get_items()
+            .iter()
+            .map(|item| item.atlas)
+            .collect();
+        assert_eq!(atlases, vec![None, Some(0), Some(1)]);
+        let plants = &item_manager.get_atlases()[1];
+        assert_eq!(
+            (plants.path.as_str(), plants.width, plants.height),
+            ("plants.png", 10, 10)
+        );
+    }
+
+    fn load_source(source: &str) -> Result<ItemManager, ScriptError> {
+        with_script_file(source, |paths| {
+            let script = ItemScript {
+                path: paths[0].clone(),
+                namespace: None,
+            };
+            ItemManager::from_scripts(&[script], &ScriptLimits::default())
         })
     }
+
+    #[test]
+    fn test_extended_item_properties() {
+        let item_manager = load_source(
+            r#"
+            setInfo("Dirt", "BlockCube") pushItem()
+            setInfo({ name = "Torch", type = "BlockCross", transparent = true, light = 14,
+                      hardness = 0, drops = { "Dirt", { name = "Torch", count = 2 } } })
+            pushItem()
+            setInfo({ name = "Bedrock", type = "BlockCube", hardness = -1, drops = {},
+                      stack_size = 16, solid = false, tags = { "stone", "deep" } })
+            pushItem()
+        "#,
+        )
+        .unwrap();
+        let items = item_manager.get_items();
+
+        // Positional calls keep working and get the defaults
+        let dirt = &items[0];
+        assert_eq!((dirt.hardness, dirt.light, dirt.max_stack), (1.0, 0, 64));
+        assert!(dirt.is_solid && dirt.tags.is_empty());
+        assert_eq!(dirt.drops[0].name, "Dirt");
+
+        let torch = &items[1];
+        assert!(torch.is_transparent && torch.show_in_inventory && !torch.is_solid);
+        assert_eq!(torch.light, 14);
+        let drops: Vec<_> = torch
+            .drops
+            .iter()
+            .map(|drop| (drop.name.as_str(), drop.count))
+            .collect();
+        assert_eq!(drops, vec![("Dirt", 1), ("Torch", 2)]);
+
+        let bedrock = &items[2];
+        assert!(!bedrock.is_breakable() && !bedrock.is_solid);
+        assert!(bedrock.drops.is_empty());
+        assert_eq!(bedrock.max_stack, 16);
+        assert!(bedrock.has_tag("deep") && !bedrock.has_tag("wood"));
+    }
+
+    #[test]
+    fn test_set_info_keeps_table_textures() {
+        let item_manager = load_source(
+            r#"
+            setCoords(5, 5)
+            setInfo({ name = "Furnace", type = "BlockCube",
+                      textures = { top = { 1, 1 }, side = { 2, 2 }, north = { 3, 3 } } })
+            pushItem()
+            setInfo({ name = "Dirt", type = "BlockCube" })
+            pushItem()
+        "#,
+        )
+        .unwrap();
+        let textures: Vec<Vec<_>> = item_manager
+            .get_items()
+            .iter()
+            .map(|item| {
+                item.textures
+                    .iter()
+                    .map(|coords| (coords.x, coords.y))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            textures[0],
+            vec![(1, 1), (1, 1), (3, 3), (2, 2), (2, 2), (2, 2)]
+        );
+        assert_eq!(textures[1], vec![(5, 5); 6]);
+    }
+
+    #[test]
+    fn test_define_item() {
+        let item_manager = load_source(
+            r#"
+            setAtlas("terrain.png", 16, 16)
+            define_item({
+                name = "Grass", type = "BlockCube", hardness = 0.6,
+                textures = { top = { 3, 9 }, side = { 2, 9 } },
+            })
+            setInfo("Hidden", "UserItem", false, false) pushItem()
+        "#,
+        )
+        .unwrap();
+        let grass = &item_manager.get_items()[0];
+        assert_eq!(grass.hardness, 0.6);
+        assert_eq!(grass.atlas, Some(0));
+        let texture = |face| {
+            let coords = grass.get_texture(face);
+            (coords.x, coords.y)
+        };
+        assert_eq!(texture(Face::Top), (3, 9));
+        assert_eq!(texture(Face::West), (2, 9));
+        // Faces that are not given use the top texture
+        assert_eq!(texture(Face::Bottom), (3, 9));
+
+        assert!(!item_manager.get_items()[1].show_in_inventory);
+    }
+
+    #[test]
+    fn test_shapes_and_face_textures() {
+        let item_manager = load_source(
+            r#"
+            define_item({
+                name = "Furnace", type = "BlockCube",
+                textures = { top = { 1, 1 }, side = { 2, 2 }, north = { 3, 3 } },
+            })
+            define_item({ name = "Slab", type = "BlockSlab" })
+            define_item({ name = "Lamp", type = "BlockModel", model = "models/lamp.obj" })
+            define_item({ name = "Flower", type = "BlockCross" })
+        "#,
+        )
+        .unwrap();
+        let items = item_manager.get_items();
+
+        let furnace: Vec<_> = items[0]
+            .textures
+            .iter()
+            .map(|coords| (coords.x, coords.y))
+            .collect();
+        assert_eq!(
+            furnace,
+            vec![(1, 1), (1, 1), (3, 3), (2, 2), (2, 2), (2, 2)]
+        );
+
+        assert_eq!(items[1].item_type, ItemType::BlockSlab);
+        assert!(items[1].is_placeable() && items[1].is_solid);
+        assert_eq!(items[2].model.as_deref(), Some("models/lamp.obj"));
+        assert!(!items[3].is_solid);
+    }
+
+    #[test]
+    fn test_block_states() {
+        let item_manager = load_source(
+            r#"
+            define_item({
+                name = "Door", type = "BlockModel", model = "models/door.obj",
+                states = {
+                    { name = "facing", values = { "north", "south", "east", "west" } },
+                    { name = "open", values = { "false", "true" } },
+                },
+            })
+            define_item({ name = "Dirt", type = "BlockCube" })
+        "#,
+        )
+        .unwrap();
+        let door = &item_manager.get_items()[0];
+        assert_eq!(door.state_count(), 8);
+        assert_eq!(item_manager.get_items()[1].state_count(), 1);
+
+        let state = door
+            .encode_state([("open", "true"), ("facing", "east")])
+            .unwrap();
+        assert_eq!(state, 6);
+        assert_eq!(
+            door.decode_state(state),
+            vec![("facing", "east"), ("open", "true")]
+        );
+        // Properties that are not given keep their first value
+        assert_eq!(door.encode_state([("open", "true")]), Ok(4));
+
+        assert!(door.encode_state([("facing", "up")]).is_err());
+        assert!(door.encode_state([("color", "red")]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_item_definitions() {
+        let cases = [
+            (
+                "define_item({ type = \"BlockCube\" })",
+                "define_item needs a name",
+            ),
+            (
+                "define_item({ name = \"Sand\", type = \"Block\" })",
+                "item Sand: unknown type Block, expected one of Air, BlockCube, BlockCross, \
+                 UserItem, BlockSlab, BlockStairs, BlockFence, BlockPane, BlockModel",
+            ),
+            (
+                "define_item({ name = \"Sand\", hardnes = 1 })",
+                "item Sand: unknown key hardnes, expected one of name, type, model, transparent, \
+                 show_in_inventory, textures, hardness, light, stack_size, solid, drops, tags, \
+                 states",
+            ),
+            (
+                "define_item({ name = \"Sand\", transparent = \"yes\" })",
+                "item Sand: transparent must be a boolean",
+            ),
+            (
+                "define_item({ name = \"Lamp\", light = 20 })",
+                "item Lamp: light must be a number from 0 to 15",
+            ),
+            (
+                "define_item({ name = \"Sand\", textures = { top = { 1 } } })",
+                "item Sand: textures.top must be a list of x and y",
+            ),
+            (
+                "setAtlas(\"terrain.png\")\ndefine_item({ name = \"Sand\", textures = { top = { 10, 0 } } })",
+                "item Sand: textures.top 10, 0 is outside the 10x10 atlas",
+            ),
+            (
+                "define_item({ name = \"Lamp\", type = \"BlockModel\" })",
+                "item Lamp: model must be given for BlockModel items and only for them",
+            ),
+            (
+                "define_item({ name = \"Sand\", textures = { up = { 1, 1 } } })",
+                "item Sand: textures only takes top, bottom, side, north, south, east, west",
+            ),
+            (
+                "define_item({ name = \"Door\", states = { { name = \"open\", values = { \"no\" } } } })",
+                "item Door: state property open needs at least two different values",
+            ),
+            (
+                "add_asset(\"Sand\", \"BlockCube\", false, true, { 1, 2 })",
+                "add_asset needs 6 texture coordinates: top, side and bottom x and y",
+            ),
+            (
+                "define_item({ name = \"Ore\", drops = { \"Gem\" } })",
+                "item Ore drops unknown item Gem",
+            ),
+            (
+                "define_item({ name = \"Sand\" })\ndefine_item({ name = \"Sand\" })",
+                "item Sand is already defined",
+            ),
+        ];
+
+        for (source, message) in cases {
+            let error = load_source(source).err().unwrap();
+            assert!(
+                error.message.ends_with(message),
+                "{} gave {}",
+                source,
+                error.message
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_item_is_an_error() {
+        let error = load_source("setInfo(\"Dirt\", \"BlockCube\")\npushItem()\npushItem()")
+            .err()
+            .unwrap();
+        assert_eq!(error.line, Some(3));
+    }
 }
@@ -1,19 +1,44 @@
 mod vector_types;
-use vector_types::Vec2;
+use vector_types::{Vec2, Vec3};
+
+mod assets;
+use assets::AssetStore;
+
+mod block_edit;
+use block_edit::{apply_edit, BlockEdit};
+
+mod console;
+use console::{Console, ConsoleCommand};
+
+mod events;
+use events::{EventScripts, GameEvent, ScriptAction};
 
 mod items;
 
+mod mods;
+use mods::ScriptSources;
+
 mod player_data;
 
+mod rate_limit;
+use rate_limit::Verdict;
+
 mod save_file;
 use save_file::SaveFile;
 
+mod scripting;
+use scripting::{ScriptLimits, ScriptWatcher};
+
+mod session;
+use session::{columns_in_range, Session};
+
 use anyhow::Result;
 
 use enet::*;
 use std::net::Ipv4Addr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{env, str};
 
 mod packets;
@@ -22,41 +47,174 @@ use packets::PacketType;
 use crate::packets::*;
 
 mod world;
-use world::World;
+use world::chunk_column::MAX_CHUNK_Y_MAGNITUDE;
+use world::{ChunkEncoding, GeneratorKind, World, WorldHeight};
+
+const TICK_DURATION: Duration = Duration::from_millis(50);
+/// Longest chat message in bytes accepted from a client
+const MAX_CHAT_MESSAGE_LENGTH: usize = 256;
 
 struct GameOptions {
     init_only: bool,
+    /// Newest chunk encoding sent to clients, older clients get the encoding of their protocol
+    chunk_encoding: ChunkEncoding,
+    /// Radius in columns streamed around each player, 0 disables streaming
+    view_distance: i32,
+    /// Maximum number of columns pushed to a single client per tick
+    columns_per_tick: usize,
+    /// Furthest a client may request columns from its last known position
+    max_chunk_request_distance: i32,
+    /// Furthest a player may place or destroy blocks from its last known position
+    reach_distance: f32,
+    /// Vertical range used when a new world is created
+    world_height: WorldHeight,
+    /// Generator used when a new world is created
+    generator: GeneratorKind,
+    script_limits: ScriptLimits,
+    /// Reloads the scripts whenever a script in the save's script directory changes
+    watch_scripts: bool,
 }
 
 impl GameOptions {
     pub fn new() -> Self {
-        GameOptions { init_only: false }
+        GameOptions {
+            init_only: false,
+            chunk_encoding: ChunkEncoding::States,
+            view_distance: 8,
+            columns_per_tick: 4,
+            max_chunk_request_distance: 32,
+            reach_distance: 8.0,
+            world_height: WorldHeight::default(),
+            generator: GeneratorKind::default(),
+            script_limits: ScriptLimits::default(),
+            watch_scripts: false,
+        }
     }
 
     pub fn parse_cli(mut self) -> Self {
         let args: Vec<String> = env::args().collect();
 
         self.init_only = args.contains(&"--no_run".to_string());
+        if args.contains(&"--stateless_chunk_encoding".to_string()) {
+            self.chunk_encoding = ChunkEncoding::Varint;
+        }
+        if args.contains(&"--legacy_chunk_encoding".to_string()) {
+            self.chunk_encoding = ChunkEncoding::Fixed;
+        }
+        if let Some(view_distance) = GameOptions::get_value(&args, "--view_distance") {
+            self.view_distance = view_distance;
+        }
+        if let Some(columns_per_tick) = GameOptions::get_value(&args, "--columns_per_tick") {
+            self.columns_per_tick = columns_per_tick;
+        }
+        if let Some(distance) = GameOptions::get_value(&args, "--max_chunk_request_distance") {
+            self.max_chunk_request_distance = distance;
+        }
+        if let Some(reach_distance) = GameOptions::get_value(&args, "--reach_distance") {
+            self.reach_distance = reach_distance;
+        }
+        let min_chunk_y = GameOptions::get_value(&args, "--min_chunk_y");
+        let max_chunk_y = GameOptions::get_value(&args, "--max_chunk_y");
+        if min_chunk_y.is_some() || max_chunk_y.is_some() {
+            match WorldHeight::new(
+                min_chunk_y.unwrap_or(self.world_height.min_chunk_y),
+                max_chunk_y.unwrap_or(self.world_height.max_chunk_y),
+            ) {
+                Some(world_height) => self.world_height = world_height,
+                None => eprintln!(
+                    "Ignoring invalid world height range, chunk y must be within {} of 0",
+                    MAX_CHUNK_Y_MAGNITUDE
+                ),
+            }
+        }
+        if let Some(generator) = GameOptions::get_value(&args, "--generator") {
+            self.generator = generator;
+        }
+        self.script_limits.allow_unsafe_libraries = args.contains(&"--unsafe_scripts".to_string());
+        if let Some(limit) = GameOptions::get_value(&args, "--script_instruction_limit") {
+            self.script_limits.instruction_limit = limit;
+        }
+        if let Some(megabytes) = GameOptions::get_value::<usize>(&args, "--script_memory_mb") {
+            self.script_limits.memory_limit = megabytes * 1024 * 1024;
+        }
+        self.watch_scripts = args.contains(&"--watch_scripts".to_string());
 
         self
     }
+
+    /// Parses the value following `name` in `args`
+    fn get_value<T: str::FromStr>(args: &[String], name: &str) -> Option<T> {
+        let index = args.iter().position(|arg| arg == name)?;
+        match args.get(index + 1).map(|value| value.parse()) {
+            Some(Ok(value)) => Some(value),
+            _ => {
+                eprintln!("Ignoring invalid or missing value for {}", name);
+                None
+            }
+        }
+    }
 }
 
 struct Game {
     options: GameOptions,
 
-    server: Host<()>,
+    server: Host<Session>,
 
     world: World,
+    events: EventScripts,
+    /// Files sent to clients that request them, such as texture atlases
+    assets: AssetStore,
+    /// Actions requested by event handlers that still have to be sent to clients
+    pending_actions: Vec<ScriptAction>,
+    tick_count: u64,
+
+    console: Console,
+    script_watcher: Option<ScriptWatcher>,
 }
 
 impl Game {
     pub fn new() -> Result<Self> {
         let options = GameOptions::new().parse_cli();
+
+        let save_directory = "./save";
+        let mut save = SaveFile::new(Some(save_directory.to_owned()));
+        if let Err(e) = save.load() {
+            eprintln!("Save file could not be loaded with error \"{}\". The save file may not be generated yet!", e);
+            save.world_height = options.world_height;
+            save.generator = options.generator;
+        } else {
+            if save.world_height != options.world_height {
+                println!(
+                    "Using saved world height of chunks {} to {}",
+                    save.world_height.min_chunk_y, save.world_height.max_chunk_y
+                );
+            }
+            if save.generator != options.generator {
+                println!("Using saved world generator {}", save.generator);
+            }
+        }
+
+        // Scripts are loaded before binding the port so broken scripts are reported at startup
+        let sources = ScriptSources::new(&save, &options.script_limits)?;
+        Game::report_mods(&sources);
+        let events = EventScripts::load(&sources.event_scripts, &options.script_limits)?;
+
+        let script_watcher = if options.watch_scripts {
+            Some(ScriptWatcher::new(
+                save.get_script_directory(),
+                Instant::now(),
+            ))
+        } else {
+            None
+        };
+        let world = World::new(save, &sources, options.script_limits)?;
+        let assets = AssetStore::load(&sources.asset_directories)?;
+        Game::report_missing_assets(&world, &assets);
+
         let enet = Enet::new().unwrap();
         let address = Address::new(Ipv4Addr::UNSPECIFIED, 1234);
         let server = enet
-            .create_host::<()>(
+            .create_host::<Session>(
                 Some(&address),
                 1,
                 ChannelLimit::Limited(2),
@@ -65,21 +223,16 @@ impl Game {
             )
             .unwrap();
 
-        let save_directory = "./save";
-        let mut save = SaveFile::new(Some(save_directory.to_owned()));
-        if save.load().is_err() {
-            eprintln!("Save file could not be loaded with error \"{}\". The save file may not be generated yet!", save.load().unwrap_err());
-        }
-
-        let mut item_manager = items::ItemManager::new();
-        item_manager.load_items(save.get_script_path("loadAssetInfo".to_string()));
-
-        let world = World::new(item_manager, save);
-
         Ok(Game {
             options,
             server,
             world,
+            events,
+            assets,
+            pending_actions: Vec::new(),
+            tick_count: 0,
+            console: Console::spawn(),
+            script_watcher,
         })
     }
 
@@ -92,11 +245,24 @@ impl Game {
         let term = Arc::new(AtomicBool::new(false));
         signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&term)).unwrap();
 
+        let mut next_tick = Instant::now() + TICK_DURATION;
         while !term.load(Ordering::Relaxed) {
-            match self.server.service(1000).unwrap() {
-                Some(Event::Connect(_)) => println!("Connected!"),
-                Some(Event::Disconnect(..)) => {
+            let timeout = next_tick.saturating_duration_since(Instant::now());
+            match self.server.service(timeout.as_millis() as u32).unwrap() {
+                Some(Event::Connect(ref mut peer)) => {
+                    println!("Connected!");
+                    peer.set_data(Some(Session::new()));
+                }
+                Some(Event::Disconnect(ref peer, _)) => {
                     println!("Disconnected!");
+                    if let Some(username) = peer.data().and_then(|session| session.username.clone())
+                    {
+                        let outcome = self.events.dispatch(
+                            &mut self.world,
+                            &GameEvent::PlayerLeave { player: &username },
+                        );
+                        self.pending_actions.extend(outcome.actions);
+                    }
                 }
                 Some(Event::Receive {
                     ref mut sender,
@@ -105,89 +271,635 @@ impl Game {
                     ..
                 }) => {
                     let data = packet.data();
-                    if data[0] == PacketType::PlayerInfoRequest as u8 {
-                        // [0: Type][1-(n-1): username][n: '\0']
-                        let username = str::from_utf8(&data[1..(data.len() - 1)]).unwrap();
-                        let player = self
-                            .world
-                            .get_save_file()
-                            .get_user_data(&username.to_string());
-
-                        let packet_data = assemble_player_info_data(&player);
-                        let packet =
-                            Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                        sender.send_packet(packet, channel_id).unwrap();
+                    let verdict = match (data.first(), sender.data_mut()) {
+                        (Some(packet_id), Some(session)) => {
+                            session.flood_guard.check_packet(*packet_id, Instant::now())
+                        }
+                        _ => Verdict::Drop,
+                    };
+                    if verdict != Verdict::Allow {
+                        Game::reject_packet(sender, verdict);
+                    } else if data[0] == PacketType::PlayerInfoRequest as u8 {
+                        // [0: Type][1-(n-1): username][n: '\0'][n+1: protocol version]
+                        // Clients that predate the protocol version byte end the packet after the
+                        // username's terminator
+                        let end = data.iter().skip(1).position(|byte| *byte == b'\0');
+                        let username = end
+                            .and_then(|end| data.get(1..end + 1))
+                            .and_then(|bytes| str::from_utf8(bytes).ok());
+                        let protocol = end.and_then(|end| data.get(end + 2)).copied();
+                        if let Some(username) = username {
+                            let player = self
+                                .world
+                                .get_save_file()
+                                .get_user_data(&username.to_string());
+
+                            let mut joined = false;
+                            if let Some(session) = sender.data_mut() {
+                                if session.position.is_none() {
+                                    session.position = Some(player.position);
+                                }
+                                session.chunk_encoding = ChunkEncoding::for_protocol(protocol)
+                                    .min(self.options.chunk_encoding);
+                                joined = session.identify(username);
+                            }
+
+                            let packet_data = assemble_player_info_data(player);
+                            let packet =
+                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                            sender.send_packet(packet, channel_id).unwrap();
+
+                            if joined {
+                                // Sent before any chunk so the client can interpret the block ids
+                                for packet_data in Game::registry_packets(&self.world, &self.assets)
+                                {
+                                    let packet =
+                                        Packet::new(&packet_data, PacketMode::ReliableSequenced)
+                                            .unwrap();
+                                    sender.send_packet(packet, channel_id).unwrap();
+                                }
+
+                                let outcome = self.events.dispatch(
+                                    &mut self.world,
+                                    &GameEvent::PlayerJoin { player: username },
+                                );
+                                self.pending_actions.extend(outcome.actions);
+                            }
+                        } else {
+                            Game::reject_malformed(sender, "player info request");
+                        }
                     } else if data[0] == PacketType::PlayerInfoData as u8 {
                         // [0: Type][1-12: position][13-20: rotation][21-: username]
-                        let username = str::from_utf8(&data[21..(data.len() - 1)]).unwrap();
-                        let position = bincode::deserialize(&data[1..13]).unwrap();
-                        let rotation = bincode::deserialize(&data[13..21]).unwrap();
-
-                        let player = self
-                            .world
-                            .get_save_file()
-                            .get_user_data(&username.to_string());
-                        player.position = position;
-                        player.rotation = rotation;
+                        let position: Option<Vec3<f32>> = data
+                            .get(1..13)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let rotation: Option<Vec2<f32>> = data
+                            .get(13..21)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let username = data
+                            .get(21..data.len() - 1)
+                            .and_then(|bytes| str::from_utf8(bytes).ok());
+
+                        match (position, rotation, username) {
+                            (Some(position), Some(rotation), Some(username)) => {
+                                let player = self
+                                    .world
+                                    .get_save_file()
+                                    .get_user_data(&username.to_string());
+                                player.position = position;
+                                player.rotation = rotation;
+
+                                let mut joined = false;
+                                if let Some(session) = sender.data_mut() {
+                                    joined = session.identify(username);
+                                    session.position = Some(position);
+                                }
+
+                                if joined {
+                                    let outcome = self.events.dispatch(
+                                        &mut self.world,
+                                        &GameEvent::PlayerJoin { player: username },
+                                    );
+                                    self.pending_actions.extend(outcome.actions);
+                                }
+                            }
+                            _ => Game::reject_malformed(sender, "player info"),
+                        }
                     } else if data[0] == PacketType::ChunkRequest as u8 {
                         // [0: Type][1-4: column X][5-8: column Z]
-                        let col_x = bincode::deserialize(&data[1..5]).unwrap();
-                        let col_z = bincode::deserialize(&data[5..9]).unwrap();
-                        let col = self.world.get_column(&Vec2::new(col_x, col_z));
-
-                        let packet_data = assemble_chunk_contents_packet(col);
-                        let packet =
-                            Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                        sender.send_packet(packet, channel_id).unwrap();
+                        let col_position: Option<Vec2<i32>> = data
+                            .get(1..9)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let session = sender.data_mut().unwrap();
+                        match col_position {
+                            Some(col_position)
+                                if session.is_column_in_reach(
+                                    &col_position,
+                                    self.options.max_chunk_request_distance,
+                                ) =>
+                            {
+                                session.mark_loaded(col_position);
+                                let col = self.world.get_column(&col_position);
+
+                                let packet_data =
+                                    assemble_chunk_contents_packet(col, session.chunk_encoding);
+                                let packet =
+                                    Packet::new(&packet_data, PacketMode::ReliableSequenced)
+                                        .unwrap();
+                                sender.send_packet(packet, channel_id).unwrap();
+                            }
+                            Some(_) => {
+                                let verdict = session.flood_guard.record_violation(
+                                    "Requested chunks too far away",
+                                    Instant::now(),
+                                );
+                                Game::reject_packet(sender, verdict);
+                            }
+                            None => Game::reject_malformed(sender, "chunk request"),
+                        }
                     } else if data[0] == PacketType::ChunkUpdate as u8 {
-                        let block_pos = bincode::deserialize(&data[1..13]).unwrap();
-                        let action_type = data[13];
-
-                        let existing_id = self.world.get_block(&block_pos);
-                        if action_type == ChunkUpdateType::PlaceBlockEvent as u8 {
-                            if existing_id > 0 {
-                                println!(
-                                    "Cannot place block over id {} @ {},{},{}",
-                                    existing_id, block_pos.x, block_pos.y, block_pos.z
+                        // [0: Type][1-12: block position][13: action][14-17: block id]
+                        // [18-19: block state, 0 if left out]
+                        let block_pos: Option<Vec3<i32>> = data
+                            .get(1..13)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let edit = match (data.get(13), data.get(14..18)) {
+                            (Some(action), Some(id_bytes))
+                                if *action == ChunkUpdateType::PlaceBlockEvent as u8 =>
+                            {
+                                let block_id: u32 = bincode::deserialize(id_bytes).unwrap();
+                                let state: u16 = data
+                                    .get(18..20)
+                                    .map_or(0, |bytes| bincode::deserialize(bytes).unwrap());
+                                Some(BlockEdit::Place(block_id as i32, state))
+                            }
+                            (Some(action), _)
+                                if *action == ChunkUpdateType::DestroyBlockEvent as u8 =>
+                            {
+                                Some(BlockEdit::Destroy)
+                            }
+                            _ => None,
+                        };
+
+                        match (block_pos, edit) {
+                            (Some(block_pos), Some(edit)) => {
+                                let player_position =
+                                    sender.data().and_then(|session| session.position);
+                                let player = sender
+                                    .data()
+                                    .and_then(|session| session.username.clone())
+                                    .unwrap_or_default();
+                                let (result, actions) = apply_edit(
+                                    &mut self.world,
+                                    &self.events,
+                                    &player,
+                                    player_position,
+                                    self.options.reach_distance,
+                                    &block_pos,
+                                    &edit,
                                 );
-                            } else {
-                                let block_id: u32 = bincode::deserialize(&data[14..18]).unwrap();
-                                self.world.set_block(&block_pos, block_id as i32);
+                                self.pending_actions.extend(actions);
+
+                                match result {
+                                    Ok(()) => {
+                                        let col_position = World::world_to_column_position(
+                                            &Vec2::new(block_pos.x, block_pos.z),
+                                        );
+                                        let packet_data = assemble_chunk_contents_packet(
+                                            self.world.get_column(&col_position),
+                                            Game::chunk_encoding(sender),
+                                        );
+                                        let packet = Packet::new(
+                                            &packet_data,
+                                            PacketMode::ReliableSequenced,
+                                        )
+                                        .unwrap();
+                                        sender.send_packet(packet, channel_id).unwrap();
+                                    }
+                                    Err(rejection) => {
+                                        println!(
+                                            "Rejected block edit @ {},{},{}: {}",
+                                            block_pos.x, block_pos.y, block_pos.z, rejection
+                                        );
+
+                                        // Tell the client what is really there, no block can be
+                                        // outside the world height
+                                        if let Some((block_id, state)) =
+                                            self.world.get_block_with_state(&block_pos)
+                                        {
+                                            let packet_data = assemble_block_update_packet(
+                                                &block_pos,
+                                                block_id,
+                                                state,
+                                                Game::chunk_encoding(sender),
+                                            );
+                                            let packet = Packet::new(
+                                                &packet_data,
+                                                PacketMode::ReliableSequenced,
+                                            )
+                                            .unwrap();
+                                            sender.send_packet(packet, channel_id).unwrap();
+                                        }
+                                    }
+                                }
                             }
-                        } else if action_type == ChunkUpdateType::DestroyBlockEvent as u8 {
-                            if existing_id < 1 {
-                                println!(
-                                    "Cannot destroy empty block id {} @ {},{},{}",
-                                    existing_id, block_pos.x, block_pos.y, block_pos.z
+                            _ => Game::reject_malformed(sender, "chunk update"),
+                        }
+                    } else if data[0] == PacketType::ChatMessage as u8 {
+                        // [0: Type][1-(n-1): message][n: '\0']
+                        let message = data
+                            .get(1..data.len() - 1)
+                            .and_then(|bytes| str::from_utf8(bytes).ok())
+                            .map(|message| message.trim())
+                            .filter(|message| {
+                                !message.is_empty() && message.len() <= MAX_CHAT_MESSAGE_LENGTH
+                            });
+                        let username = sender.data().and_then(|session| session.username.clone());
+
+                        match (message, username) {
+                            (Some(message), Some(username)) => {
+                                let outcome = self.events.dispatch(
+                                    &mut self.world,
+                                    &GameEvent::Chat {
+                                        player: &username,
+                                        message,
+                                    },
                                 );
-                            } else {
-                                self.world.set_block(&block_pos, 0);
+                                self.pending_actions.extend(outcome.actions);
+                                if !outcome.cancelled {
+                                    self.pending_actions.push(ScriptAction::SendMessage {
+                                        player: None,
+                                        message: format!("<{}> {}", username, message),
+                                    });
+                                }
                             }
-                        } else {
-                            println!(
-                                "Received unknown chunk update type with value {}",
-                                action_type
-                            );
+                            _ => Game::reject_malformed(sender, "chat message"),
+                        }
+                    } else if data[0] == PacketType::BiomeRequest as u8 {
+                        // [0: Type][1-4: block X][5-8: block Z]
+                        let position: Option<Vec2<i32>> = data
+                            .get(1..9)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let session = sender.data_mut().unwrap();
+                        match position {
+                            Some(position)
+                                if session.is_column_in_reach(
+                                    &World::world_to_column_position(&position),
+                                    self.options.max_chunk_request_distance,
+                                ) =>
+                            {
+                                let biome = self.world.get_biome(position.x, position.y);
+                                let packet_data =
+                                    assemble_biome_data_packet(position.x, position.y, biome);
+                                let packet =
+                                    Packet::new(&packet_data, PacketMode::ReliableSequenced)
+                                        .unwrap();
+                                sender.send_packet(packet, channel_id).unwrap();
+                            }
+                            Some(_) => {
+                                let verdict = session.flood_guard.record_violation(
+                                    "Requested biomes too far away",
+                                    Instant::now(),
+                                );
+                                Game::reject_packet(sender, verdict);
+                            }
+                            None => Game::reject_malformed(sender, "biome request"),
+                        }
+                    } else if data[0] == PacketType::AssetRequest as u8 {
+                        // [0: Type][1-4: offset][5-(n-1): name][n: '\0']
+                        let offset: Option<u32> = data
+                            .get(1..5)
+                            .and_then(|bytes| bincode::deserialize(bytes).ok());
+                        let name = data
+                            .get(5..data.len() - 1)
+                            .and_then(|bytes| str::from_utf8(bytes).ok());
+                        let packet_data = offset.zip(name).and_then(|(offset, name)| {
+                            let asset = self.assets.get(name)?;
+                            assemble_asset_chunk_packet(asset, offset as usize)
+                        });
+                        match packet_data {
+                            Some(packet_data) => {
+                                let packet =
+                                    Packet::new(&packet_data, PacketMode::ReliableSequenced)
+                                        .unwrap();
+                                sender.send_packet(packet, channel_id).unwrap();
+                            }
+                            None => Game::reject_malformed(sender, "asset request"),
                         }
-
-                        let col_position =
-                            World::world_to_column_position(&Vec2::new(block_pos.x, block_pos.z));
-                        let packet_data =
-                            assemble_chunk_contents_packet(self.world.get_column(&col_position));
-                        let packet =
-                            Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
-                        sender.send_packet(packet, channel_id).unwrap();
                     } else {
                         println!("Unknown packet id: {}", data[0])
                     }
                 }
                 _ => (),
             }
+            self.apply_script_actions();
+
+            if Instant::now() >= next_tick {
+                self.process_commands();
+                self.tick();
+                next_tick = Instant::now() + TICK_DURATION;
+            }
         }
 
         Ok(())
     }
 
+    /// Drops a packet that broke the limits, kicking the client if the verdict says so
+    fn reject_packet(peer: &mut Peer<Session>, verdict: Verdict) {
+        if let Verdict::Kick(reason) = verdict {
+            let name = peer
+                .data()
+                .and_then(|session| session.username.clone())
+                .unwrap_or_else(|| "unknown".to_string());
+            println!("Kicking {} for \"{}\"", name, reason);
+
+            let packet_data = assemble_player_kick_packet(&reason);
+            let packet = Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+            peer.send_packet(packet, 0).unwrap();
+            peer.disconnect_later(0);
+        }
+    }
+
+    /// Gets the encoding of the chunks and block updates sent to `peer`
+    fn chunk_encoding(peer: &Peer<Session>) -> ChunkEncoding {
+        peer.data()
+            .map_or(ChunkEncoding::Fixed, |session| session.chunk_encoding)
+    }
+
+    /// Drops a packet that could not be parsed, which counts as a violation of the limits
+    fn reject_malformed(peer: &mut Peer<Session>, packet: &str) {
+        println!("Received malformed {}", packet);
+        let verdict = match peer.data_mut() {
+            Some(session) => session
+                .flood_guard
+                .record_violation(&format!("Sent a malformed {}", packet), Instant::now()),
+            None => Verdict::Drop,
+        };
+        Game::reject_packet(peer, verdict);
+    }
+
+    /// Sends the results of the queued event handler actions to the clients
+    fn apply_script_actions(&mut self) {
+        for action in std::mem::take(&mut self.pending_actions) {
+            match action {
+                ScriptAction::UpdateBlock(position) => {
+                    let col_position =
+                        World::world_to_column_position(&Vec2::new(position.x, position.z));
+                    // Scripts can only change blocks inside the world height
+                    let (id, state) = match self.world.get_block_with_state(&position) {
+                        Some(block) => block,
+                        None => continue,
+                    };
+                    for mut peer in self.server.peers() {
+                        if peer.state() == PeerState::Connected
+                            && peer
+                                .data()
+                                .is_some_and(|session| session.has_loaded(&col_position))
+                        {
+                            let packet_data = assemble_block_update_packet(
+                                &position,
+                                id,
+                                state,
+                                Game::chunk_encoding(&peer),
+                            );
+                            let packet =
+                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                            peer.send_packet(packet, 0).unwrap();
+                        }
+                    }
+                }
+                ScriptAction::UpdateBlockEntity(position) => {
+                    let col_position =
+                        World::world_to_column_position(&Vec2::new(position.x, position.z));
+                    let packet_data = assemble_block_entity_packet(
+                        &position,
+                        self.world.get_block_entity(&position),
+                    );
+                    for mut peer in self.server.peers() {
+                        if peer.state() == PeerState::Connected
+                            && peer.data().is_some_and(|session| {
+                                session.has_loaded(&col_position)
+                                    && session.chunk_encoding == ChunkEncoding::States
+                            })
+                        {
+                            let packet =
+                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                            peer.send_packet(packet, 0).unwrap();
+                        }
+                    }
+                }
+                ScriptAction::SendMessage { player, message } => {
+                    println!("{}", message);
+                    let packet_data = assemble_server_message_packet(&message);
+                    for mut peer in self.server.peers() {
+                        let is_recipient = peer.data().is_some_and(|session| {
+                            session.username.is_some()
+                                && (player.is_none() || session.username == player)
+                        });
+                        if peer.state() == PeerState::Connected && is_recipient {
+                            let packet =
+                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                            peer.send_packet(packet, 0).unwrap();
+                        }
+                    }
+                }
+                ScriptAction::Teleport { player, position } => {
+                    self.world.get_save_file().get_user_data(&player).position = position;
+
+                    let packet_data = assemble_player_teleport_packet(&position);
+                    for mut peer in self.server.peers() {
+                        if peer.state() != PeerState::Connected {
+                            continue;
+                        }
+                        if let Some(session) = peer.data_mut() {
+                            if session.username.as_deref() != Some(player.as_str()) {
+                                continue;
+                            }
+                            session.position = Some(position);
+                            let packet =
+                                Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                            peer.send_packet(packet, 0).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs commands typed into the console and reloads scripts that changed on disk
+    fn process_commands(&mut self) {
+        while let Some(line) = self.console.try_read_line() {
+            match ConsoleCommand::parse(&line) {
+                Ok(ConsoleCommand::Help) => println!("{}", ConsoleCommand::USAGE),
+                Ok(ConsoleCommand::Reload) => self.reload_scripts(),
+                Ok(ConsoleCommand::Regenerate { center, radius }) => {
+                    self.regenerate_columns(&center, radius)
+                }
+                Err(e) => println!("{}. Type \"help\" for a list of commands", e),
+            }
+        }
+
+        let scripts_changed = match &mut self.script_watcher {
+            Some(watcher) => watcher.poll(Instant::now()),
+            None => false,
+        };
+        if scripts_changed {
+            println!("Scripts changed on disk, reloading");
+            self.reload_scripts();
+        }
+    }
+
+    fn reload_scripts(&mut self) {
+        let sources =
+            match ScriptSources::new(self.world.get_save_file(), &self.options.script_limits) {
+                Ok(sources) => sources,
+                Err(e) => {
+                    eprintln!("Scripts were not reloaded: {}", e);
+                    return;
+                }
+            };
+        Game::report_mods(&sources);
+
+        match self.world.reload_scripts(&sources) {
+            Ok(()) => {
+                println!("Scripts reloaded");
+
+                match AssetStore::load(&sources.asset_directories) {
+                    Ok(assets) => self.assets = assets,
+                    Err(e) => eprintln!("Assets were not reloaded: {}", e),
+                }
+                Game::report_missing_assets(&self.world, &self.assets);
+
+                // Reloads may add items, which clients need before they see them in chunks
+                let packets = Game::registry_packets(&self.world, &self.assets);
+                for mut peer in self.server.peers() {
+                    let joined = peer
+                        .data()
+                        .is_some_and(|session| session.username.is_some());
+                    if peer.state() != PeerState::Connected || !joined {
+                        continue;
+                    }
+                    for packet_data in &packets {
+                        let packet =
+                            Packet::new(packet_data, PacketMode::ReliableSequenced).unwrap();
+                        peer.send_packet(packet, 0).unwrap();
+                    }
+                }
+            }
+            Err(e) => eprintln!("Scripts were not reloaded: {}", e),
+        }
+
+        match EventScripts::load(&sources.event_scripts, &self.options.script_limits) {
+            Ok(events) => {
+                self.events = events;
+                println!("Event scripts reloaded");
+            }
+            Err(e) => eprintln!("Event scripts were not reloaded: {}", e),
+        }
+    }
+
+    /// Gets the packets telling a client about the items and the assets it may request
+    fn registry_packets(world: &World, assets: &AssetStore) -> [Vec<u8>; 2] {
+        [
+            assemble_item_registry_packet(world.get_item_manager()),
+            assemble_asset_list_packet(assets),
+        ]
+    }
+
+    /// Warns about texture atlases and models used by item scripts that clients cannot download
+    fn report_missing_assets(world: &World, assets: &AssetStore) {
+        let item_manager = world.get_item_manager();
+        for atlas in item_manager.get_atlases() {
+            if assets.get(&atlas.path).is_none() {
+                println!("Texture atlas {} is not in any asset directory", atlas.path);
+            }
+        }
+        for item in item_manager.get_items() {
+            if let Some(model) = &item.model {
+                if assets.get(model).is_none() {
+                    println!(
+                        "Model {} of item {} is not in any asset directory",
+                        model, item.name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Lists the loaded mods and the conflicts between them
+    fn report_mods(sources: &ScriptSources) {
+        for manifest in &sources.mods {
+            println!("Loaded mod {} {}", manifest.name, manifest.version);
+        }
+        for conflict in &sources.conflicts {
+            println!("Mod conflict: {}", conflict);
+        }
+    }
+
+    /// Regenerates the unmodified columns around `center` and resends them to clients that have them loaded
+    fn regenerate_columns(&mut self, center: &Vec2<i32>, radius: i32) {
+        let mut regenerated = Vec::new();
+        let mut kept = 0;
+        for col_position in columns_in_range(center, radius) {
+            if self.world.regenerate_column(&col_position) {
+                regenerated.push(col_position);
+            } else if self.world.is_column_modified(&col_position) {
+                kept += 1;
+            }
+        }
+        println!(
+            "Regenerated {} columns, kept {} modified columns",
+            regenerated.len(),
+            kept
+        );
+
+        for mut peer in self.server.peers() {
+            if peer.state() != PeerState::Connected {
+                continue;
+            }
+            for col_position in &regenerated {
+                if !peer
+                    .data()
+                    .is_some_and(|session| session.has_loaded(col_position))
+                {
+                    continue;
+                }
+                let packet_data = assemble_chunk_contents_packet(
+                    self.world.get_column(col_position),
+                    Game::chunk_encoding(&peer),
+                );
+                let packet = Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                peer.send_packet(packet, 0).unwrap();
+            }
+        }
+    }
+
+    /// Runs the fixed rate server update
+    fn tick(&mut self) {
+        self.tick_count += 1;
+        let outcome = self.events.dispatch(
+            &mut self.world,
+            &GameEvent::Tick {
+                tick: self.tick_count,
+            },
+        );
+        self.pending_actions.extend(outcome.actions);
+        self.apply_script_actions();
+
+        if self.options.view_distance <= 0 {
+            return;
+        }
+
+        for mut peer in self.server.peers() {
+            if peer.state() != PeerState::Connected {
+                continue;
+            }
+            let (update, encoding) = match peer.data_mut() {
+                Some(session) => (
+                    session.update_streaming(
+                        self.options.view_distance,
+                        self.options.columns_per_tick,
+                    ),
+                    session.chunk_encoding,
+                ),
+                None => continue,
+            };
+
+            if !update.unload.is_empty() {
+                let packet_data = assemble_chunk_unload_packet(&update.unload);
+                let packet = Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                peer.send_packet(packet, 0).unwrap();
+            }
+
+            for col_position in &update.load {
+                let packet_data =
+                    assemble_chunk_contents_packet(self.world.get_column(col_position), encoding);
+                let packet = Packet::new(&packet_data, PacketMode::ReliableSequenced).unwrap();
+                peer.send_packet(packet, 0).unwrap();
+            }
+        }
+    }
+
     pub fn shutdown(&mut self) -> Result<()> {
         self.world.save_to_file();
 
@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::{fmt, fs, io};
+
+use rlua::Table;
+
+use crate::items::ItemScript;
+use crate::save_file::SaveFile;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+
+const MANIFEST_FILE: &str = "mod.lua";
+const ITEM_SCRIPT_FILE: &str = "items.lua";
+const GENERATOR_SCRIPT_FILE: &str = "generator.lua";
+const BIOME_SCRIPT_FILE: &str = "biomes.lua";
+const STRUCTURE_SCRIPT_FILE: &str = "structures.lua";
+const UNDERGROUND_SCRIPT_FILE: &str = "underground.lua";
+const EVENT_SCRIPT_FILE: &str = "events.lua";
+const ASSET_DIRECTORY: &str = "assets";
+
+/// Errors found while loading the mods of a world
+#[derive(Debug)]
+pub enum ModError {
+    Io(io::Error),
+    /// The `mod.lua` of a mod failed to run or returned an invalid manifest
+    Manifest(ScriptError),
+    /// Mod names may only contain lowercase letters, digits and underscores
+    InvalidName(String),
+    DuplicateName(String),
+    MissingDependency {
+        name: String,
+        dependency: String,
+    },
+    /// The mods depend on each other so no load order exists
+    DependencyCycle(Vec<String>),
+}
+
+impl fmt::Display for ModError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModError::Io(e) => write!(f, "unable to read mods: {}", e),
+            ModError::Manifest(e) => write!(f, "invalid mod manifest: {}", e),
+            ModError::InvalidName(name) => write!(f, "invalid mod name \"{}\"", name),
+            ModError::DuplicateName(name) => write!(f, "more than one mod is named {}", name),
+            ModError::MissingDependency { name, dependency } => {
+                write!(f, "mod {} depends on missing mod {}", name, dependency)
+            }
+            ModError::DependencyCycle(names) => {
+                write!(f, "mods {} depend on each other", names.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModError {}
+
+impl From<io::Error> for ModError {
+    fn from(e: io::Error) -> Self {
+        ModError::Io(e)
+    }
+}
+
+/// Contents of a mod's `mod.lua`, which returns a table like
+/// `{ name = "castles", version = "1.0", dependencies = { "stone" }, load_after = { "trees" } }`
+pub struct ModManifest {
+    pub name: String,
+    pub version: String,
+    /// Mods that must be present and are loaded first
+    pub dependencies: Vec<String>,
+    /// Mods that are loaded first if present
+    pub load_after: Vec<String>,
+    /// Directory of the mod, does not contain trailing slashes
+    pub directory: String,
+}
+
+impl ModManifest {
+    /// Runs the manifest of the mod in `directory`
+    pub fn load(directory: &str, limits: &ScriptLimits) -> Result<ModManifest, ModError> {
+        let path = format!("{}/{}", directory, MANIFEST_FILE);
+        let source = fs::read_to_string(&path).map_err(|e| {
+            ModError::Manifest(ScriptError::new(
+                &path,
+                format!("unable to read manifest: {}", e),
+            ))
+        })?;
+
+        let lua = new_sandboxed_lua(limits);
+        let manifest = run_limited(&lua, limits, |lua_ctx| {
+            let table: Table = lua_ctx.load(&source).set_name(&chunk_name(&path))?.eval()?;
+
+            let name: Option<String> = table.get("name")?;
+            let version: Option<String> = table.get("version")?;
+            let dependencies: Option<Vec<String>> = table.get("dependencies")?;
+            let load_after: Option<Vec<String>> = table.get("load_after")?;
+            Ok(ModManifest {
+                name: name
+                    .ok_or_else(|| rlua::Error::RuntimeError("manifest has no name".to_string()))?,
+                version: version.unwrap_or_else(|| "0.0.0".to_string()),
+                dependencies: dependencies.unwrap_or_default(),
+                load_after: load_after.unwrap_or_default(),
+                directory: directory.to_string(),
+            })
+        })
+        .map_err(|e| ModError::Manifest(ScriptError::from_lua(&path, &e)))?;
+
+        let is_valid_name = !manifest.name.is_empty()
+            && manifest
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_valid_name {
+            return Err(ModError::InvalidName(manifest.name));
+        }
+
+        Ok(manifest)
+    }
+
+    /// Gets the path of `file` if the mod provides it
+    fn script_path(&self, file: &str) -> Option<String> {
+        let path = format!("{}/{}", self.directory, file);
+        Path::new(&path).is_file().then_some(path)
+    }
+}
+
+/// Sorts `mods` so every mod comes after its dependencies, otherwise alphabetically
+pub fn load_order(mods: Vec<ModManifest>) -> Result<Vec<ModManifest>, ModError> {
+    let mut by_name = BTreeMap::new();
+    for manifest in mods {
+        if by_name.contains_key(&manifest.name) {
+            return Err(ModError::DuplicateName(manifest.name));
+        }
+        by_name.insert(manifest.name.clone(), manifest);
+    }
+
+    // Mods that still have to be loaded before each mod
+    let mut waiting_on = BTreeMap::new();
+    for manifest in by_name.values() {
+        for dependency in &manifest.dependencies {
+            if !by_name.contains_key(dependency) {
+                return Err(ModError::MissingDependency {
+                    name: manifest.name.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+        let before: BTreeSet<String> = manifest
+            .dependencies
+            .iter()
+            .chain(manifest.load_after.iter())
+            .filter(|name| by_name.contains_key(*name))
+            .cloned()
+            .collect();
+        waiting_on.insert(manifest.name.clone(), before);
+    }
+
+    let mut order = Vec::new();
+    while !waiting_on.is_empty() {
+        let ready = waiting_on
+            .iter()
+            .find(|(_, before)| before.is_empty())
+            .map(|(name, _)| name.clone());
+        match ready {
+            Some(name) => {
+                waiting_on.remove(&name);
+                for before in waiting_on.values_mut() {
+                    before.remove(&name);
+                }
+                order.push(by_name.remove(&name).unwrap());
+            }
+            None => return Err(ModError::DependencyCycle(waiting_on.into_keys().collect())),
+        }
+    }
+
+    Ok(order)
+}
+
+/// Every script the server runs for a world, from its script directory and its mods
+pub struct ScriptSources {
+    pub item_scripts: Vec<ItemScript>,
+    pub column_script: String,
+    /// Biome scripts in load order, later biomes replace earlier ones with the same name
+    pub biome_scripts: Vec<String>,
+    /// Structure scripts in load order, later structures replace earlier ones with the same name
+    pub structure_scripts: Vec<String>,
+    /// Cave and ore scripts in load order, later ores replace earlier ones with the same name
+    pub underground_scripts: Vec<String>,
+    pub event_scripts: Vec<String>,
+    /// Directories of the assets sent to clients, later assets replace earlier ones with the same name
+    pub asset_directories: Vec<String>,
+    /// Mods in the order they were loaded
+    pub mods: Vec<ModManifest>,
+    /// Mods overriding each other in ways that work but may not be intended
+    pub conflicts: Vec<String>,
+}
+
+impl ScriptSources {
+    /// Finds the scripts of the world saved in `save` and orders its mods
+    pub fn new(save: &SaveFile, limits: &ScriptLimits) -> Result<ScriptSources, ModError> {
+        let mut sources = ScriptSources {
+            item_scripts: vec![ItemScript {
+                path: save.get_script_path("loadAssetInfo".to_string()),
+                namespace: None,
+            }],
+            column_script: save.get_script_path("generateChunkColumn".to_string()),
+            biome_scripts: vec![save.get_script_path("biomes".to_string())],
+            structure_scripts: vec![save.get_script_path("structures".to_string())],
+            underground_scripts: vec![save.get_script_path("underground".to_string())],
+            event_scripts: save.get_event_script_paths()?,
+            asset_directories: vec![format!(
+                "{}/{}",
+                save.get_script_directory(),
+                ASSET_DIRECTORY
+            )],
+            mods: Vec::new(),
+            conflicts: Vec::new(),
+        };
+
+        let mod_directory = match save.get_mod_directory() {
+            Some(directory) => directory,
+            None => return Ok(sources),
+        };
+        let mut manifests = Vec::new();
+        for entry in fs::read_dir(mod_directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                manifests.push(ModManifest::load(&path.to_string_lossy(), limits)?);
+            }
+        }
+
+        let mut generator_mod: Option<String> = None;
+        for manifest in load_order(manifests)? {
+            if let Some(path) = manifest.script_path(ITEM_SCRIPT_FILE) {
+                sources.item_scripts.push(ItemScript {
+                    path,
+                    namespace: Some(manifest.name.clone()),
+                });
+            }
+            if let Some(path) = manifest.script_path(GENERATOR_SCRIPT_FILE) {
+                if let Some(previous) = &generator_mod {
+                    sources.conflicts.push(format!(
+                        "the generator of mod {} replaces the generator of mod {}",
+                        manifest.name, previous
+                    ));
+                }
+                generator_mod = Some(manifest.name.clone());
+                sources.column_script = path;
+            }
+            if let Some(path) = manifest.script_path(BIOME_SCRIPT_FILE) {
+                sources.biome_scripts.push(path);
+            }
+            if let Some(path) = manifest.script_path(STRUCTURE_SCRIPT_FILE) {
+                sources.structure_scripts.push(path);
+            }
+            if let Some(path) = manifest.script_path(UNDERGROUND_SCRIPT_FILE) {
+                sources.underground_scripts.push(path);
+            }
+            if let Some(path) = manifest.script_path(EVENT_SCRIPT_FILE) {
+                sources.event_scripts.push(path);
+            }
+            sources
+                .asset_directories
+                .push(format!("{}/{}", manifest.directory, ASSET_DIRECTORY));
+            sources.mods.push(manifest);
+        }
+
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::ItemManager;
+
+    fn manifest(name: &str, dependencies: &[&str], load_after: &[&str]) -> ModManifest {
+        ModManifest {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            dependencies: dependencies.iter().map(|name| name.to_string()).collect(),
+            load_after: load_after.iter().map(|name| name.to_string()).collect(),
+            directory: String::new(),
+        }
+    }
+
+    fn names(mods: &[ModManifest]) -> Vec<&str> {
+        mods.iter().map(|manifest| manifest.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_load_order() {
+        let order = load_order(vec![
+            manifest("castles", &["stone"], &["trees"]),
+            manifest("trees", &[], &["missing_is_fine"]),
+            manifest("stone", &[], &[]),
+            manifest("apples", &["trees"], &[]),
+        ])
+        .unwrap();
+        assert_eq!(names(&order), vec!["stone", "trees", "apples", "castles"]);
+
+        assert!(matches!(
+            load_order(vec![manifest("castles", &["stone"], &[])]),
+            Err(ModError::MissingDependency { .. })
+        ));
+        assert!(matches!(
+            load_order(vec![manifest("a", &[], &[]), manifest("a", &[], &[])]),
+            Err(ModError::DuplicateName(_))
+        ));
+        assert!(matches!(
+            load_order(vec![
+                manifest("a", &["b"], &[]),
+                manifest("b", &[], &["a"]),
+                manifest("c", &[], &[]),
+            ]),
+            Err(ModError::DependencyCycle(names)) if names == vec!["a", "b"]
+        ));
+    }
+
+    #[test]
+    fn test_script_sources_from_mods() {
+        let directory =
+            std::env::temp_dir().join(format!("voxelbuilder_mods_{}", rand::random::<u32>()));
+        let directory = directory.to_string_lossy().to_string();
+        let save = SaveFile::new(Some(directory.clone()));
+        let mod_directory = save.get_mod_directory().unwrap();
+
+        let files = [
+            (
+                "stone/mod.lua",
+                "return { name = \"stone\", version = \"2.1\" }",
+            ),
+            (
+                "stone/items.lua",
+                "setInfo(\"Marble\", \"BlockCube\") setCoords(1, 1) pushItem()",
+            ),
+            ("stone/generator.lua", ""),
+            (
+                "castles/mod.lua",
+                "return { name = \"castles\", dependencies = { \"stone\" } }",
+            ),
+            (
+                "castles/items.lua",
+                "setInfo(\"Marble\", \"BlockCube\") setCoords(2, 1) pushItem()",
+            ),
+            ("castles/generator.lua", ""),
+            ("castles/events.lua", ""),
+            ("castles/biomes.lua", ""),
+            ("castles/structures.lua", ""),
+            ("castles/underground.lua", ""),
+        ];
+        for (file, contents) in files {
+            let path = format!("{}/{}", mod_directory, file);
+            fs::create_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let limits = ScriptLimits::default();
+        let sources = ScriptSources::new(&save, &limits).unwrap();
+        assert_eq!(names(&sources.mods), vec!["stone", "castles"]);
+        assert_eq!(sources.mods[0].version, "2.1");
+        assert!(sources.column_script.ends_with("castles/generator.lua"));
+        assert!(sources.event_scripts[0].ends_with("scripts/events.lua"));
+        assert!(sources.event_scripts[1].ends_with("castles/events.lua"));
+        assert!(sources.biome_scripts[1].ends_with("castles/biomes.lua"));
+        assert!(sources.structure_scripts[1].ends_with("castles/structures.lua"));
+        assert!(sources.underground_scripts[1].ends_with("castles/underground.lua"));
+        assert!(sources.asset_directories[2].ends_with("castles/assets"));
+        assert_eq!(sources.conflicts.len(), 1);
+
+        // Items of different mods never clash
+        let item_manager = ItemManager::from_scripts(&sources.item_scripts, &limits).unwrap();
+        let marble = item_manager.get_id_by_name("stone:Marble".to_string());
+        let castle_marble = item_manager.get_id_by_name("castles:Marble".to_string());
+        assert!(marble.is_some() && castle_marble.is_some() && marble != castle_marble);
+        assert!(item_manager.get_id_by_name("Marble".to_string()).is_none());
+
+        fs::write(
+            format!("{}/castles/mod.lua", mod_directory),
+            "return { name = \"Castles!\" }",
+        )
+        .unwrap();
+        assert!(matches!(
+            ScriptSources::new(&save, &limits),
+            Err(ModError::InvalidName(_))
+        ));
+
+        fs::remove_dir_all(directory).ok();
+    }
+}
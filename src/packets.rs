@@ -1,5 +1,7 @@
 #[allow(dead_code)]
 pub enum PacketType {
+    // TODO: Add client command to server // Send a command from the client to the server, after
+    // the other packets so their ids stay the same
     PlayerConnect,
     PlayerDisconnect,
     PlayerInfoRequest, // Get saved player data from file (if available)
@@ -7,8 +9,19 @@ pub enum PacketType {
     ChunkRequest,      // Request from the client to send data about a chunk
     ChunkUpdate,       // Request from the client to update a chunk
     ChunkContents,     // The contents of a chunk as requested by the client
-                       // TODO: Add server message to client // Send a message from the server to the client
-                       // TODO: Add client command to server // Send a command from the client to the server
+    ChunkUnload,       // Columns that left the client's view distance
+    PlayerKick,        // The server is disconnecting the client, with the reason
+    BlockUpdate,       // The authoritative id of a single block
+    ServerMessage,     // A chat or script message from the server to the client
+    ChatMessage,       // A chat message from the client to the server
+    PlayerTeleport,    // The server moved the player to a new position
+    BiomeRequest,      // Request from the client for the biome at a block position
+    BiomeData,         // The biome at a block position as requested by the client
+    ItemRegistry,      // Every item and texture atlas of the server, sent on join and after reloads
+    AssetList,         // Names, hashes and sizes of the assets clients need, sent with the items
+    AssetRequest,      // Request from the client for the part of an asset starting at an offset
+    AssetChunk,        // Part of an asset as requested by the client
+    BlockEntityData,   // A block entity that was set or removed by a script
 }
 
 pub enum ChunkUpdateType {
@@ -16,7 +29,13 @@ pub enum ChunkUpdateType {
     DestroyBlockEvent,
 }
 
-use crate::{player_data::Player, world::ChunkColumn};
+use crate::{
+    assets::{Asset, AssetStore},
+    items::{Face, ItemManager},
+    player_data::Player,
+    vector_types::{Vec2, Vec3},
+    world::{chunk_encoding::encode_sets, Biome, ChunkColumn, ChunkEncoding, EntityValue},
+};
 
 pub fn assemble_player_info_data(player: &Player) -> Vec<u8> {
     let mut packet_data = Vec::<u8>::new();
@@ -27,7 +46,7 @@ pub fn assemble_player_info_data(player: &Player) -> Vec<u8> {
     for c in player.username.as_bytes() {
         packet_data.push(*c);
     }
-    packet_data.push('\0' as u8);
+    packet_data.push(b'\0');
 
     // position
     let mut pos = bincode::serialize(&player.position).unwrap();
@@ -40,7 +59,7 @@ pub fn assemble_player_info_data(player: &Player) -> Vec<u8> {
     packet_data
 }
 
-pub fn assemble_chunk_contents_packet(col: &mut ChunkColumn) -> Vec<u8> {
+pub fn assemble_chunk_contents_packet(col: &mut ChunkColumn, encoding: ChunkEncoding) -> Vec<u8> {
     let mut packet_data = Vec::<u8>::new();
 
     packet_data.push(PacketType::ChunkContents as u8);
@@ -51,17 +70,250 @@ pub fn assemble_chunk_contents_packet(col: &mut ChunkColumn) -> Vec<u8> {
         packet_data.append(&mut pos);
 
         let compressed_data = chunk.compress();
+        let mut encoded = encode_sets(&compressed_data, encoding);
+        packet_data.append(&mut encoded);
+    }
+
+    // Clients older than block states read chunks up to the end of the packet
+    if encoding != ChunkEncoding::States {
+        return packet_data;
+    }
+
+    // block entities: [count] then [position in the column][size][entity] each, the entities
+    // are encoded as described by `EntityValue`
+    let entities = col.get_block_entities();
+    let mut count = bincode::serialize(&(entities.len() as u32)).unwrap();
+    packet_data.append(&mut count);
+    for (position, entity) in entities {
+        let mut pos = bincode::serialize(position).unwrap();
+        packet_data.append(&mut pos);
+        let mut encoded = entity.encode();
+        let mut size = bincode::serialize(&(encoded.len() as u32)).unwrap();
+        packet_data.append(&mut size);
+        packet_data.append(&mut encoded);
+    }
+
+    packet_data
+}
+
+pub fn assemble_chunk_unload_packet(columns: &[Vec2<i32>]) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::ChunkUnload as u8);
+
+    let mut count = bincode::serialize(&(columns.len() as u32)).unwrap();
+    packet_data.append(&mut count);
+    for column in columns {
+        let mut pos = bincode::serialize(column).unwrap();
+        packet_data.append(&mut pos);
+    }
+
+    packet_data
+}
+
+pub fn assemble_player_kick_packet(reason: &str) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::PlayerKick as u8);
+
+    // reason
+    packet_data.extend_from_slice(reason.as_bytes());
+    packet_data.push(b'\0');
+
+    packet_data
+}
+
+pub fn assemble_server_message_packet(message: &str) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::ServerMessage as u8);
+
+    // message
+    packet_data.extend_from_slice(message.as_bytes());
+    packet_data.push(b'\0');
+
+    packet_data
+}
+
+pub fn assemble_player_teleport_packet(position: &Vec3<f32>) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::PlayerTeleport as u8);
+
+    let mut pos = bincode::serialize(position).unwrap();
+    packet_data.append(&mut pos);
+
+    packet_data
+}
+
+/// Assembles a block update, the state is left out for clients that do not understand states
+pub fn assemble_block_update_packet(
+    position: &Vec3<i32>,
+    id: i32,
+    state: u16,
+    encoding: ChunkEncoding,
+) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::BlockUpdate as u8);
+
+    let mut pos = bincode::serialize(position).unwrap();
+    packet_data.append(&mut pos);
+    let mut id = bincode::serialize(&id).unwrap();
+    packet_data.append(&mut id);
+    if encoding == ChunkEncoding::States {
+        let mut state = bincode::serialize(&state).unwrap();
+        packet_data.append(&mut state);
+    }
 
-        for set in compressed_data {
-            let mut id = bincode::serialize(&set.id).unwrap();
-            packet_data.append(&mut id);
-            let mut count = bincode::serialize(&set.count).unwrap();
+    packet_data
+}
+
+/// Assembles a block entity update, an empty entity means it was removed
+///
+/// Only clients that understand block states are sent block entities
+pub fn assemble_block_entity_packet(position: &Vec3<i32>, entity: Option<&EntityValue>) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::BlockEntityData as u8);
+
+    let mut pos = bincode::serialize(position).unwrap();
+    packet_data.append(&mut pos);
+
+    // [size][entity encoded as described by `EntityValue`]
+    let mut encoded = entity.map_or(Vec::new(), |entity| entity.encode());
+    let mut size = bincode::serialize(&(encoded.len() as u32)).unwrap();
+    packet_data.append(&mut size);
+    packet_data.append(&mut encoded);
+
+    packet_data
+}
+
+pub fn assemble_biome_data_packet(x: i32, z: i32, biome: &Biome) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::BiomeData as u8);
+
+    let mut pos = bincode::serialize(&Vec2::new(x, z)).unwrap();
+    packet_data.append(&mut pos);
+
+    // name
+    packet_data.extend_from_slice(biome.name.as_bytes());
+    packet_data.push(b'\0');
+
+    packet_data
+}
+
+pub fn assemble_item_registry_packet(items: &ItemManager) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::ItemRegistry as u8);
+
+    // atlases: [count] then [path\0][width][height] each
+    let atlases = items.get_atlases();
+    let mut count = bincode::serialize(&(atlases.len() as u32)).unwrap();
+    packet_data.append(&mut count);
+    for atlas in atlases {
+        packet_data.extend_from_slice(atlas.path.as_bytes());
+        packet_data.push(b'\0');
+        let mut size = bincode::serialize(&(atlas.width, atlas.height)).unwrap();
+        packet_data.append(&mut size);
+    }
+
+    // items in id order: [count] then [name\0][type][transparent][in inventory][atlas, -1 if none]
+    // [top][bottom][north][south][east][west tex coords][model\0, empty if none]
+    // [hardness][light][max stack][solid][drop count]{[id][count]}[tag count]{[tag\0]}
+    // [state property count]{[name\0][value count]{[value\0]}} each
+    let item_manager = items;
+    let items = items.get_items();
+    let mut count = bincode::serialize(&(items.len() as u32)).unwrap();
+    packet_data.append(&mut count);
+    for item in items {
+        packet_data.extend_from_slice(item.name.as_bytes());
+        packet_data.push(b'\0');
+        packet_data.push(item.item_type as u8);
+        packet_data.push(item.is_transparent as u8);
+        packet_data.push(item.show_in_inventory as u8);
+        let atlas = item.atlas.map_or(-1, |atlas| atlas as i32);
+        let mut atlas = bincode::serialize(&atlas).unwrap();
+        packet_data.append(&mut atlas);
+        for face in Face::ALL {
+            let mut coords = bincode::serialize(item.get_texture(face)).unwrap();
+            packet_data.append(&mut coords);
+        }
+        packet_data.extend_from_slice(item.model.as_deref().unwrap_or("").as_bytes());
+        packet_data.push(b'\0');
+        let mut properties =
+            bincode::serialize(&(item.hardness, item.light, item.max_stack)).unwrap();
+        packet_data.append(&mut properties);
+        packet_data.push(item.is_solid as u8);
+
+        let mut count = bincode::serialize(&(item.drops.len() as u32)).unwrap();
+        packet_data.append(&mut count);
+        for drop in &item.drops {
+            // Drops are checked when the items are loaded
+            let id = item_manager.get_id_by_name(drop.name.clone()).unwrap_or(0);
+            let mut drop = bincode::serialize(&(id, drop.count)).unwrap();
+            packet_data.append(&mut drop);
+        }
+
+        let mut count = bincode::serialize(&(item.tags.len() as u32)).unwrap();
+        packet_data.append(&mut count);
+        for tag in &item.tags {
+            packet_data.extend_from_slice(tag.as_bytes());
+            packet_data.push(b'\0');
+        }
+
+        let mut count = bincode::serialize(&(item.states.len() as u32)).unwrap();
+        packet_data.append(&mut count);
+        for property in &item.states {
+            packet_data.extend_from_slice(property.name.as_bytes());
+            packet_data.push(b'\0');
+            let mut count = bincode::serialize(&(property.values.len() as u32)).unwrap();
             packet_data.append(&mut count);
+            for value in &property.values {
+                packet_data.extend_from_slice(value.as_bytes());
+                packet_data.push(b'\0');
+            }
         }
+    }
+
+    packet_data
+}
+
+pub fn assemble_asset_list_packet(assets: &AssetStore) -> Vec<u8> {
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::AssetList as u8);
 
-        let mut end_indicator = bincode::serialize(&(-1)).unwrap();
-        packet_data.append(&mut end_indicator);
+    // [count] then [name\0][hash][size] each
+    let count = assets.get_assets().count();
+    let mut count = bincode::serialize(&(count as u32)).unwrap();
+    packet_data.append(&mut count);
+    for asset in assets.get_assets() {
+        packet_data.extend_from_slice(asset.name.as_bytes());
+        packet_data.push(b'\0');
+        let mut info = bincode::serialize(&(asset.hash, asset.data.len() as u32)).unwrap();
+        packet_data.append(&mut info);
     }
 
     packet_data
 }
+
+/// Returns None if `offset` is past the end of `asset`
+pub fn assemble_asset_chunk_packet(asset: &Asset, offset: usize) -> Option<Vec<u8>> {
+    let chunk = asset.chunk(offset)?;
+    let mut packet_data = Vec::<u8>::new();
+
+    packet_data.push(PacketType::AssetChunk as u8);
+
+    // [hash][offset][size of the asset][name\0][data]
+    let header = (asset.hash, offset as u32, asset.data.len() as u32);
+    let mut header = bincode::serialize(&header).unwrap();
+    packet_data.append(&mut header);
+    packet_data.extend_from_slice(asset.name.as_bytes());
+    packet_data.push(b'\0');
+    packet_data.extend_from_slice(chunk);
+
+    Some(packet_data)
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::packets::PacketType;
+
+/// Number of violations a client may accumulate before being kicked
+const MAX_STRIKES: f32 = 20.0;
+/// Violations forgiven per second
+const STRIKE_DECAY_PER_SECOND: f32 = 1.0;
+
+/// Classic token bucket, refilled continuously up to `capacity`
+pub struct TokenBucket {
+    capacity: f32,
+    refill_per_second: f32,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket
+    pub fn new(capacity: f32, refill_per_second: f32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity,
+            refill_per_second,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token if one is available
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f32();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// What to do with a packet after checking it against the limits
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Drop,
+    Kick(String),
+}
+
+/// Per session packet limits with a strike counter for persistent offenders
+pub struct FloodGuard {
+    buckets: HashMap<u8, TokenBucket>,
+    strikes: TokenBucket,
+}
+
+impl FloodGuard {
+    pub fn new() -> FloodGuard {
+        FloodGuard {
+            buckets: HashMap::new(),
+            strikes: TokenBucket::new(MAX_STRIKES, STRIKE_DECAY_PER_SECOND, Instant::now()),
+        }
+    }
+
+    /// Burst size and sustained rate per second allowed for `packet_id`
+    fn packet_limit(packet_id: u8) -> (f32, f32) {
+        if packet_id == PacketType::PlayerInfoRequest as u8 {
+            (5.0, 1.0)
+        } else if packet_id == PacketType::PlayerInfoData as u8 {
+            (40.0, 20.0)
+        } else if packet_id == PacketType::ChunkRequest as u8 {
+            (128.0, 32.0)
+        } else if packet_id == PacketType::ChunkUpdate as u8 {
+            (20.0, 10.0)
+        } else if packet_id == PacketType::ChatMessage as u8 {
+            (5.0, 1.0)
+        } else if packet_id == PacketType::AssetRequest as u8 {
+            (64.0, 64.0)
+        } else {
+            (10.0, 5.0)
+        }
+    }
+
+    /// Checks a received packet of type `packet_id` against its rate limit
+    pub fn check_packet(&mut self, packet_id: u8, now: Instant) -> Verdict {
+        let bucket = self.buckets.entry(packet_id).or_insert_with(|| {
+            let (capacity, refill_per_second) = FloodGuard::packet_limit(packet_id);
+            TokenBucket::new(capacity, refill_per_second, now)
+        });
+
+        if bucket.try_take(now) {
+            Verdict::Allow
+        } else {
+            self.record_violation(&format!("Too many packets of type {}", packet_id), now)
+        }
+    }
+
+    /// Records misbehaviour, kicking once the client has done so persistently
+    pub fn record_violation(&mut self, reason: &str, now: Instant) -> Verdict {
+        if self.strikes.try_take(now) {
+            Verdict::Drop
+        } else {
+            Verdict::Kick(reason.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3.0, 2.0, start);
+
+        for _ in 0..3 {
+            assert!(bucket.try_take(start));
+        }
+        assert!(!bucket.try_take(start));
+
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+
+        // Never refills past capacity
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.try_take(later));
+        }
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn test_flood_guard_kicks_persistent_spam() {
+        let now = Instant::now();
+        let mut guard = FloodGuard::new();
+        let packet_id = PacketType::ChunkUpdate as u8;
+
+        let mut verdicts = Vec::new();
+        for _ in 0..100 {
+            verdicts.push(guard.check_packet(packet_id, now));
+        }
+
+        assert!(verdicts[..20].iter().all(|v| *v == Verdict::Allow));
+        assert!(verdicts[20..40].iter().all(|v| *v == Verdict::Drop));
+        assert!(matches!(verdicts[40], Verdict::Kick(_)));
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
 use std::path::Path;
@@ -6,17 +6,28 @@ use std::{fs, io};
 
 use anyhow::Result;
 
+use crate::items::{ItemManager, UNKNOWN_ITEM};
 use crate::player_data::Player;
 
 use crate::vector_types::{Vec2, Vec3};
+use crate::world::block_entity::MAX_ENTITY_SIZE;
 use crate::world::chunk_column::CompressedSet;
-use crate::world::{BlockToPlace, Chunk};
+use crate::world::chunk_encoding::{decode_sets, encode_sets, validate_sets};
+use crate::world::{BlockToPlace, Chunk, ChunkEncoding, EntityValue, GeneratorKind, WorldHeight};
 
 const DEFAULT_SCRIPT_SUBDIRECTORY: &str = "/default_scripts";
 const SAVE_FILE_NAME: &str = "worldData";
 const SAVE_FILE_EXTENSION: &str = "vbdat";
 const PLAYER_SAVE_SUBDIRECTORY: &str = "/players";
 const SCRIPT_SAVE_SUBDIRECTORY: &str = "/scripts";
+const MOD_SAVE_SUBDIRECTORY: &str = "/mods";
+/// Scripts run by the server itself, every other script in the directory handles events
+const ITEM_SCRIPT_FILE: &str = "loadAssetInfo.lua";
+const COLUMN_SCRIPT_FILE: &str = "generateChunkColumn.lua";
+const BIOME_SCRIPT_FILE: &str = "biomes.lua";
+const STRUCTURE_SCRIPT_FILE: &str = "structures.lua";
+const UNDERGROUND_SCRIPT_FILE: &str = "underground.lua";
+const EVENT_SCRIPT_FILE: &str = "events.lua";
 
 pub struct ChunkInfo {
     pub position: Vec3<i32>,
@@ -27,37 +38,51 @@ pub struct SaveFile {
     // save_directory does not contain trailing slashes, if None do not save
     pub save_directory: Option<String>,
     pub world_seed: i32,
+    pub world_height: WorldHeight,
+    /// Generator filling columns that are not in the save
+    pub generator: GeneratorKind,
     chunk_data: Vec<ChunkInfo>,
     block_to_place: Vec<BlockToPlace>,
+    /// Blocks neighbours spilled into generated columns, placed again when they are regenerated
+    spilled_blocks: Vec<BlockToPlace>,
+    /// Names of the items by the ids stored in the save, None for saves written before they were stored
+    item_names: Option<Vec<String>>,
+    /// Block entities of every saved column, by position relative to the column
+    block_entities: HashMap<Vec2<i32>, Vec<(Vec3<i32>, EntityValue)>>,
+    /// Saved columns changed by players, which are never regenerated
+    modified_columns: HashSet<Vec2<i32>>,
     players: HashMap<String, Player>,
 }
 
 impl SaveFile {
     pub fn new(directory: Option<String>) -> SaveFile {
-        match directory.clone() {
-            Some(dir) => {
-                assert!(!dir.is_empty(), "Empty save directory entered!");
-                assert!(
-                    !dir.contains('\\'),
-                    "Save directory may not contain \\ characters"
-                );
-                assert!(
-                    !dir.ends_with('/'),
-                    "Save directory may not end with / character"
-                );
-                match SaveFile::generate_save_structure(dir) {
-                    Ok(_) => (),
-                    Err(e) => panic!("Unable to generate save directory structure: {}", e),
-                }
+        if let Some(dir) = directory.clone() {
+            assert!(!dir.is_empty(), "Empty save directory entered!");
+            assert!(
+                !dir.contains('\\'),
+                "Save directory may not contain \\ characters"
+            );
+            assert!(
+                !dir.ends_with('/'),
+                "Save directory may not end with / character"
+            );
+            match SaveFile::generate_save_structure(dir) {
+                Ok(_) => (),
+                Err(e) => panic!("Unable to generate save directory structure: {}", e),
             }
-            None => (),
         }
 
         SaveFile {
             save_directory: directory,
             world_seed: rand::random(),
+            world_height: WorldHeight::default(),
+            generator: GeneratorKind::default(),
             chunk_data: Vec::<ChunkInfo>::new(),
             block_to_place: Vec::<BlockToPlace>::new(),
+            spilled_blocks: Vec::<BlockToPlace>::new(),
+            item_names: None,
+            block_entities: HashMap::new(),
+            modified_columns: HashSet::new(),
             players: HashMap::new(),
         }
     }
@@ -65,8 +90,16 @@ impl SaveFile {
     fn generate_save_structure(directory: String) -> io::Result<()> {
         fs::create_dir_all(format!("{}{}", directory, SCRIPT_SAVE_SUBDIRECTORY))?;
         fs::create_dir_all(format!("{}{}", directory, PLAYER_SAVE_SUBDIRECTORY))?;
+        fs::create_dir_all(format!("{}{}", directory, MOD_SAVE_SUBDIRECTORY))?;
 
-        let script_files = ["loadAssetInfo.lua", "generateChunkColumn.lua"];
+        let script_files = [
+            ITEM_SCRIPT_FILE,
+            COLUMN_SCRIPT_FILE,
+            BIOME_SCRIPT_FILE,
+            STRUCTURE_SCRIPT_FILE,
+            UNDERGROUND_SCRIPT_FILE,
+            EVENT_SCRIPT_FILE,
+        ];
 
         for script_file in script_files {
             let to_path_str = format!("{}{}/{}", directory, SCRIPT_SAVE_SUBDIRECTORY, script_file);
@@ -81,24 +114,51 @@ impl SaveFile {
         Ok(())
     }
 
-    pub fn get_script_path(&self, script_name: String) -> String {
+    /// Gets the directory the world's scripts are loaded from
+    pub fn get_script_directory(&self) -> String {
         match self.save_directory.clone() {
-            Some(directory) => format!(
-                "{}{}/{}.lua",
-                directory, SCRIPT_SAVE_SUBDIRECTORY, script_name
-            ),
-            None => format!(".{}/{}.lua", DEFAULT_SCRIPT_SUBDIRECTORY, script_name),
+            Some(directory) => format!("{}{}", directory, SCRIPT_SAVE_SUBDIRECTORY),
+            None => format!(".{}", DEFAULT_SCRIPT_SUBDIRECTORY),
         }
     }
 
-    pub fn get_chunk(&self, position: Vec3<i32>) -> Option<&ChunkInfo> {
-        for chunk in self.chunk_data.as_slice() {
-            if chunk.position == position {
-                return Some(chunk);
+    /// Gets the directory containing one subdirectory per mod, None if the world is not saved
+    pub fn get_mod_directory(&self) -> Option<String> {
+        self.save_directory
+            .as_ref()
+            .map(|directory| format!("{}{}", directory, MOD_SAVE_SUBDIRECTORY))
+    }
+
+    pub fn get_script_path(&self, script_name: String) -> String {
+        format!("{}/{}.lua", self.get_script_directory(), script_name)
+    }
+
+    /// Gets the paths of all event scripts in the script directory, sorted by name
+    pub fn get_event_script_paths(&self) -> io::Result<Vec<String>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(self.get_script_directory())? {
+            let path = entry?.path();
+            let is_event_script = path.extension().is_some_and(|extension| extension == "lua")
+                && path.file_name().is_some_and(|name| {
+                    name != ITEM_SCRIPT_FILE
+                        && name != COLUMN_SCRIPT_FILE
+                        && name != BIOME_SCRIPT_FILE
+                        && name != STRUCTURE_SCRIPT_FILE
+                        && name != UNDERGROUND_SCRIPT_FILE
+                });
+            if is_event_script {
+                paths.push(path.to_string_lossy().to_string());
             }
         }
+        paths.sort();
 
-        None
+        Ok(paths)
+    }
+
+    pub fn get_chunk(&self, position: Vec3<i32>) -> Option<&ChunkInfo> {
+        self.chunk_data
+            .iter()
+            .find(|chunk| chunk.position == position)
     }
 
     pub fn get_user_data(&mut self, username: &String) -> &mut Player {
@@ -136,6 +196,119 @@ impl SaveFile {
         self.chunk_data.push(data)
     }
 
+    /// Gets the saved block entities of the column at `column_position`
+    pub fn get_block_entities(&self, column_position: &Vec2<i32>) -> &[(Vec3<i32>, EntityValue)] {
+        self.block_entities
+            .get(column_position)
+            .map_or(&[], |entities| entities.as_slice())
+    }
+
+    /// Replaces the saved block entities of the column at `column_position`
+    pub fn save_block_entities(
+        &mut self,
+        column_position: Vec2<i32>,
+        entities: Vec<(Vec3<i32>, EntityValue)>,
+    ) {
+        if entities.is_empty() {
+            self.block_entities.remove(&column_position);
+        } else {
+            self.block_entities.insert(column_position, entities);
+        }
+    }
+
+    /// Returns whether the saved column at `column_position` was changed by players
+    pub fn is_column_modified(&self, column_position: &Vec2<i32>) -> bool {
+        self.modified_columns.contains(column_position)
+    }
+
+    /// Records whether the column at `column_position` was changed by players
+    pub fn set_column_modified(&mut self, column_position: Vec2<i32>, modified: bool) {
+        if modified {
+            self.modified_columns.insert(column_position);
+        } else {
+            self.modified_columns.remove(&column_position);
+        }
+    }
+
+    /// Takes the blocks waiting for their column to be generated, leaving none in the save
+    pub fn take_blocks_to_place(&mut self) -> Vec<BlockToPlace> {
+        std::mem::take(&mut self.block_to_place)
+    }
+
+    /// Replaces the blocks waiting for their column to be generated
+    pub fn set_blocks_to_place(&mut self, blocks: Vec<BlockToPlace>) {
+        self.block_to_place = blocks;
+    }
+
+    /// Takes the blocks spilled into generated columns, leaving none in the save
+    pub fn take_spilled_blocks(&mut self) -> Vec<BlockToPlace> {
+        std::mem::take(&mut self.spilled_blocks)
+    }
+
+    /// Replaces the blocks spilled into generated columns
+    pub fn set_spilled_blocks(&mut self, blocks: Vec<BlockToPlace>) {
+        self.spilled_blocks = blocks;
+    }
+
+    /// Records the names of the items by the ids the save is written with
+    pub fn set_item_names(&mut self, names: Vec<String>) {
+        self.item_names = Some(names);
+    }
+
+    /// Changes the ids stored in the save to the ids of the same items in `items`
+    ///
+    /// Blocks of items that no longer exist become the Unknown item, and states the items no longer
+    /// have become their default. Returns the names of the items that no longer exist
+    pub fn remap_item_ids(&mut self, items: &ItemManager) -> Vec<String> {
+        let saved_names = match self.item_names.replace(items.get_item_names()) {
+            Some(names) => names,
+            // Saves without names were written with the ids of the current items
+            None => return Vec::new(),
+        };
+
+        let unknown = items.get_id_by_name(UNKNOWN_ITEM.to_string()).unwrap_or(0);
+        let mut missing = Vec::new();
+        let ids: Vec<i32> = saved_names
+            .into_iter()
+            .map(|name| match items.get_id_by_name(name.clone()) {
+                Some(id) => id,
+                None => {
+                    missing.push(name);
+                    unknown
+                }
+            })
+            .collect();
+        let remap = |id: i32| {
+            usize::try_from(id)
+                .ok()
+                .and_then(|index| ids.get(index))
+                .copied()
+                .unwrap_or(unknown)
+        };
+
+        for chunk in &mut self.chunk_data {
+            for set in &mut chunk.data {
+                set.id = remap(set.id);
+                // States the item no longer has fall back to the default
+                let state_count = items
+                    .get_item_by_id(set.id)
+                    .map_or(1, |item| item.state_count());
+                if set.state as u32 >= state_count {
+                    set.state = 0;
+                }
+            }
+        }
+        for block in self
+            .block_to_place
+            .iter_mut()
+            .chain(self.spilled_blocks.iter_mut())
+        {
+            block.block_id = remap(block.block_id);
+        }
+
+        missing
+    }
+
     pub fn write_save(&self) -> Result<()> {
         if self.save_directory.is_none() {
             eprintln!("Save directory not provided, save will not be written");
@@ -162,7 +335,7 @@ impl SaveFile {
                 }
             };
             let binary_player = bincode::serialize(&player)?;
-            file.write(&binary_player)?;
+            file.write_all(&binary_player)?;
         }
 
         // World data
@@ -175,23 +348,62 @@ impl SaveFile {
         };
 
         // World seed
-        file.write(&bincode::serialize(&self.world_seed)?)?;
+        file.write_all(&bincode::serialize(&self.world_seed)?)?;
+
+        // World height
+        file.write_all(b"H")?;
+        file.write_all(&bincode::serialize(&self.world_height)?)?;
+
+        // World generator
+        file.write_all(b"G")?;
+        file.write_all(&[self.generator.id()])?;
+
+        // Item names by id
+        if let Some(names) = &self.item_names {
+            let encoded = bincode::serialize(names)?;
+            file.write_all(b"I")?;
+            file.write_all(&bincode::serialize(&(encoded.len() as u32))?)?;
+            file.write_all(&encoded)?;
+        }
 
-        // Compressed chunk data
+        // Compressed chunk data with block states
         for chunk in &self.chunk_data {
-            file.write(&[b'C'])?;
+            file.write_all(b"S")?;
             // Chunk Position
-            file.write(&bincode::serialize(&chunk.position)?)?;
-            file.write(&bincode::serialize(&(chunk.data.len() as u32))?)?;
-            for set in &chunk.data {
-                file.write(&bincode::serialize(&set)?)?;
+            file.write_all(&bincode::serialize(&chunk.position)?)?;
+            let encoded = encode_sets(&chunk.data, ChunkEncoding::States);
+            file.write_all(&bincode::serialize(&(encoded.len() as u32))?)?;
+            file.write_all(&encoded)?;
+        }
+
+        // Block entities
+        for (column_position, entities) in &self.block_entities {
+            for (position, entity) in entities {
+                file.write_all(b"E")?;
+                file.write_all(&bincode::serialize(column_position)?)?;
+                file.write_all(&bincode::serialize(position)?)?;
+                let encoded = entity.encode();
+                file.write_all(&bincode::serialize(&(encoded.len() as u32))?)?;
+                file.write_all(&encoded)?;
             }
         }
 
+        // Columns changed by players
+        let encoded = bincode::serialize(&self.modified_columns)?;
+        file.write_all(b"M")?;
+        file.write_all(&bincode::serialize(&(encoded.len() as u32))?)?;
+        file.write_all(&encoded)?;
+
         // Blocks to place
         for block in &self.block_to_place {
-            file.write(&[b'N'])?;
-            file.write(&bincode::serialize(&block)?)?;
+            file.write_all(b"N")?;
+            file.write_all(&bincode::serialize(&block)?)?;
+        }
+
+        // Blocks spilled into generated columns
+        for block in &self.spilled_blocks {
+            file.write_all(b"P")?;
+            file.write_all(&bincode::serialize(&block)?)?;
         }
 
         Ok(())
@@ -219,7 +431,7 @@ impl SaveFile {
 
                     let metadata = fs::metadata(&path).expect("unable to read metadata");
                     let mut buffer = vec![0; metadata.len() as usize];
-                    in_file.read(&mut buffer).expect("buffer overflow");
+                    in_file.read_exact(&mut buffer)?;
 
                     let new_player: Player = bincode::deserialize(&buffer)?;
                     self.players.insert(new_player.username.clone(), new_player);
@@ -229,13 +441,10 @@ impl SaveFile {
         }
 
         // Load world
-        let file = match File::open(format!(
+        let file = File::open(format!(
             "{}/{}.{}",
             directory_str, SAVE_FILE_NAME, SAVE_FILE_EXTENSION
-        )) {
-            Ok(file) => file,
-            Err(e) => return Err(e.into()),
-        };
+        ))?;
 
         let mut reader = BufReader::new(file);
 
@@ -243,18 +452,40 @@ impl SaveFile {
         reader.read_exact(&mut buffer)?;
         self.world_seed = bincode::deserialize(&buffer)?;
 
+        let mut has_modified_columns = false;
         loop {
             let mut buffer: [u8; 1] = [0; 1];
             if reader.read(&mut buffer)? == 0 {
                 break;
             }
-            if buffer[0] == b'C' {
+            if buffer[0] == b'H' {
+                let mut buffer: [u8; 8] = [0; 8];
+                reader.read_exact(&mut buffer)?;
+
+                let height: WorldHeight = bincode::deserialize(&buffer)?;
+                self.world_height = WorldHeight::new(height.min_chunk_y, height.max_chunk_y)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid world height {:?}", height))?;
+            } else if buffer[0] == b'G' {
+                let mut buffer: [u8; 1] = [0; 1];
+                reader.read_exact(&mut buffer)?;
+
+                self.generator = GeneratorKind::from_id(buffer[0])
+                    .ok_or_else(|| anyhow::anyhow!("Unknown world generator {}", buffer[0]))?;
+            } else if buffer[0] == b'I' {
+                let mut buffer: [u8; 4] = [0; 4];
+                reader.read_exact(&mut buffer)?;
+                let num_bytes: u32 = bincode::deserialize(&buffer)?;
+
+                let mut encoded = vec![0; num_bytes as usize];
+                reader.read_exact(&mut encoded)?;
+                self.item_names = Some(bincode::deserialize(&encoded)?);
+            } else if buffer[0] == b'C' {
                 let mut buffer: [u8; 12 + 4] = [0; 12 + 4];
                 reader.read_exact(&mut buffer)?;
 
                 let position: Vec3<i32> = bincode::deserialize(&buffer[..12])?;
                 let mut new_chunk = ChunkInfo {
-                    position: position,
+                    position,
                     data: Vec::new(),
                 };
 
@@ -265,20 +496,83 @@ impl SaveFile {
                     let mut buffer: [u8; 8] = [0; 8];
                     reader.read_exact(&mut buffer)?;
 
-                    new_chunk.data.push(bincode::deserialize(&buffer)?);
+                    let (id, count) = bincode::deserialize(&buffer)?;
+                    new_chunk.data.push(CompressedSet {
+                        id,
+                        count,
+                        state: 0,
+                    });
                 }
 
+                validate_sets(&new_chunk.data)?;
                 self.chunk_data.push(new_chunk);
+            } else if buffer[0] == b'V' || buffer[0] == b'S' {
+                // Chunks saved before block states were added are 'V' records
+                let encoding = match buffer[0] {
+                    b'S' => ChunkEncoding::States,
+                    _ => ChunkEncoding::Varint,
+                };
+                let mut buffer: [u8; 12 + 4] = [0; 12 + 4];
+                reader.read_exact(&mut buffer)?;
+
+                let position: Vec3<i32> = bincode::deserialize(&buffer[..12])?;
+                let num_bytes: u32 = bincode::deserialize(&buffer[12..])?;
+
+                let mut encoded = vec![0; num_bytes as usize];
+                reader.read_exact(&mut encoded)?;
+                let (data, _) = decode_sets(&encoded, encoding)?;
+
+                self.chunk_data.push(ChunkInfo { position, data });
+            } else if buffer[0] == b'E' {
+                let mut buffer: [u8; 8 + 12 + 4] = [0; 8 + 12 + 4];
+                reader.read_exact(&mut buffer)?;
+
+                let column_position: Vec2<i32> = bincode::deserialize(&buffer[..8])?;
+                let position: Vec3<i32> = bincode::deserialize(&buffer[8..20])?;
+                let num_bytes: u32 = bincode::deserialize(&buffer[20..])?;
+                if num_bytes as usize > MAX_ENTITY_SIZE {
+                    anyhow::bail!("Block entity of {} bytes is too large", num_bytes);
+                }
+
+                let mut encoded = vec![0; num_bytes as usize];
+                reader.read_exact(&mut encoded)?;
+                self.block_entities
+                    .entry(column_position)
+                    .or_default()
+                    .push((position, bincode::deserialize(&encoded)?));
+            } else if buffer[0] == b'M' {
+                let mut buffer: [u8; 4] = [0; 4];
+                reader.read_exact(&mut buffer)?;
+                let num_bytes: u32 = bincode::deserialize(&buffer)?;
+
+                let mut encoded = vec![0; num_bytes as usize];
+                reader.read_exact(&mut encoded)?;
+                self.modified_columns = bincode::deserialize(&encoded)?;
+                has_modified_columns = true;
             } else if buffer[0] == b'N' {
                 let mut buffer: [u8; 24] = [0; 24];
                 reader.read_exact(&mut buffer)?;
 
                 self.block_to_place.push(bincode::deserialize(&buffer)?);
+            } else if buffer[0] == b'P' {
+                let mut buffer: [u8; 24] = [0; 24];
+                reader.read_exact(&mut buffer)?;
+
+                self.spilled_blocks.push(bincode::deserialize(&buffer)?);
             } else {
-                panic!("Unknown save data type {}", buffer[0]);
+                anyhow::bail!("Unknown save data type {}", buffer[0]);
             }
         }
 
+        // Saves written before the modified columns were stored may contain player changes anywhere
+        if !has_modified_columns {
+            self.modified_columns = self
+                .chunk_data
+                .iter()
+                .map(|chunk| Vec2::new(chunk.position.x, chunk.position.z))
+                .collect();
+        }
+
         println!("Done Reading Save!");
 
         Ok(())
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, fs};
+
+use rlua::{Context, HookTriggers, Lua, StdLib};
+
+/// Number of instructions between checks of the script limits
+const HOOK_INSTRUCTION_INTERVAL: u32 = 1000;
+/// Time between checks of the watched script directory
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resource limits applied to user editable Lua scripts
+#[derive(Copy, Clone, Debug)]
+pub struct ScriptLimits {
+    /// Maximum number of Lua instructions a single script run may execute
+    pub instruction_limit: u64,
+    /// Maximum wall clock time a single script run may take
+    pub time_limit: Duration,
+    /// Maximum memory in bytes a Lua state may use
+    pub memory_limit: usize,
+    /// Loads the `io`, `os` and `package` libraries and file loading functions
+    pub allow_unsafe_libraries: bool,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        ScriptLimits {
+            instruction_limit: 50_000_000,
+            time_limit: Duration::from_secs(5),
+            memory_limit: 64 * 1024 * 1024,
+            allow_unsafe_libraries: false,
+        }
+    }
+}
+
+/// A failure while loading or running a Lua script
+#[derive(Debug)]
+pub struct ScriptError {
+    /// Path or name of the script that failed
+    pub script: String,
+    /// Line in `script` the error was raised from, if known
+    pub line: Option<u32>,
+    pub message: String,
+    /// Lua stack traceback, if one was captured
+    pub traceback: Option<String>,
+}
+
+impl ScriptError {
+    /// Creates an error for `script` that did not come from Lua (e.g. the file could not be read)
+    pub fn new(script: &str, message: String) -> ScriptError {
+        ScriptError {
+            script: script.to_string(),
+            line: None,
+            message,
+            traceback: None,
+        }
+    }
+
+    /// Extracts the location, message and traceback of a Lua error raised while running `script`
+    pub fn from_lua(script: &str, error: &rlua::Error) -> ScriptError {
+        let (message, traceback) = match error {
+            rlua::Error::RuntimeError(message) => match message.split_once("\nstack traceback:") {
+                Some((message, traceback)) => (
+                    message.to_string(),
+                    Some(format!("stack traceback:{}", traceback)),
+                ),
+                None => (message.clone(), None),
+            },
+            rlua::Error::SyntaxError { message, .. } => (message.clone(), None),
+            rlua::Error::CallbackError { traceback, cause } => {
+                let mut cause = cause.as_ref();
+                while let rlua::Error::CallbackError { cause: inner, .. } = cause {
+                    cause = inner.as_ref();
+                }
+                (cause.to_string(), Some(traceback.clone()))
+            }
+            _ => (error.to_string(), None),
+        };
+
+        // Prefer the location in the message, otherwise the innermost frame of the script
+        let line = ScriptError::parse_line(script, &message).or_else(|| {
+            traceback.as_ref().and_then(|traceback| {
+                traceback
+                    .lines()
+                    .find_map(|frame| ScriptError::parse_line(script, frame.trim_start()))
+            })
+        });
+
+        // Strip the location prefix, it is reported separately
+        let message = match line {
+            Some(line) => message
+                .strip_prefix(&format!("{}:{}: ", script, line))
+                .unwrap_or(&message)
+                .to_string(),
+            None => message,
+        };
+
+        ScriptError {
+            script: script.to_string(),
+            line,
+            message,
+            traceback,
+        }
+    }
+
+    /// Parses the line number from text starting with `script:line:`
+    fn parse_line(script: &str, text: &str) -> Option<u32> {
+        let rest = text.strip_prefix(script)?.strip_prefix(':')?;
+        let (line, _) = rest.split_once(':')?;
+        line.parse().ok()
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.script, line, self.message)?,
+            None => write!(f, "{}: {}", self.script, self.message)?,
+        }
+        if let Some(traceback) = &self.traceback {
+            write!(f, "\n{}", traceback)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Gets the chunk name that makes Lua report errors with the plain `path`
+pub fn chunk_name(path: &str) -> String {
+    format!("@{}", path)
+}
+
+/// Creates a Lua state restricted according to `limits`
+pub fn new_sandboxed_lua(limits: &ScriptLimits) -> Lua {
+    let lua = if limits.allow_unsafe_libraries {
+        Lua::new()
+    } else {
+        let lua = Lua::new_with(
+            StdLib::BASE
+                | StdLib::COROUTINE
+                | StdLib::TABLE
+                | StdLib::STRING
+                | StdLib::UTF8
+                | StdLib::MATH,
+        );
+        lua.context(|lua_ctx| {
+            let globals = lua_ctx.globals();
+            for name in ["dofile", "loadfile"] {
+                globals
+                    .set(name, rlua::Nil)
+                    .expect("Unable to remove unsafe Lua function");
+            }
+        });
+        lua
+    };
+
+    lua.set_memory_limit(Some(limits.memory_limit));
+
+    lua
+}
+
+/// Runs `f` in `lua`, aborting the script with an error once it exceeds the instruction or time limit
+pub fn run_limited<F, R>(lua: &Lua, limits: &ScriptLimits, f: F) -> rlua::Result<R>
+where
+    F: FnOnce(Context) -> rlua::Result<R>,
+{
+    let instruction_limit = limits.instruction_limit;
+    let time_limit = limits.time_limit;
+    let start = Instant::now();
+    let mut instructions: u64 = 0;
+
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(HOOK_INSTRUCTION_INTERVAL),
+            ..Default::default()
+        },
+        move |_, _| {
+            instructions += HOOK_INSTRUCTION_INTERVAL as u64;
+            if instructions > instruction_limit {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "script exceeded the limit of {} instructions",
+                    instruction_limit
+                )));
+            }
+            if start.elapsed() > time_limit {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "script exceeded the time limit of {:?}",
+                    time_limit
+                )));
+            }
+            Ok(())
+        },
+    );
+
+    let result = lua.context(f);
+    lua.remove_hook();
+
+    result
+}
+
+/// Polls a directory for added, removed or changed Lua scripts
+pub struct ScriptWatcher {
+    directory: String,
+    modified_times: HashMap<PathBuf, SystemTime>,
+    next_poll: Instant,
+}
+
+impl ScriptWatcher {
+    /// Starts watching `directory`, the scripts currently in it count as unchanged
+    pub fn new(directory: String, now: Instant) -> ScriptWatcher {
+        let modified_times = ScriptWatcher::scan(&directory);
+        ScriptWatcher {
+            directory,
+            modified_times,
+            next_poll: now + WATCH_POLL_INTERVAL,
+        }
+    }
+
+    /// Returns whether any script changed since the last poll
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + WATCH_POLL_INTERVAL;
+
+        let modified_times = ScriptWatcher::scan(&self.directory);
+        let changed = modified_times != self.modified_times;
+        self.modified_times = modified_times;
+        changed
+    }
+
+    /// Gets the modification time of every Lua script in `directory`
+    fn scan(directory: &str) -> HashMap<PathBuf, SystemTime> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return HashMap::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "lua"))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect()
+    }
+}
+
+/// Writes `script` to a temporary file and runs `load` with its path, used by the tests of the
+/// script loaders
+#[cfg(test)]
+pub fn with_script_file<T>(script: &str, load: impl FnOnce(&[String]) -> T) -> T {
+    let path =
+        std::env::temp_dir().join(format!("voxelbuilder_script_{}.lua", rand::random::<u32>()));
+    fs::write(&path, script).unwrap();
+    let path = path.to_string_lossy().to_string();
+    let loaded = load(std::slice::from_ref(&path));
+    fs::remove_file(path).ok();
+    loaded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_removes_unsafe_libraries() {
+        let limits = ScriptLimits::default();
+        let lua = new_sandboxed_lua(&limits);
+
+        let missing: bool = lua.context(|lua_ctx| {
+            lua_ctx
+                .load("return os == nil and io == nil and loadfile == nil and dofile == nil")
+                .eval()
+                .unwrap()
+        });
+        assert!(missing);
+    }
+
+    #[test]
+    fn test_instruction_limit_stops_infinite_loop() {
+        let limits = ScriptLimits {
+            instruction_limit: 100_000,
+            ..Default::default()
+        };
+        let lua = new_sandboxed_lua(&limits);
+
+        let result = run_limited(&lua, &limits, |lua_ctx| {
+            lua_ctx.load("while true do end").exec()
+        });
+        assert!(result.is_err());
+
+        // The hook is removed afterwards and the state is still usable
+        let result: rlua::Result<i32> =
+            run_limited(&lua, &limits, |lua_ctx| lua_ctx.load("return 1 + 2").eval());
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_script_error_location() {
+        let limits = ScriptLimits::default();
+        let lua = new_sandboxed_lua(&limits);
+        let script = "scripts/test.lua";
+
+        let run = |source: &str| {
+            let error = run_limited(&lua, &limits, |lua_ctx| {
+                let fail = lua_ctx.create_function(|_, (): ()| -> rlua::Result<()> {
+                    Err(rlua::Error::RuntimeError("unknown noise".to_string()))
+                })?;
+                lua_ctx.globals().set("fail", fail)?;
+                lua_ctx.load(source).set_name(&chunk_name(script))?.exec()
+            })
+            .unwrap_err();
+            ScriptError::from_lua(script, &error)
+        };
+
+        let error = run("local x = nil\nx.y = 1");
+        assert_eq!(error.line, Some(2));
+        assert!(error.message.starts_with("attempt to index a nil value"));
+        assert!(error.traceback.is_some());
+
+        let error = run("x = = 1");
+        assert_eq!(error.line, Some(1));
+        assert!(error.traceback.is_none());
+
+        let error = run("function a()\n  fail()\nend\n\na()");
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.message, "runtime error: unknown noise");
+        assert!(error
+            .to_string()
+            .starts_with("scripts/test.lua:2: runtime error: unknown noise"));
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let limits = ScriptLimits {
+            memory_limit: 4 * 1024 * 1024,
+            ..Default::default()
+        };
+        let lua = new_sandboxed_lua(&limits);
+
+        let result = run_limited(&lua, &limits, |lua_ctx| {
+            lua_ctx
+                .load("local t = {} for i = 1, 10000000 do t[i] = i end")
+                .exec()
+        });
+        assert!(matches!(result, Err(rlua::Error::MemoryError(_))));
+    }
+
+    #[test]
+    fn test_script_watcher_detects_new_script() {
+        let directory = std::env::temp_dir().join(format!(
+            "voxelbuilder_script_watcher_{}",
+            rand::random::<u32>()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        fs::remove_file(directory.join("new.lua")).ok();
+
+        let start = Instant::now();
+        let mut watcher = ScriptWatcher::new(directory.to_string_lossy().to_string(), start);
+        assert!(!watcher.poll(start + WATCH_POLL_INTERVAL));
+
+        fs::write(directory.join("new.lua"), "return 1").unwrap();
+        fs::write(directory.join("notes.txt"), "ignored").unwrap();
+
+        // Not polled again until the interval has passed
+        assert!(!watcher.poll(start + WATCH_POLL_INTERVAL));
+        assert!(watcher.poll(start + WATCH_POLL_INTERVAL * 2));
+        assert!(!watcher.poll(start + WATCH_POLL_INTERVAL * 3));
+
+        fs::remove_dir_all(directory).ok();
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use crate::rate_limit::FloodGuard;
+use crate::vector_types::{Vec2, Vec3};
+use crate::world::{ChunkEncoding, World};
+
+/// Columns that should be sent to or dropped by a client this tick
+pub struct StreamUpdate {
+    pub load: Vec<Vec2<i32>>,
+    pub unload: Vec<Vec2<i32>>,
+}
+
+/// Server side state of a connected client
+pub struct Session {
+    pub username: Option<String>,
+    /// Last position reported by the client in `PlayerInfoData`
+    pub position: Option<Vec3<f32>>,
+    /// Encoding of the chunks and block updates sent to the client, set from the protocol version
+    /// the client sends when it joins
+    pub chunk_encoding: ChunkEncoding,
+    loaded_columns: HashSet<Vec2<i32>>,
+    pub flood_guard: FloodGuard,
+}
+
+impl Session {
+    /// Creates a session for a client that has not identified itself yet
+    pub fn new() -> Session {
+        Session {
+            username: None,
+            position: None,
+            chunk_encoding: ChunkEncoding::Fixed,
+            loaded_columns: HashSet::new(),
+            flood_guard: FloodGuard::new(),
+        }
+    }
+
+    /// Records the name the client identified itself with
+    ///
+    /// Returns true the first time the client identifies itself, when it has joined the game
+    pub fn identify(&mut self, username: &str) -> bool {
+        let joined = self.username.is_none();
+        self.username = Some(username.to_string());
+        joined
+    }
+
+    /// Gets the column the client is currently standing in
+    pub fn column_position(&self) -> Option<Vec2<i32>> {
+        self.position.map(|position| {
+            World::world_to_column_position(&Vec2::new(
+                position.x.floor() as i32,
+                position.z.floor() as i32,
+            ))
+        })
+    }
+
+    /// Returns whether the column at `pos` is within `max_distance` columns of the client
+    pub fn is_column_in_reach(&self, pos: &Vec2<i32>, max_distance: i32) -> bool {
+        match self.column_position() {
+            Some(center) => {
+                let dx = (pos.x as i64 - center.x as i64).abs();
+                let dz = (pos.y as i64 - center.y as i64).abs();
+                dx <= max_distance as i64 && dz <= max_distance as i64
+            }
+            None => false,
+        }
+    }
+
+    /// Marks the column at `pos` as loaded by the client
+    pub fn mark_loaded(&mut self, pos: Vec2<i32>) {
+        self.loaded_columns.insert(pos);
+    }
+
+    /// Returns whether the client currently has the column at `pos` loaded
+    pub fn has_loaded(&self, pos: &Vec2<i32>) -> bool {
+        self.loaded_columns.contains(pos)
+    }
+
+    /// Works out which columns to push to the client and which it should unload
+    ///
+    /// At most `budget` columns are loaded per call, nearest to the client first
+    pub fn update_streaming(&mut self, view_distance: i32, budget: usize) -> StreamUpdate {
+        let center = match self.column_position() {
+            Some(center) => center,
+            None => {
+                return StreamUpdate {
+                    load: Vec::new(),
+                    unload: Vec::new(),
+                }
+            }
+        };
+
+        let wanted = columns_in_range(&center, view_distance);
+        let wanted_set: HashSet<Vec2<i32>> = wanted.iter().copied().collect();
+
+        let unload: Vec<Vec2<i32>> = self
+            .loaded_columns
+            .iter()
+            .filter(|pos| !wanted_set.contains(pos))
+            .copied()
+            .collect();
+        for pos in &unload {
+            self.loaded_columns.remove(pos);
+        }
+
+        let load: Vec<Vec2<i32>> = wanted
+            .into_iter()
+            .filter(|pos| !self.loaded_columns.contains(pos))
+            .take(budget)
+            .collect();
+        for pos in &load {
+            self.loaded_columns.insert(*pos);
+        }
+
+        StreamUpdate { load, unload }
+    }
+}
+
+/// Gets all columns within `view_distance` columns of `center`, nearest first
+pub fn columns_in_range(center: &Vec2<i32>, view_distance: i32) -> Vec<Vec2<i32>> {
+    let mut columns = Vec::new();
+    for dx in -view_distance..=view_distance {
+        for dz in -view_distance..=view_distance {
+            if dx * dx + dz * dz <= view_distance * view_distance {
+                columns.push(Vec2::new(center.x + dx, center.y + dz));
+            }
+        }
+    }
+
+    columns.sort_by_key(|pos| {
+        let dx = pos.x - center.x;
+        let dz = pos.y - center.y;
+        dx * dx + dz * dz
+    });
+
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_in_range_nearest_first() {
+        let center = Vec2::new(3, -2);
+        let columns = columns_in_range(&center, 4);
+
+        assert_eq!(columns[0], center);
+        let mut last = 0;
+        for pos in &columns {
+            let dist = (pos.x - center.x).pow(2) + (pos.y - center.y).pow(2);
+            assert!(dist >= last);
+            assert!(dist <= 16);
+            last = dist;
+        }
+        assert!(columns.contains(&Vec2::new(7, -2)));
+        assert!(!columns.contains(&Vec2::new(7, 2)));
+    }
+
+    #[test]
+    fn test_streaming_budget_and_unload() {
+        let mut session = Session::new();
+        assert!(session.update_streaming(2, 4).load.is_empty());
+
+        session.position = Some(Vec3::new(0.5, 80.0, 0.5));
+        let total = columns_in_range(&Vec2::new(0, 0), 2).len();
+
+        let mut loaded = 0;
+        loop {
+            let update = session.update_streaming(2, 4);
+            assert!(update.load.len() <= 4);
+            assert!(update.unload.is_empty());
+            if update.load.is_empty() {
+                break;
+            }
+            loaded += update.load.len();
+        }
+        assert_eq!(loaded, total);
+
+        // Teleport far away, every column leaves range
+        session.position = Some(Vec3::new(-1000.0, 80.0, 1000.0));
+        let update = session.update_streaming(2, 4);
+        assert_eq!(update.unload.len(), total);
+        assert_eq!(update.load[0], Vec2::new(-63, 62));
+        assert_eq!(update.load.len(), 4);
+    }
+}
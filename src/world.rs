@@ -1,18 +1,43 @@
 pub mod chunk_column;
-pub use chunk_column::{Chunk, ChunkColumn};
+pub use chunk_column::{Chunk, ChunkColumn, WorldHeight, CHUNK_SIZE};
+
+pub mod block_entity;
+pub use block_entity::EntityValue;
+
+pub mod error;
+pub use error::WorldError;
+
+pub mod chunk_encoding;
+pub use chunk_encoding::ChunkEncoding;
+
+pub mod noise;
+
+pub mod biome;
+pub use biome::{Biome, BiomeMap};
+
+pub mod generator;
+pub use generator::{GenerationContext, GeneratorKind, WorldGenerator};
+
+pub mod script_generator;
+
+pub mod structures;
+pub use structures::Structures;
+
+pub mod underground;
+pub use underground::Underground;
 
-use fast_noise_lite_rs::{FastNoiseLite, NoiseType};
-use rlua::Lua;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
-use std::fs;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::items::ItemManager;
+use crate::mods::ScriptSources;
 
 use crate::save_file::SaveFile;
+use crate::scripting::{ScriptError, ScriptLimits};
 use crate::vector_types::{Vec2, Vec3};
+use generator::FlatGenerator;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct BlockToPlace {
     pub column_position: Vec2<i32>,
     pub position_in_column: Vec3<i32>,
@@ -23,68 +48,106 @@ pub struct World {
     save_file: SaveFile,
     column_map: BTreeMap<i32, BTreeMap<i32, ChunkColumn>>,
     item_manager: ItemManager,
-    lua: Lua,
-    column_script: String,
-    noise_functions: HashMap<String, FastNoiseLite>,
+    generator: Box<dyn WorldGenerator>,
+    biomes: BiomeMap,
+    underground: Underground,
+    structures: Structures,
+    script_limits: ScriptLimits,
+    /// Blocks generated into columns that do not exist yet, placed once their column is generated
+    pending_blocks: HashMap<Vec2<i32>, Vec<BlockToPlace>>,
+    /// Blocks neighbours spilled into generated columns, placed again when they are regenerated
+    spilled_blocks: HashMap<Vec2<i32>, Vec<BlockToPlace>>,
+    /// Columns changed by players, which are never regenerated
+    modified_columns: HashSet<Vec2<i32>>,
 }
 
 impl World {
-    /// Creates a new world with no chunks
-    pub fn new(item_manager: ItemManager, save: SaveFile) -> World {
-        let seed = save.world_seed;
-        let mut noise_functions = HashMap::new();
-
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::OpenSimplex2);
-        noise_functions.insert("OpenSimplex2".to_string(), noise);
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::OpenSimplex2S);
-        noise_functions.insert("OpenSimplex2S".to_string(), noise);
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::Cellular);
-        noise_functions.insert("Cellular".to_string(), noise);
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::Perlin);
-        noise_functions.insert("Perlin".to_string(), noise);
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::ValueCubic);
-        noise_functions.insert("ValueCubic".to_string(), noise);
-        let mut noise = FastNoiseLite::new(seed);
-        noise.set_noise_type(NoiseType::Value);
-        noise_functions.insert("Value".to_string(), noise);
-
-        let column_script_path = save.get_script_path("generateChunkColumn".to_string());
-
-        World {
+    /// Creates a new world with no chunks, loading the items and generator from `sources`
+    ///
+    /// Item ids stored in `save` are changed to the ids of the loaded items with the same names
+    pub fn new(
+        mut save: SaveFile,
+        sources: &ScriptSources,
+        script_limits: ScriptLimits,
+    ) -> Result<World, ScriptError> {
+        let mut item_manager = ItemManager::from_scripts(&sources.item_scripts, &script_limits)?;
+        item_manager.add_unknown_item(None);
+        for name in save.remap_item_ids(&item_manager) {
+            eprintln!(
+                "Item {} in the save no longer exists, its blocks are replaced by Unknown",
+                name
+            );
+        }
+        let biomes = BiomeMap::load(
+            &sources.biome_scripts,
+            &item_manager,
+            save.world_seed,
+            &script_limits,
+        )?;
+        let underground = Underground::load(
+            &sources.underground_scripts,
+            &item_manager,
+            save.world_seed,
+            &script_limits,
+        )?;
+        let structures =
+            Structures::load(&sources.structure_scripts, &item_manager, &script_limits)?;
+        let generator =
+            save.generator
+                .create(&sources.column_script, save.world_seed, script_limits)?;
+
+        let mut pending_blocks: HashMap<Vec2<i32>, Vec<BlockToPlace>> = HashMap::new();
+        for block in save.take_blocks_to_place() {
+            pending_blocks
+                .entry(block.column_position)
+                .or_default()
+                .push(block);
+        }
+        let mut spilled_blocks: HashMap<Vec2<i32>, Vec<BlockToPlace>> = HashMap::new();
+        for block in save.take_spilled_blocks() {
+            spilled_blocks
+                .entry(block.column_position)
+                .or_default()
+                .push(block);
+        }
+
+        Ok(World {
             save_file: save,
             column_map: BTreeMap::new(),
             item_manager,
-            lua: Lua::new(),
-            column_script: fs::read_to_string(column_script_path)
-                .expect("Unable to load generateChunkColumn script"),
-            noise_functions,
-        }
+            generator,
+            biomes,
+            underground,
+            structures,
+            script_limits,
+            pending_blocks,
+            spilled_blocks,
+            modified_columns: HashSet::new(),
+        })
     }
 
     /// Generates a new column at the given position (`x`,`y`)
-    fn generate_column(&mut self, pos: &Vec2<i32>) {
-        let mut col = ChunkColumn::new(pos, 0);
-        let col_ptr = &mut col as *mut ChunkColumn;
+    ///
+    /// The saved contents of the column are used unless `from_save` is false
+    fn generate_column(&mut self, pos: &Vec2<i32>, from_save: bool) {
+        let world_height = self.get_height();
+        let mut col = ChunkColumn::new(pos, 0, world_height);
 
-        let mut was_saved = true;
+        let mut was_saved = from_save;
 
         // For each chunk in column
-        for height in 0..16 as u8 {
-            let saved_chunk =
-                self.save_file
-                    .get_chunk(Vec3::<i32>::new(pos.x, height as i32, pos.y));
+        for height in world_height.min_chunk_y..=world_height.max_chunk_y {
+            let saved_chunk = self
+                .save_file
+                .get_chunk(Vec3::<i32>::new(pos.x, height, pos.y))
+                .filter(|_| from_save);
             match saved_chunk {
                 Some(chunk_data) => {
-                    let chunk = col.get_chunk(height as u8);
+                    let chunk = col.get_chunk(height).unwrap();
                     let mut i = 0;
                     for set in chunk_data.data.as_slice() {
                         for _ in 0..set.count {
-                            chunk.set_block_i(i, set.id);
+                            chunk.set_block_i(i, set.id, set.state);
                             i += 1;
                         }
                     }
@@ -95,106 +158,177 @@ impl World {
             }
         }
 
-        struct ToPlaceAfter {
-            position: Vec3<i32>,
-            id: i32,
+        let mut spilled_blocks = Vec::new();
+        if !was_saved {
+            let context = GenerationContext::new(
+                *pos,
+                self.save_file.world_seed,
+                world_height,
+                &self.item_manager,
+                &self.biomes,
+                &self.column_map,
+            );
+            match self.generator.generate_column(&context, &mut col) {
+                Ok(spilled) => {
+                    // The stages after the terrain run in this order for every column
+                    spilled_blocks = spilled;
+                    self.underground.generate(&context, &mut col);
+                    spilled_blocks.extend(self.structures.place(&context, &mut col));
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Column generation failed for column {},{}, using a flat column instead: {}",
+                        pos.x, pos.y, e
+                    );
+                    col = ChunkColumn::new(pos, 0, world_height);
+                    FlatGenerator.generate_column(&context, &mut col).ok();
+                }
+            }
+        } else {
+            for (position, entity) in self.save_file.get_block_entities(pos) {
+                if let Err(e) = col.set_block_entity(position, Some(entity.clone())) {
+                    println!("Saved block entity was not loaded: {}", e);
+                }
+            }
+            if self.save_file.is_column_modified(pos) {
+                self.modified_columns.insert(*pos);
+            }
         }
 
-        let mut set_world_after_list = Vec::<ToPlaceAfter>::new();
-        let set_world_after_list_ptr = &mut set_world_after_list as *mut Vec<ToPlaceAfter>;
+        // Insert new col into map
+        self.column_map.entry(pos.x).or_default().insert(pos.y, col);
 
-        if !was_saved {
-            self.lua.context(|lua_ctx| {
-                let globals = lua_ctx.globals(); // Get globals from lua
-
-                lua_ctx.scope(|scope| {
-                    lua_ctx
-                        .load(&format!(
-                            r#"
-                            column_x = {}
-                            column_z = {}
-                        "#,
-                            pos.x, pos.y
-                        ))
-                        .set_name("Generate column variables")
-                        .unwrap()
-                        .exec()
-                        .expect("Generate column variables failed to load");
-
-                    let rust_random = scope
-                        .create_function(|_, (): ()| Ok(rand::random::<i32>()))
-                        .unwrap();
-                    globals.set("random", rust_random).unwrap();
-
-                    let get_id_by_name = scope
-                        .create_function_mut(|_, item_name: String| {
-                            let id = self.item_manager.get_id_by_name(item_name);
-                            Ok(id)
-                        })
-                        .unwrap();
-                    globals.set("get_id_by_name", get_id_by_name).unwrap();
-                    let get_noise_2d = scope
-                        .create_function(|_, (noise_type, x, y): (String, f32, f32)| {
-                            let noise = self.noise_functions.get(noise_type.as_str()).expect(
-                                format!("Noise function {} does not exist", noise_type.as_str())
-                                    .as_str(),
-                            );
-                            let noise_val = noise.get_noise_2d(x, y);
-                            Ok(noise_val)
-                        })
-                        .unwrap();
-                    globals.set("get_noise_2d", get_noise_2d).unwrap();
-
-                    let set_block = scope
-                        .create_function(|_, (x, y, z, id): (i32, i32, i32, i32)| {
-                            if x >= 0 && x < 16 && y >= 0 && y < 16 {
-                                unsafe {
-                                    (*col_ptr).set_block(&Vec3::new(x, y, z), id);
-                                }
-                            } else {
-                                let to_place = ToPlaceAfter {
-                                    position: Vec3::new(pos.x * 16 + x, y, pos.y * 16 + z),
-                                    id,
-                                };
-                                unsafe {
-                                    (*set_world_after_list_ptr).push(to_place);
-                                }
-                            }
-                            Ok(())
-                        })
-                        .unwrap();
-                    globals.set("set_block", set_block).unwrap();
-
-                    let set_layers = scope
-                        .create_function(|_, (lower, upper, id): (u32, u32, i32)| {
-                            unsafe {
-                                (*col_ptr).set_layers(lower, upper, id);
-                            }
-                            Ok(())
-                        })
-                        .unwrap();
-                    globals.set("set_layers", set_layers).unwrap();
-
-                    lua_ctx
-                        .load(&self.column_script)
-                        .set_name("Generate Chunk Column")
-                        .unwrap()
-                        .exec()
-                        .expect("Lua chunk generation script failed!");
-                });
-            });
+        // Blocks spilled into this column by neighbours generated before it
+        for to_place in self.pending_blocks.remove(pos).unwrap_or_default() {
+            self.place_spilled_block(to_place);
         }
 
-        // Insert new col into map
-        if !self.column_map.contains_key(&pos.x) {
-            self.column_map.insert(pos.x, BTreeMap::new());
+        // Blocks spilled into columns that do not exist yet wait for them instead of generating
+        // them, which would spill further
+        for to_place in spilled_blocks {
+            let column_position = World::world_to_column_position(&Vec2::new(
+                to_place.position.x,
+                to_place.position.z,
+            ));
+            let position_in_column = Vec3::new(
+                to_place.position.x.rem_euclid(CHUNK_SIZE),
+                to_place.position.y,
+                to_place.position.z.rem_euclid(CHUNK_SIZE),
+            );
+            let to_place = BlockToPlace {
+                column_position,
+                position_in_column,
+                block_id: to_place.id,
+            };
+            if self.does_column_exist(&column_position) {
+                self.place_spilled_block(to_place);
+            } else {
+                self.pending_blocks
+                    .entry(column_position)
+                    .or_default()
+                    .push(to_place);
+            }
         }
+    }
 
-        self.column_map.get_mut(&pos.x).unwrap().insert(pos.y, col);
+    /// Places a block spilled into an existing column, remembering it for when the column is
+    /// regenerated
+    fn place_spilled_block(&mut self, to_place: BlockToPlace) {
+        let column_position = to_place.column_position;
+        let position = Vec3::new(
+            column_position.x * CHUNK_SIZE + to_place.position_in_column.x,
+            to_place.position_in_column.y,
+            column_position.y * CHUNK_SIZE + to_place.position_in_column.z,
+        );
+        self.place_generated_block(&position, to_place.block_id);
 
-        for to_place in set_world_after_list.as_slice() {
-            self.set_block(&to_place.position, to_place.id);
+        // Only the last block spilled at a position is kept, like in the column
+        let spilled = self.spilled_blocks.entry(column_position).or_default();
+        spilled.retain(|block| block.position_in_column != to_place.position_in_column);
+        spilled.push(to_place);
+    }
+
+    /// Writes a block placed by the generator into an existing column
+    ///
+    /// Columns changed by players are left alone, so a structure crossing into one cannot destroy
+    /// what they built
+    fn place_generated_block(&mut self, position: &Vec3<i32>, id: i32) {
+        let column_position = World::world_to_column_position(&Vec2::new(position.x, position.z));
+        if self.is_column_modified(&column_position) {
+            return;
+        }
+        // Blocks outside the world height are clipped
+        match self.write_block(position, id, 0) {
+            Ok(()) | Err(WorldError::OutOfBounds(_)) => (),
+            Err(e) => println!("Generator could not place block: {}", e),
+        }
+    }
+
+    /// Re-reads the column generation, item, biome, underground and structure scripts from `sources`
+    ///
+    /// Nothing is replaced if a script fails or the reloaded items would change ids already in use
+    pub fn reload_scripts(&mut self, sources: &ScriptSources) -> Result<(), ScriptError> {
+        // The new generator is created up front so a syntax error keeps the old one running
+        let generator = self.save_file.generator.create(
+            &sources.column_script,
+            self.save_file.world_seed,
+            self.script_limits,
+        )?;
+
+        let mut item_manager =
+            ItemManager::from_scripts(&sources.item_scripts, &self.script_limits)?;
+        item_manager.add_unknown_item(Some(&self.item_manager));
+        self.item_manager
+            .validate_reload(&item_manager)
+            .map_err(|message| ScriptError::new("item scripts", message))?;
+        let biomes = BiomeMap::load(
+            &sources.biome_scripts,
+            &item_manager,
+            self.save_file.world_seed,
+            &self.script_limits,
+        )?;
+        let underground = Underground::load(
+            &sources.underground_scripts,
+            &item_manager,
+            self.save_file.world_seed,
+            &self.script_limits,
+        )?;
+        let structures = Structures::load(
+            &sources.structure_scripts,
+            &item_manager,
+            &self.script_limits,
+        )?;
+
+        // Noise created by the old script is dropped with it
+        self.generator = generator;
+        self.item_manager = item_manager;
+        self.biomes = biomes;
+        self.underground = underground;
+        self.structures = structures;
+
+        Ok(())
+    }
+
+    /// Generates the existing column at `pos` again with the current script
+    ///
+    /// Columns that were modified are kept, returns whether the column was regenerated. Blocks
+    /// that neighbours spilled into the column are placed again
+    pub fn regenerate_column(&mut self, pos: &Vec2<i32>) -> bool {
+        if !self.does_column_exist(pos) || self.is_column_modified(pos) {
+            return false;
         }
+
+        self.column_map.get_mut(&pos.x).unwrap().remove(&pos.y);
+        if let Some(spilled) = self.spilled_blocks.remove(pos) {
+            self.pending_blocks.entry(*pos).or_default().extend(spilled);
+        }
+        self.generate_column(pos, false);
+        true
+    }
+
+    /// Returns whether the column at `pos` was changed by players since it was first generated
+    pub fn is_column_modified(&self, pos: &Vec2<i32>) -> bool {
+        self.modified_columns.contains(pos)
     }
 
     /// Returns whether the column at `pos` exists
@@ -206,7 +340,7 @@ impl World {
     /// Gets the column at `pos` and generates the column if it doesn't exist
     pub fn get_column(&mut self, pos: &Vec2<i32>) -> &mut ChunkColumn {
         if !self.does_column_exist(pos) {
-            self.generate_column(pos);
+            self.generate_column(pos, true);
         }
 
         self.column_map
@@ -218,91 +352,161 @@ impl World {
 
     /// Translates absolute world position to absolute column position
     pub fn world_to_column_position(pos: &Vec2<i32>) -> Vec2<i32> {
-        let mut column_position = Vec2::new(pos.x / 16, pos.y / 16);
-
-        if pos.x < 0 && -pos.x % 16 != 0 {
-            column_position.x -= 1;
-        }
-        if pos.y < 0 && -pos.y % 16 != 0 {
-            column_position.y -= 1;
-        }
-
-        column_position
+        Vec2::new(pos.x.div_euclid(CHUNK_SIZE), pos.y.div_euclid(CHUNK_SIZE))
     }
 
     /// Translates absolute world position to absolute chunk position
     pub fn world_to_chunk_position(pos: &Vec3<i32>) -> Vec3<i32> {
-        let mut chunk_position = Vec3::new(pos.x / 16, pos.y / 16, pos.z / 16);
-
-        if pos.x < 0 && -pos.x % 16 != 0 {
-            chunk_position.x -= 1;
-        }
-        if pos.y < 0 && -pos.y % 16 != 0 {
-            chunk_position.y -= 1;
-        }
-        if pos.z < 0 && -pos.z % 16 != 0 {
-            chunk_position.z -= 1;
-        }
-
-        chunk_position
+        Vec3::new(
+            pos.x.div_euclid(CHUNK_SIZE),
+            pos.y.div_euclid(CHUNK_SIZE),
+            pos.z.div_euclid(CHUNK_SIZE),
+        )
     }
 
     /// Translates absolute world position into internal chunk position
     pub fn world_to_position_in_chunk(pos: &Vec3<i32>) -> Vec3<i32> {
-        let mut block_pos_in_chunk = Vec3::new(pos.x % 16, pos.y % 16, pos.z % 16);
-
-        if block_pos_in_chunk.x < 0 {
-            block_pos_in_chunk.x += 16;
-        }
-        if block_pos_in_chunk.y < 0 {
-            block_pos_in_chunk.y += 16;
-        }
-        if block_pos_in_chunk.z < 0 {
-            block_pos_in_chunk.z += 16;
-        }
+        Vec3::new(
+            pos.x.rem_euclid(CHUNK_SIZE),
+            pos.y.rem_euclid(CHUNK_SIZE),
+            pos.z.rem_euclid(CHUNK_SIZE),
+        )
+    }
 
-        block_pos_in_chunk
+    /// Converts a world position to a position with x and z relative to its column
+    fn world_to_position_in_column(pos: &Vec3<i32>) -> Vec3<i32> {
+        Vec3::new(
+            pos.x.rem_euclid(CHUNK_SIZE),
+            pos.y,
+            pos.z.rem_euclid(CHUNK_SIZE),
+        )
     }
 
-    /// Gets the block at `pos`
+    /// Gets the block at `pos`, or -1 if `pos` is outside the world height
     pub fn get_block(&mut self, position: &Vec3<i32>) -> i32 {
+        self.get_block_with_state(position).map_or(-1, |(id, _)| id)
+    }
+
+    /// Gets the block at `pos` and its state, None if `pos` is outside the world height
+    pub fn get_block_with_state(&mut self, position: &Vec3<i32>) -> Option<(i32, u16)> {
+        if !self.is_height_in_bounds(position.y) {
+            return None;
+        }
+
         let chunk_position = World::world_to_chunk_position(position);
         let block_position_in_chunk = World::world_to_position_in_chunk(position);
 
         let column = self.get_column(&Vec2::new(chunk_position.x, chunk_position.z));
-        if !(chunk_position.y >= 0 && chunk_position.y <= 15) {
-            return -1;
-        }
-
-        column.get_chunk(chunk_position.y as u8).get_block(
+        let chunk = column.get_chunk(chunk_position.y)?;
+        let (x, y, z) = (
             block_position_in_chunk.x as u8,
             block_position_in_chunk.y as u8,
             block_position_in_chunk.z as u8,
-        )
+        );
+        Some((chunk.get_block(x, y, z), chunk.get_state(x, y, z)))
     }
 
-    /// Sets the block at `pos` to `id`
-    pub fn set_block(&mut self, position: &Vec3<i32>, id: i32) {
-        if self.item_manager.get_item_by_id(id).is_none() {
-            println!("Tried to set block of unknown id {}", id);
-            return;
+    /// Sets the block at `pos` to `id` in its default state, marking its column as modified
+    pub fn set_block(&mut self, position: &Vec3<i32>, id: i32) -> Result<(), WorldError> {
+        self.set_block_with_state(position, id, 0)
+    }
+
+    /// Sets the block at `pos` to `id` with `state`, marking its column as modified
+    pub fn set_block_with_state(
+        &mut self,
+        position: &Vec3<i32>,
+        id: i32,
+        state: u16,
+    ) -> Result<(), WorldError> {
+        self.write_block(position, id, state)?;
+        self.modified_columns
+            .insert(World::world_to_column_position(&Vec2::new(
+                position.x, position.z,
+            )));
+
+        Ok(())
+    }
+
+    /// Sets the block at `pos` to `id` with `state` without marking the column as modified
+    fn write_block(&mut self, position: &Vec3<i32>, id: i32, state: u16) -> Result<(), WorldError> {
+        if !self.is_height_in_bounds(position.y) {
+            return Err(WorldError::OutOfBounds(*position));
+        }
+        let item = self
+            .item_manager
+            .get_item_by_id(id)
+            .ok_or(WorldError::UnknownBlock(id))?;
+        if state as u32 >= item.state_count() {
+            return Err(WorldError::InvalidState(id, state));
         }
 
         let chunk_position = World::world_to_chunk_position(position);
         let block_position_in_chunk = World::world_to_position_in_chunk(position);
+        let (x, y, z) = (
+            block_position_in_chunk.x as u8,
+            block_position_in_chunk.y as u8,
+            block_position_in_chunk.z as u8,
+        );
+
+        let column = self.get_column(&Vec2::new(chunk_position.x, chunk_position.z));
+        let chunk = column
+            .get_chunk(chunk_position.y)
+            .ok_or(WorldError::OutOfBounds(*position))?;
+        let replaced_id = chunk.get_block(x, y, z);
+        chunk.set_block_with_state(x, y, z, id, state);
 
-        if !self.does_column_exist(&Vec2::new(chunk_position.x, chunk_position.z)) {
-            self.generate_column(&Vec2::new(chunk_position.x, chunk_position.z));
+        // The entity belongs to the block, changing only the state keeps it
+        if replaced_id != id {
+            column.set_block_entity(&World::world_to_position_in_column(position), None)?;
         }
 
-        self.get_column(&Vec2::new(chunk_position.x, chunk_position.z))
-            .get_chunk(chunk_position.y as u8)
-            .set_block(
-                block_position_in_chunk.x as u8,
-                block_position_in_chunk.y as u8,
-                block_position_in_chunk.z as u8,
-                id,
-            );
+        Ok(())
+    }
+
+    /// Gets the block entity at `pos`, such as the contents of a chest
+    pub fn get_block_entity(&mut self, position: &Vec3<i32>) -> Option<&EntityValue> {
+        let column_position = World::world_to_column_position(&Vec2::new(position.x, position.z));
+        self.get_column(&column_position)
+            .get_block_entity(&World::world_to_position_in_column(position))
+    }
+
+    /// Sets the block entity at `pos`, None removes it, marking its column as modified
+    ///
+    /// The entity is removed when the block is replaced by another block
+    pub fn set_block_entity(
+        &mut self,
+        position: &Vec3<i32>,
+        entity: Option<EntityValue>,
+    ) -> Result<(), WorldError> {
+        if entity.is_some() && self.get_block(position) < 1 {
+            return Err(WorldError::EmptyBlock(*position));
+        }
+
+        let column_position = World::world_to_column_position(&Vec2::new(position.x, position.z));
+        self.get_column(&column_position)
+            .set_block_entity(&World::world_to_position_in_column(position), entity)?;
+        self.modified_columns.insert(column_position);
+
+        Ok(())
+    }
+
+    /// Gets the biome at world position `x`,`z`
+    pub fn get_biome(&self, x: i32, z: i32) -> &Biome {
+        self.biomes.get_biome(x, z)
+    }
+
+    /// Returns whether blocks can exist at height `y`
+    pub fn is_height_in_bounds(&self, y: i32) -> bool {
+        self.get_height().contains_block_y(y)
+    }
+
+    /// Gets the vertical range of every column in the world
+    pub fn get_height(&self) -> WorldHeight {
+        self.save_file.world_height
+    }
+
+    pub fn get_item_manager(&self) -> &ItemManager {
+        &self.item_manager
     }
 
     pub fn get_save_file(&mut self) -> &mut SaveFile {
@@ -315,15 +519,32 @@ impl World {
         }
 
         println!("Saving world data");
-        for column_x in self.column_map.values() {
-            for column_z in column_x.values() {
+        for (x, column_x) in &self.column_map {
+            for (z, column_z) in column_x {
+                let column_position = Vec2::new(*x, *z);
                 for chunk in column_z.get_chunks() {
                     self.save_file.save_chunk_data(chunk);
                 }
+                let entities = column_z
+                    .get_block_entities()
+                    .iter()
+                    .map(|(position, entity)| (*position, entity.clone()))
+                    .collect();
+                self.save_file
+                    .save_block_entities(column_position, entities);
+                self.save_file.set_column_modified(
+                    column_position,
+                    self.modified_columns.contains(&column_position),
+                );
             }
         }
 
-        // TODO: Save block_to_place
+        let pending_blocks = self.pending_blocks.values().flatten().cloned().collect();
+        self.save_file.set_blocks_to_place(pending_blocks);
+        let spilled_blocks = self.spilled_blocks.values().flatten().cloned().collect();
+        self.save_file.set_spilled_blocks(spilled_blocks);
+        self.save_file
+            .set_item_names(self.item_manager.get_item_names());
 
         println!("Writing save file");
         match self.save_file.write_save() {
@@ -336,6 +557,57 @@ impl World {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scripting::with_script_file;
+    use proptest::prelude::*;
+    use script_generator::ScriptGenerator;
+    use std::fs;
+
+    fn test_world() -> World {
+        let save = SaveFile::new(None);
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        World::new(save, &sources, ScriptLimits::default()).unwrap()
+    }
+
+    /// Loads the world saved in `directory`, or creates it with a fixed seed
+    fn load_saved_world(directory: &str) -> World {
+        let mut save = SaveFile::new(Some(directory.to_string()));
+        if save.load().is_err() {
+            save.world_seed = 1234;
+        }
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        World::new(save, &sources, ScriptLimits::default()).unwrap()
+    }
+
+    fn set_column_script(world: &mut World, script: &str) {
+        let generator = ScriptGenerator::from_source(
+            "test generator",
+            script.to_string(),
+            world.save_file.world_seed,
+            world.script_limits,
+        );
+        world.generator = Box::new(generator.unwrap());
+        // Only the blocks placed by the script are expected
+        let seed = world.save_file.world_seed;
+        let underground = Underground::load(&[], &world.item_manager, seed, &world.script_limits);
+        world.underground = underground.unwrap();
+        world.structures =
+            Structures::load(&[], &world.item_manager, &world.script_limits).unwrap();
+    }
+
+    fn set_structure_script(world: &mut World, script: &str) {
+        let structures = with_script_file(script, |paths| {
+            Structures::load(paths, &world.item_manager, &world.script_limits)
+        });
+        world.structures = structures.unwrap();
+    }
+
+    fn set_underground_script(world: &mut World, script: &str) {
+        let seed = world.save_file.world_seed;
+        let underground = with_script_file(script, |paths| {
+            Underground::load(paths, &world.item_manager, seed, &world.script_limits)
+        });
+        world.underground = underground.unwrap();
+    }
 
     #[test]
     fn test_world_to_column_position() {
@@ -445,4 +717,677 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_generation_timeout_falls_back_to_flat_column() {
+        let mut world = test_world();
+        world.script_limits.instruction_limit = 100_000;
+        set_column_script(&mut world, "while true do end");
+
+        let grass_id = world
+            .get_item_manager()
+            .get_id_by_name("Grass".to_string())
+            .unwrap();
+        assert_eq!(world.get_block(&Vec3::new(3, 64, 5)), grass_id);
+        assert_eq!(world.get_block(&Vec3::new(3, 65, 5)), 0);
+    }
+
+    #[test]
+    fn test_unknown_noise_is_a_script_error() {
+        let mut world = test_world();
+        set_column_script(&mut world, "get_noise_2d(\"Nope\", 0, 0)");
+
+        // The failing column is replaced instead of crashing the server
+        assert_eq!(world.get_block(&Vec3::new(0, 65, 0)), 0);
+        assert!(world.does_column_exist(&Vec2::new(0, 0)));
+    }
+
+    #[test]
+    fn test_generator_reads_blocks() {
+        let mut world = test_world();
+        set_column_script(
+            &mut world,
+            r#"
+            stone_id = get_id_by_name("Stone")
+            rose_id = get_id_by_name("Rose")
+            set_layers(min_build_height, 60, stone_id)
+            set_block(3, 61, 3, get_id_by_name("Grass"))
+
+            -- Flowers only on grass
+            for x = 0, 15 do
+                for z = 0, 15 do
+                    local top = get_height(x, z)
+                    if get_block(x, top, z) ~= stone_id then
+                        set_block(x, top + 1, z, rose_id)
+                    end
+                end
+            end
+
+            -- Mark whether the western neighbour was already generated
+            if get_height(-1, 0) == 60 and get_block(-16, 0, 15) == stone_id then
+                set_block(0, 100, 0, stone_id)
+            end
+            if get_block(16, 0, 0) == nil and get_height(0, 16) == nil
+                and get_block(0, max_build_height, 0) == nil then
+                set_block(0, 101, 0, stone_id)
+            end
+        "#,
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let rose = world.get_item_manager().get_id_by_name("Rose".to_string());
+
+        assert_eq!(world.get_block(&Vec3::new(3, 62, 3)), rose.unwrap());
+        assert_eq!(world.get_block(&Vec3::new(4, 61, 3)), 0);
+        assert_eq!(world.get_block(&Vec3::new(0, 100, 0)), 0);
+        assert_eq!(world.get_block(&Vec3::new(0, 101, 0)), stone.unwrap());
+
+        assert_eq!(world.get_block(&Vec3::new(16, 100, 0)), stone.unwrap());
+    }
+
+    #[test]
+    fn test_generator_noise_api() {
+        let mut world = test_world();
+        set_column_script(
+            &mut world,
+            r#"
+            create_noise("caves", { type = "Perlin", seed_offset = 1, frequency = 0.05,
+                fractal = "Ridged", octaves = 4, warp = { amplitude = 10 } })
+            local a = get_noise_3d("caves", column_x * 16, 10, column_z * 16)
+            local b = get_noise_3d("caves", column_x * 16, 10, column_z * 16)
+            if a == b and a >= -1.5 and a <= 1.5 and get_noise_2d("caves", 1, 2) ~= nil then
+                set_block(0, 100, 0, get_id_by_name("Stone"))
+            end
+        "#,
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        assert_eq!(world.get_block(&Vec3::new(0, 100, 0)), stone.unwrap());
+
+        // Invalid settings fail the script instead of the server
+        set_column_script(&mut world, "create_noise(\"bad\", { octaves = 99 })");
+        assert_eq!(world.get_block(&Vec3::new(16, 100, 0)), 0);
+        set_column_script(&mut world, "get_noise_3d(\"missing\", 0, 0, 0)");
+        assert_eq!(world.get_block(&Vec3::new(32, 100, 0)), 0);
+        assert!(world.does_column_exist(&Vec2::new(2, 0)));
+    }
+
+    #[test]
+    fn test_generator_biome_api() {
+        let mut world = test_world();
+        set_column_script(
+            &mut world,
+            r#"
+            local biome = get_biome(3, 5)
+            local height = get_surface_height(3, 5)
+            set_block(3, height, 5, biome.surface)
+        "#,
+        );
+
+        let surface_block = world.get_biome(3, 5).surface_block;
+        let height = world.biomes.surface_height(3, 5);
+        assert_eq!(world.get_block(&Vec3::new(3, height, 5)), surface_block);
+    }
+
+    #[test]
+    fn test_reload_and_regenerate_unmodified_columns() {
+        let mut world = test_world();
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let stone = stone.unwrap();
+
+        world.get_column(&Vec2::new(0, 0));
+        world.get_column(&Vec2::new(1, 0));
+        world.set_block(&Vec3::new(16, 100, 0), stone).unwrap();
+        assert!(world.is_column_modified(&Vec2::new(1, 0)));
+
+        let sources = ScriptSources::new(&SaveFile::new(None), &ScriptLimits::default());
+        world.reload_scripts(&sources.unwrap()).unwrap();
+        set_column_script(
+            &mut world,
+            "set_layers(min_build_height, max_build_height - 1, 0)",
+        );
+
+        assert!(world.regenerate_column(&Vec2::new(0, 0)));
+        assert_eq!(world.get_block(&Vec3::new(0, 1, 0)), 0);
+
+        // Player changes are never thrown away
+        assert!(!world.regenerate_column(&Vec2::new(1, 0)));
+        assert_eq!(world.get_block(&Vec3::new(16, 100, 0)), stone);
+
+        // Columns that were never generated are left to the normal path
+        assert!(!world.regenerate_column(&Vec2::new(5, 5)));
+    }
+
+    #[test]
+    fn test_script_random_is_seeded_per_column() {
+        let mut world = test_world();
+        set_column_script(
+            &mut world,
+            r#"
+            stone_id = get_id_by_name("Stone")
+            for x = 0, 15 do
+                for z = 0, 15 do
+                    if random() % 2 == 0 then
+                        set_block(x, 100, z, stone_id)
+                    end
+                end
+            end
+        "#,
+        );
+        let layer = |world: &mut World, col_x: i32| {
+            let mut blocks = Vec::new();
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    blocks.push(world.get_block(&Vec3::new(col_x * CHUNK_SIZE + x, 100, z)));
+                }
+            }
+            blocks
+        };
+
+        let first = layer(&mut world, 0);
+        let second = layer(&mut world, 1);
+        assert_ne!(first, second);
+        assert!(world.regenerate_column(&Vec2::new(0, 0)));
+        assert!(world.regenerate_column(&Vec2::new(1, 0)));
+        assert_eq!(layer(&mut world, 0), first);
+        assert_eq!(layer(&mut world, 1), second);
+    }
+
+    #[test]
+    fn test_saved_columns_keep_their_modified_flag() {
+        let directory =
+            std::env::temp_dir().join(format!("voxelbuilder_modified_{}", rand::random::<u32>()));
+        let directory = directory.to_string_lossy().to_string();
+
+        let mut world = load_saved_world(&directory);
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let stone = stone.unwrap();
+        world.get_column(&Vec2::new(0, 0));
+        world.get_column(&Vec2::new(2, 0));
+        world.set_block(&Vec3::new(16, 100, 0), stone).unwrap();
+        world.save_to_file();
+
+        // Only the column a player changed is kept after a restart, including columns that
+        // were saved without being loaded again
+        let mut world = load_saved_world(&directory);
+        world.get_column(&Vec2::new(0, 0));
+        world.get_column(&Vec2::new(1, 0));
+        world.save_to_file();
+
+        let mut world = load_saved_world(&directory);
+        for x in 0..3 {
+            world.get_column(&Vec2::new(x, 0));
+        }
+        set_column_script(
+            &mut world,
+            "set_layers(min_build_height, max_build_height - 1, 0)",
+        );
+        assert!(world.regenerate_column(&Vec2::new(0, 0)));
+        assert_eq!(world.get_block(&Vec3::new(0, 1, 0)), 0);
+        assert!(world.regenerate_column(&Vec2::new(2, 0)));
+        assert!(!world.regenerate_column(&Vec2::new(1, 0)));
+        assert_eq!(world.get_block(&Vec3::new(16, 100, 0)), stone);
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn test_reload_keeps_script_on_syntax_error() {
+        let mut world = test_world();
+        set_column_script(
+            &mut world,
+            "set_block(0, 100, 0, get_id_by_name(\"Stone\"))",
+        );
+
+        let mut sources =
+            ScriptSources::new(&SaveFile::new(None), &ScriptLimits::default()).unwrap();
+        let error = with_script_file("set_block(0, 0, 0,", |paths| {
+            sources.column_script = paths[0].clone();
+            world.reload_scripts(&sources).unwrap_err()
+        });
+        assert_eq!(error.line, Some(1));
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        assert_eq!(world.get_block(&Vec3::new(0, 100, 0)), stone.unwrap());
+    }
+
+    #[test]
+    fn test_native_generators() {
+        let mut world = test_world();
+        // Structures would grow out of the flat surface
+        set_structure_script(&mut world, "");
+        world.generator = Box::new(generator::VoidGenerator);
+        assert_eq!(world.get_column(&Vec2::new(0, 0)).get_height(5, 5), None);
+
+        world.generator = Box::new(FlatGenerator);
+        assert_eq!(
+            world.get_column(&Vec2::new(1, 0)).get_height(5, 5),
+            Some(64)
+        );
+
+        // The same seed always generates the same terrain, caves, ores and trees included
+        let mut other_world = test_world();
+        let sources = ScriptSources::new(&SaveFile::new(None), &ScriptLimits::default()).unwrap();
+        for world in [&mut world, &mut other_world] {
+            world.save_file.world_seed = 1234;
+            let biomes = BiomeMap::load(
+                &sources.biome_scripts,
+                &world.item_manager,
+                1234,
+                &world.script_limits,
+            );
+            world.biomes = biomes.unwrap();
+            let underground = Underground::load(
+                &sources.underground_scripts,
+                &world.item_manager,
+                1234,
+                &world.script_limits,
+            );
+            world.underground = underground.unwrap();
+            let structures = Structures::load(
+                &sources.structure_scripts,
+                &world.item_manager,
+                &world.script_limits,
+            );
+            world.structures = structures.unwrap();
+            world.generator = Box::new(generator::TerrainGenerator);
+        }
+        let bedrock = world
+            .get_item_manager()
+            .get_id_by_name("Bedrock".to_string());
+        for x in 10..14 {
+            for z in 10..14 {
+                let pos = Vec2::new(x, z);
+                let compress = |world: &mut World| -> Vec<u8> {
+                    let chunks = world.get_column(&pos).get_chunks();
+                    let sets: Vec<_> = chunks.iter().flat_map(|chunk| chunk.compress()).collect();
+                    bincode::serialize(&sets).unwrap()
+                };
+                assert_eq!(compress(&mut world), compress(&mut other_world));
+            }
+        }
+        for x in 0..16 {
+            for z in 0..16 {
+                let surface = world.get_column(&Vec2::new(11, 11)).get_height(x, z);
+                assert!((60..100).contains(&surface.unwrap()));
+                assert_eq!(
+                    world.get_block(&Vec3::new(176 + x, 0, 176 + z)),
+                    bedrock.unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_structures_wait_for_neighbour_columns() {
+        let mut world = test_world();
+        world.generator = Box::new(FlatGenerator);
+        // With the spacing just fitting the structure its origins are at x and z 8, 25, 42...
+        set_structure_script(
+            &mut world,
+            r#"
+            register_structure({ name = "wall", spacing = 17,
+                blocks = { { -8, 0, 0, "Stone" }, { 0, 0, 0, "Stone" }, { 8, 0, 0, "Stone" } } })
+        "#,
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let stone = stone.unwrap();
+
+        world.get_column(&Vec2::new(0, 0));
+        assert_eq!(world.get_block(&Vec3::new(0, 65, 8)), stone);
+        assert_eq!(world.get_block(&Vec3::new(8, 65, 8)), stone);
+        assert_eq!(world.get_block(&Vec3::new(8, 65, 9)), 0);
+        assert!(!world.does_column_exist(&Vec2::new(1, 0)));
+        assert_eq!(world.pending_blocks[&Vec2::new(1, 0)].len(), 1);
+
+        // The queue survives saving, the block is placed once its column is generated
+        let mut save = SaveFile::new(None);
+        save.set_blocks_to_place(world.pending_blocks.values().flatten().cloned().collect());
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        let mut world = World::new(save, &sources, ScriptLimits::default()).unwrap();
+        set_structure_script(&mut world, "");
+        world.generator = Box::new(FlatGenerator);
+        assert_eq!(world.get_block(&Vec3::new(16, 65, 8)), stone);
+        assert_eq!(world.get_block(&Vec3::new(17, 65, 8)), 0);
+        assert!(world.pending_blocks.is_empty());
+    }
+
+    #[test]
+    fn test_regenerated_columns_keep_spilled_blocks() {
+        let mut world = test_world();
+        world.generator = Box::new(FlatGenerator);
+        set_structure_script(
+            &mut world,
+            r#"
+            register_structure({ name = "wall", spacing = 17,
+                blocks = { { -8, 0, 0, "Stone" }, { 0, 0, 0, "Stone" }, { 8, 0, 0, "Stone" } } })
+        "#,
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let stone = stone.unwrap();
+
+        // The wall of column 0,0 ends in the column next to it, which is generated first
+        let spilled = Vec3::new(16, 65, 8);
+        world.get_column(&Vec2::new(1, 0));
+        assert_eq!(world.get_block(&spilled), 0);
+        world.get_column(&Vec2::new(0, 0));
+        assert_eq!(world.get_block(&spilled), stone);
+
+        assert!(world.regenerate_column(&Vec2::new(1, 0)));
+        assert_eq!(world.get_block(&spilled), stone);
+        assert_eq!(world.get_block(&Vec3::new(17, 65, 8)), stone);
+
+        // The spilled blocks survive saving
+        let mut save = SaveFile::new(None);
+        save.set_spilled_blocks(world.spilled_blocks.values().flatten().cloned().collect());
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        let mut world = World::new(save, &sources, ScriptLimits::default()).unwrap();
+        set_structure_script(&mut world, "");
+        world.generator = Box::new(FlatGenerator);
+        world.get_column(&Vec2::new(1, 0));
+        assert_eq!(world.get_block(&spilled), 0);
+        assert!(world.regenerate_column(&Vec2::new(1, 0)));
+        assert_eq!(world.get_block(&spilled), stone);
+    }
+
+    #[test]
+    fn test_structures_keep_out_of_modified_columns() {
+        let mut world = test_world();
+        world.generator = Box::new(FlatGenerator);
+        set_structure_script(&mut world, "");
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let dirt = world.get_item_manager().get_id_by_name("Dirt".to_string());
+        let (stone, dirt) = (stone.unwrap(), dirt.unwrap());
+
+        // The walls starting in column 0,0 and 0,1 end in the columns east of them
+        world.get_column(&Vec2::new(1, 0));
+        world.get_column(&Vec2::new(1, 1));
+        world.set_block(&Vec3::new(16, 65, 8), dirt).unwrap();
+        set_structure_script(
+            &mut world,
+            r#"
+            register_structure({ name = "wall", spacing = 17,
+                blocks = { { -8, 0, 0, "Stone" }, { 0, 0, 0, "Stone" }, { 8, 0, 0, "Stone" } } })
+        "#,
+        );
+
+        world.get_column(&Vec2::new(0, 0));
+        world.get_column(&Vec2::new(0, 1));
+        assert_eq!(world.get_block(&Vec3::new(8, 65, 8)), stone);
+        assert_eq!(world.get_block(&Vec3::new(16, 65, 8)), dirt);
+        assert_eq!(world.get_block(&Vec3::new(16, 65, 25)), stone);
+    }
+
+    #[test]
+    fn test_structures_do_not_overlap() {
+        let mut world = test_world();
+        world.generator = Box::new(FlatGenerator);
+        set_structure_script(
+            &mut world,
+            r#"
+            register_structure({ name = "big", radius = 1, generate = function(structure)
+                structure.place(0, 0, 0, get_id_by_name("Stone"))
+            end })
+            register_structure({ name = "small", blocks = { { 0, 0, 0, "Glass" } } })
+        "#,
+        );
+        let stone = world.get_item_manager().get_id_by_name("Stone".to_string());
+        let glass = world.get_item_manager().get_id_by_name("Glass".to_string());
+
+        // Every position is within reach of an instance of the first structure
+        let column = world.get_column(&Vec2::new(0, 0));
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let top = column.get_block(&Vec3::new(x, 65, z)).unwrap();
+                assert_ne!(top, glass.unwrap());
+                let is_origin = x % 3 == 1 && z % 3 == 1;
+                assert_eq!(top == stone.unwrap(), is_origin);
+            }
+        }
+    }
+
+    #[test]
+    fn test_caves_and_ores() {
+        let mut world = test_world();
+        world.generator = Box::new(FlatGenerator);
+        set_structure_script(&mut world, "");
+        // The roses only replace glass, so they only exist if the ores run in order
+        set_underground_script(
+            &mut world,
+            r#"
+            set_caves({ noise = {}, threshold = -2, min_height = 62, replaces = { "Dirt" } })
+            register_ore({ name = "glass", block = "Glass", min_height = 10, max_height = 20,
+                vein_size = 64, frequency = 64 })
+            register_ore({ name = "rose", block = "Rose", min_height = 10, max_height = 20,
+                frequency = 8, replaces = { "Glass" } })
+        "#,
+        );
+        let id = |name: &str| world.get_item_manager().get_id_by_name(name.to_string());
+        let (dirt, grass) = (id("Dirt").unwrap(), id("Grass").unwrap());
+        let (glass, rose) = (id("Glass").unwrap(), id("Rose").unwrap());
+
+        let pos = Vec2::new(0, 0);
+        let compressed = |world: &mut World| -> Vec<u8> {
+            let chunks = world.get_column(&pos).get_chunks();
+            let sets: Vec<_> = chunks.iter().flat_map(|chunk| chunk.compress()).collect();
+            bincode::serialize(&sets).unwrap()
+        };
+        let first = compressed(&mut world);
+        assert!(world.regenerate_column(&pos));
+        assert_eq!(compressed(&mut world), first);
+
+        let column = world.get_column(&pos);
+        let (mut glass_count, mut rose_count) = (0, 0);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                assert_eq!(column.get_block(&Vec3::new(x, 61, z)), Ok(dirt));
+                assert_eq!(column.get_block(&Vec3::new(x, 62, z)), Ok(0));
+                assert_eq!(column.get_block(&Vec3::new(x, 63, z)), Ok(0));
+                assert_eq!(column.get_block(&Vec3::new(x, 64, z)), Ok(grass));
+                for y in 0..64 {
+                    let block = column.get_block(&Vec3::new(x, y, z)).unwrap();
+                    if block == glass || block == rose {
+                        assert!((10..=20).contains(&y));
+                    }
+                    glass_count += (block == glass) as i32;
+                    rose_count += (block == rose) as i32;
+                }
+            }
+        }
+        assert!(glass_count > 0);
+        assert!(rose_count > 0);
+    }
+
+    #[test]
+    fn test_saved_item_ids_are_remapped_by_name() {
+        let directory =
+            std::env::temp_dir().join(format!("voxelbuilder_item_names_{}", rand::random::<u32>()));
+        let directory = directory.to_string_lossy().to_string();
+
+        // A save written when the items were Air, Gone and Grass, in that order
+        let mut save = SaveFile::new(Some(directory.clone()));
+        let height = save.world_height;
+        for y in height.min_chunk_y..=height.max_chunk_y {
+            let mut chunk = Chunk::new(Vec3::new(0, y, 0), 0);
+            if y == 0 {
+                chunk.set_block(0, 0, 0, 1);
+                chunk.set_block(1, 0, 0, 2);
+                chunk.set_block(2, 0, 0, 7);
+            }
+            save.save_chunk_data(&chunk);
+        }
+        save.set_item_names(vec![
+            "Air".to_string(),
+            "Gone".to_string(),
+            "Grass".to_string(),
+        ]);
+        save.write_save().unwrap();
+
+        // Saves created before the Unknown item was added to the default script lack it
+        let mut save = SaveFile::new(Some(directory.clone()));
+        save.load().unwrap();
+        let item_script = format!("{}/loadAssetInfo.lua", save.get_script_directory());
+        let items = fs::read_to_string(&item_script).unwrap();
+        let items = items.replace(
+            "define_item({ name = \"Unknown\"",
+            "_ = ({ name = \"Unknown\"",
+        );
+        fs::write(&item_script, items).unwrap();
+        let sources = ScriptSources::new(&save, &ScriptLimits::default()).unwrap();
+        let mut world = World::new(save, &sources, ScriptLimits::default()).unwrap();
+        let unknown = world
+            .get_item_manager()
+            .get_id_by_name("Unknown".to_string());
+        let grass = world.get_item_manager().get_id_by_name("Grass".to_string());
+
+        assert_eq!(world.get_block(&Vec3::new(0, 0, 0)), unknown.unwrap());
+        assert_eq!(world.get_block(&Vec3::new(1, 0, 0)), grass.unwrap());
+        assert_eq!(world.get_block(&Vec3::new(2, 0, 0)), unknown.unwrap());
+        assert_eq!(world.get_block(&Vec3::new(3, 0, 0)), 0);
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn test_block_states_are_saved() {
+        let directory =
+            std::env::temp_dir().join(format!("voxelbuilder_states_{}", rand::random::<u32>()));
+        let directory = directory.to_string_lossy().to_string();
+
+        let mut world = load_saved_world(&directory);
+        let log = world.get_item_manager().get_id_by_name("Log".to_string());
+        let log = log.unwrap();
+        let state = world.get_item_manager().get_item_by_id(log).unwrap();
+        let state = state.encode_state([("axis", "z")]).unwrap();
+        let position = Vec3::new(3, 40, 5);
+        world.set_block_with_state(&position, log, state).unwrap();
+        assert_eq!(
+            world.set_block_with_state(&position, log, 3),
+            Err(WorldError::InvalidState(log, 3))
+        );
+        world.save_to_file();
+
+        let mut world = load_saved_world(&directory);
+        assert_eq!(world.get_block_with_state(&position), Some((log, state)));
+        // Placing another block resets the state
+        world.set_block(&position, log).unwrap();
+        assert_eq!(world.get_block_with_state(&position), Some((log, 0)));
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn test_block_entities() {
+        let directory =
+            std::env::temp_dir().join(format!("voxelbuilder_entities_{}", rand::random::<u32>()));
+        let directory = directory.to_string_lossy().to_string();
+        let sign = EntityValue::Table(vec![(
+            EntityValue::String("text".to_string()),
+            EntityValue::String("Welcome".to_string()),
+        )]);
+
+        let mut world = load_saved_world(&directory);
+        let log = world.get_item_manager().get_id_by_name("Log".to_string());
+        let log = log.unwrap();
+        let (chest, sign_post) = (Vec3::new(-3, 120, 17), Vec3::new(4, 120, 4));
+        assert_eq!(
+            world.set_block_entity(&chest, Some(sign.clone())),
+            Err(WorldError::EmptyBlock(chest))
+        );
+        for position in [chest, sign_post] {
+            world.set_block(&position, log).unwrap();
+            world
+                .set_block_entity(&position, Some(sign.clone()))
+                .unwrap();
+        }
+        world.save_to_file();
+
+        let mut world = load_saved_world(&directory);
+        assert_eq!(world.get_block_entity(&chest), Some(&sign));
+        // Changing the state keeps the entity, replacing the block removes it
+        world.set_block_with_state(&chest, log, 1).unwrap();
+        assert_eq!(world.get_block_entity(&chest), Some(&sign));
+        world.set_block(&sign_post, 0).unwrap();
+        assert_eq!(world.get_block_entity(&sign_post), None);
+        world.save_to_file();
+
+        let mut world = load_saved_world(&directory);
+        assert_eq!(world.get_block_entity(&chest), Some(&sign));
+        assert_eq!(world.get_block_entity(&sign_post), None);
+        fs::remove_dir_all(directory).ok();
+    }
+
+    #[test]
+    fn test_generator_kind_names() {
+        for name in ["script", "flat", "void", "terrain"] {
+            let kind: GeneratorKind = name.parse().unwrap();
+            assert_eq!(kind.to_string(), name);
+            assert_eq!(GeneratorKind::from_id(kind.id()), Some(kind));
+        }
+        assert!("caves".parse::<GeneratorKind>().is_err());
+        assert_eq!(GeneratorKind::from_id(200), None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_world_position_round_trip(x in any::<i32>(), y in any::<i32>(), z in any::<i32>()) {
+            let pos = Vec3::new(x, y, z);
+            let chunk = World::world_to_chunk_position(&pos);
+            let in_chunk = World::world_to_position_in_chunk(&pos);
+
+            prop_assert!((0..CHUNK_SIZE).contains(&in_chunk.x));
+            prop_assert!((0..CHUNK_SIZE).contains(&in_chunk.y));
+            prop_assert!((0..CHUNK_SIZE).contains(&in_chunk.z));
+            prop_assert_eq!(chunk.x as i64 * 16 + in_chunk.x as i64, x as i64);
+            prop_assert_eq!(chunk.y as i64 * 16 + in_chunk.y as i64, y as i64);
+            prop_assert_eq!(chunk.z as i64 * 16 + in_chunk.z as i64, z as i64);
+
+            let column = World::world_to_column_position(&Vec2::new(x, z));
+            prop_assert_eq!(column, Vec2::new(chunk.x, chunk.z));
+        }
+
+        #[test]
+        fn prop_world_rejects_out_of_bounds_height(
+            x in any::<i32>(),
+            y in prop_oneof![i32::MIN..0, 256..=i32::MAX],
+            z in any::<i32>(),
+        ) {
+            let mut world = test_world();
+            let pos = Vec3::new(x, y, z);
+            prop_assert_eq!(world.set_block(&pos, 1), Err(WorldError::OutOfBounds(pos)));
+            prop_assert_eq!(world.get_block(&pos), -1);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_world_set_block_anywhere_in_height(
+            x in any::<i32>(),
+            y in 0..256,
+            z in any::<i32>(),
+        ) {
+            let mut world = test_world();
+            let stone = world.get_item_manager().get_id_by_name("Stone".to_string()).unwrap();
+            let pos = Vec3::new(x, y, z);
+            prop_assert_eq!(world.set_block(&pos, stone), Ok(()));
+            prop_assert_eq!(world.get_block(&pos), stone);
+            prop_assert_eq!(world.set_block(&pos, -5), Err(WorldError::UnknownBlock(-5)));
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_world_below_zero_height(x in -1000..1000, y in -128..64, z in -1000..1000) {
+            let mut world = test_world();
+            world.get_save_file().world_height = WorldHeight::new(-8, 3).unwrap();
+            let stone = world.get_item_manager().get_id_by_name("Stone".to_string()).unwrap();
+            let pos = Vec3::new(x, y, z);
+
+            prop_assert_eq!(world.set_block(&pos, stone), Ok(()));
+            prop_assert_eq!(world.get_block(&pos), stone);
+
+            let below = Vec3::new(x, -129, z);
+            prop_assert_eq!(world.set_block(&below, stone), Err(WorldError::OutOfBounds(below)));
+            let above = Vec3::new(x, 64, z);
+            prop_assert_eq!(world.set_block(&above, stone), Err(WorldError::OutOfBounds(above)));
+        }
+    }
 }
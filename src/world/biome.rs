@@ -0,0 +1,284 @@
+use std::fs;
+
+use fast_noise_lite_rs::{FastNoiseLite, FractalType, NoiseType};
+use rlua::{Context, Table};
+
+use crate::items::ItemManager;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+
+/// Distance in blocks around a position whose biomes are blended into its surface height
+const BLEND_RADIUS: i32 = 8;
+/// Distance in blocks between the biome samples that are blended
+const BLEND_STEP: usize = 4;
+/// Frequency of the temperature and humidity noise, lower gives larger biomes
+const CLIMATE_FREQUENCY: f32 = 0.0015;
+/// Frequency of the noise shaping the surface within the height range of the biomes
+const HEIGHT_FREQUENCY: f32 = 0.00625;
+
+/// A biome registered by a biome script with `register_biome`
+pub struct Biome {
+    pub name: String,
+    /// Climate the biome is picked for, both from -1 to 1
+    pub temperature: f32,
+    pub humidity: f32,
+    /// Block on top of the surface
+    pub surface_block: i32,
+    /// Block below the surface block, above the stone
+    pub filler_block: i32,
+    pub filler_depth: i32,
+    /// Range of the surface height, blended with neighbouring biomes at the borders
+    pub min_height: i32,
+    pub max_height: i32,
+    /// Chance of one of `flowers` on each surface block
+    pub flower_density: f64,
+    pub flowers: Vec<i32>,
+}
+
+impl Biome {
+    /// Reads a biome from the table passed to `register_biome`, block names are looked up in `items`
+    fn from_table(table: &Table, items: &ItemManager) -> rlua::Result<Biome> {
+        let name: String = table.get("name")?;
+        let invalid = |message: &str| {
+            rlua::Error::RuntimeError(format!("invalid biome {}: {}", name, message))
+        };
+        let block_id = |block: String| {
+            items
+                .get_id_by_name(block.clone())
+                .ok_or_else(|| invalid(&format!("unknown block {}", block)))
+        };
+
+        let temperature: Option<f32> = table.get("temperature")?;
+        let humidity: Option<f32> = table.get("humidity")?;
+        let filler_depth: Option<i32> = table.get("filler_depth")?;
+        let min_height: Option<i32> = table.get("min_height")?;
+        let max_height: Option<i32> = table.get("max_height")?;
+        let flower_density: Option<f64> = table.get("flower_density")?;
+        let flowers: Option<Vec<String>> = table.get("flowers")?;
+
+        let biome = Biome {
+            surface_block: block_id(table.get("surface")?)?,
+            filler_block: block_id(table.get("filler")?)?,
+            temperature: temperature.unwrap_or(0.0),
+            humidity: humidity.unwrap_or(0.0),
+            filler_depth: filler_depth.unwrap_or(6),
+            min_height: min_height.unwrap_or(60),
+            max_height: max_height.unwrap_or(90),
+            flower_density: flower_density.unwrap_or(0.0),
+            flowers: flowers
+                .unwrap_or_default()
+                .into_iter()
+                .map(block_id)
+                .collect::<rlua::Result<_>>()?,
+            name: name.clone(),
+        };
+
+        let climate = -1.0..=1.0;
+        if !climate.contains(&biome.temperature) || !climate.contains(&biome.humidity) {
+            return Err(invalid("temperature and humidity must be between -1 and 1"));
+        }
+        if biome.min_height > biome.max_height {
+            return Err(invalid("min_height is above max_height"));
+        }
+        if biome.filler_depth < 0 {
+            return Err(invalid("filler_depth may not be negative"));
+        }
+        if !(0.0..=1.0).contains(&biome.flower_density) {
+            return Err(invalid("flower_density must be between 0 and 1"));
+        }
+
+        Ok(biome)
+    }
+
+    /// Creates the table describing the biome to generator scripts
+    pub fn to_table<'lua>(&self, lua_ctx: Context<'lua>) -> rlua::Result<Table<'lua>> {
+        let table = lua_ctx.create_table()?;
+        table.set("name", self.name.as_str())?;
+        table.set("temperature", self.temperature)?;
+        table.set("humidity", self.humidity)?;
+        table.set("surface", self.surface_block)?;
+        table.set("filler", self.filler_block)?;
+        table.set("filler_depth", self.filler_depth)?;
+        table.set("min_height", self.min_height)?;
+        table.set("max_height", self.max_height)?;
+        table.set("flower_density", self.flower_density)?;
+        table.set("flowers", self.flowers.clone())?;
+        Ok(table)
+    }
+}
+
+/// Picks the biome of every position from temperature and humidity noise
+pub struct BiomeMap {
+    biomes: Vec<Biome>,
+    temperature: FastNoiseLite,
+    humidity: FastNoiseLite,
+    height: FastNoiseLite,
+}
+
+impl BiomeMap {
+    /// Creates the map of the world with `seed`, None if there are no biomes
+    pub fn new(biomes: Vec<Biome>, seed: i32) -> Option<BiomeMap> {
+        if biomes.is_empty() {
+            return None;
+        }
+
+        let noise = |seed_offset: i32, frequency: f32| {
+            let mut noise = FastNoiseLite::new(seed.wrapping_add(seed_offset));
+            noise.set_noise_type(NoiseType::OpenSimplex2);
+            noise.set_frequency(frequency);
+            noise
+        };
+        let mut temperature = noise(1, CLIMATE_FREQUENCY);
+        let mut humidity = noise(2, CLIMATE_FREQUENCY);
+        for climate in [&mut temperature, &mut humidity] {
+            climate.set_fractal_type(FractalType::FBm);
+            climate.set_fractal_octaves(3);
+        }
+
+        Some(BiomeMap {
+            biomes,
+            temperature,
+            humidity,
+            height: noise(0, HEIGHT_FREQUENCY),
+        })
+    }
+
+    /// Runs the biome scripts at `paths` in order, a biome registered again replaces the earlier one
+    pub fn load(
+        paths: &[String],
+        items: &ItemManager,
+        seed: i32,
+        limits: &ScriptLimits,
+    ) -> Result<BiomeMap, ScriptError> {
+        let mut biomes: Vec<Biome> = Vec::new();
+        for path in paths {
+            let script = fs::read_to_string(path)
+                .map_err(|e| ScriptError::new(path, format!("unable to read script: {}", e)))?;
+
+            let lua = new_sandboxed_lua(limits);
+            run_limited(&lua, limits, |lua_ctx| {
+                lua_ctx.scope(|scope| {
+                    let register_biome = scope.create_function_mut(|_, table: Table| {
+                        let biome = Biome::from_table(&table, items)?;
+                        match biomes.iter_mut().find(|other| other.name == biome.name) {
+                            Some(other) => *other = biome,
+                            None => biomes.push(biome),
+                        }
+                        Ok(())
+                    })?;
+                    lua_ctx.globals().set("register_biome", register_biome)?;
+
+                    lua_ctx.load(&script).set_name(&chunk_name(path))?.exec()
+                })
+            })
+            .map_err(|e| ScriptError::from_lua(path, &e))?;
+        }
+
+        BiomeMap::new(biomes, seed)
+            .ok_or_else(|| ScriptError::new("biome scripts", "no biomes registered".to_string()))
+    }
+
+    pub fn get_biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// Gets the biome at world position `x`,`z`, the one with the closest climate
+    pub fn get_biome(&self, x: i32, z: i32) -> &Biome {
+        let temperature = self.temperature.get_noise_2d(x as f32, z as f32);
+        let humidity = self.humidity.get_noise_2d(x as f32, z as f32);
+
+        let distance = |biome: &Biome| {
+            (biome.temperature - temperature).powi(2) + (biome.humidity - humidity).powi(2)
+        };
+        self.biomes
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .unwrap()
+    }
+
+    /// Gets the surface height at world position `x`,`z`
+    ///
+    /// The height range is averaged over the biomes around the position, so borders are smooth
+    pub fn surface_height(&self, x: i32, z: i32) -> i32 {
+        let mut min_height = 0;
+        let mut max_height = 0;
+        let mut samples = 0;
+        for offset_x in (-BLEND_RADIUS..=BLEND_RADIUS).step_by(BLEND_STEP) {
+            for offset_z in (-BLEND_RADIUS..=BLEND_RADIUS).step_by(BLEND_STEP) {
+                let biome = self.get_biome(x.saturating_add(offset_x), z.saturating_add(offset_z));
+                min_height += biome.min_height as i64;
+                max_height += biome.max_height as i64;
+                samples += 1;
+            }
+        }
+        let min_height = min_height as f32 / samples as f32;
+        let max_height = max_height as f32 / samples as f32;
+
+        let noise = (self.height.get_noise_2d(x as f32, z as f32) + 1.0) / 2.0;
+        (min_height + (max_height - min_height) * noise).floor() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::default_items;
+    use crate::scripting::with_script_file;
+
+    fn load_biomes(script: &str) -> Result<BiomeMap, ScriptError> {
+        let items = default_items();
+        with_script_file(script, |paths| {
+            BiomeMap::load(paths, &items, 42, &ScriptLimits::default())
+        })
+    }
+
+    #[test]
+    fn test_biome_selection_and_blending() {
+        let biomes = load_biomes(
+            r#"
+            register_biome({ name = "cold", surface = "Stone", filler = "Stone",
+                temperature = -1, min_height = 100, max_height = 100 })
+            register_biome({ name = "warm", surface = "Grass", filler = "Dirt",
+                temperature = 1, min_height = 10, max_height = 20 })
+            register_biome({ name = "warm", surface = "Grass", filler = "Dirt",
+                temperature = 1, min_height = 50, max_height = 50, flowers = { "Rose" } })
+            "#,
+        )
+        .unwrap();
+        assert_eq!(biomes.get_biomes().len(), 2);
+        assert_eq!(biomes.get_biomes()[1].min_height, 50);
+
+        let mut seen = Vec::new();
+        let mut previous: Option<i32> = None;
+        for x in (0..20_000).step_by(4) {
+            let biome = biomes.get_biome(x, 0);
+            let height = biomes.surface_height(x, 0);
+            if !seen.contains(&biome.name) {
+                seen.push(biome.name.clone());
+            }
+
+            // Away from borders the height is the biome's own, across borders it changes gradually
+            assert!((50..=100).contains(&height));
+            if let Some(previous) = previous {
+                assert!((height - previous).abs() <= 25);
+            }
+            previous = Some(height);
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_biomes() {
+        assert!(load_biomes("").is_err());
+        for biome in [
+            "{ name = \"a\", surface = \"Nope\", filler = \"Dirt\" }",
+            "{ name = \"a\", surface = \"Grass\" }",
+            "{ name = \"a\", surface = \"Grass\", filler = \"Dirt\", temperature = 2 }",
+            "{ name = \"a\", surface = \"Grass\", filler = \"Dirt\", min_height = 9, max_height = 1 }",
+            "{ name = \"a\", surface = \"Grass\", filler = \"Dirt\", flower_density = 1.5 }",
+            "{ name = \"a\", surface = \"Grass\", filler = \"Dirt\", flowers = { \"Nope\" } }",
+        ] {
+            let error = load_biomes(&format!("register_biome({})", biome));
+            assert!(error.is_err(), "{}", biome);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use rlua::{Context, Value};
+use serde::{Deserialize, Serialize};
+
+/// Deepest nesting of tables in a block entity
+const MAX_DEPTH: usize = 8;
+/// Largest encoded block entity in bytes, keeps chunk packets and saves small
+pub const MAX_ENTITY_SIZE: usize = 16 * 1024;
+
+/// Data attached to a block that does not fit in its state, such as chest contents or sign text
+///
+/// Block entities are set by scripts as lua tables, so a block entity is always a `Table`
+///
+/// Entities are saved and sent to clients with bincode, little endian: a u32 variant index
+/// (0 `Boolean`, 1 `Integer`, 2 `Number`, 3 `String`, 4 `Table`) followed by a u8 for booleans,
+/// an i64, an f64, a u64 length and the UTF-8 bytes of a string, or a u64 pair count and every
+/// key and value of a table
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EntityValue {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    /// Key and value pairs, keys are integers or strings
+    Table(Vec<(EntityValue, EntityValue)>),
+}
+
+impl EntityValue {
+    /// Creates a block entity from the lua table `value`
+    ///
+    /// Functions and other values that cannot be saved are errors, as are entities larger than
+    /// `MAX_ENTITY_SIZE`
+    pub fn from_lua(value: Value) -> rlua::Result<EntityValue> {
+        if !matches!(value, Value::Table(_)) {
+            return Err(rlua::Error::RuntimeError(
+                "a block entity must be a table".to_string(),
+            ));
+        }
+        let entity = EntityValue::convert(value, 0)?;
+        if entity.encode().len() > MAX_ENTITY_SIZE {
+            return Err(rlua::Error::RuntimeError(format!(
+                "a block entity may not be larger than {} bytes",
+                MAX_ENTITY_SIZE
+            )));
+        }
+        Ok(entity)
+    }
+
+    fn convert(value: Value, depth: usize) -> rlua::Result<EntityValue> {
+        let entity = match value {
+            Value::Boolean(value) => EntityValue::Boolean(value),
+            Value::Integer(value) => EntityValue::Integer(value),
+            Value::Number(value) => EntityValue::Number(value),
+            Value::String(value) => EntityValue::String(value.to_str()?.to_string()),
+            Value::Table(table) => {
+                if depth >= MAX_DEPTH {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "block entity tables may not be nested more than {} deep",
+                        MAX_DEPTH
+                    )));
+                }
+                let mut pairs = Vec::new();
+                for pair in table.pairs::<Value, Value>() {
+                    let (key, value) = pair?;
+                    if !matches!(key, Value::Integer(_) | Value::String(_)) {
+                        return Err(rlua::Error::RuntimeError(format!(
+                            "block entity keys must be integers or strings, not {}",
+                            key.type_name()
+                        )));
+                    }
+                    pairs.push((
+                        EntityValue::convert(key, depth)?,
+                        EntityValue::convert(value, depth + 1)?,
+                    ));
+                }
+                EntityValue::Table(pairs)
+            }
+            value => {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "a {} cannot be stored in a block entity",
+                    value.type_name()
+                )))
+            }
+        };
+        Ok(entity)
+    }
+
+    /// Creates the lua value of the entity, a table for block entities
+    pub fn to_lua<'lua>(&self, lua_ctx: Context<'lua>) -> rlua::Result<Value<'lua>> {
+        let value = match self {
+            EntityValue::Boolean(value) => Value::Boolean(*value),
+            EntityValue::Integer(value) => Value::Integer(*value),
+            EntityValue::Number(value) => Value::Number(*value),
+            EntityValue::String(value) => Value::String(lua_ctx.create_string(value)?),
+            EntityValue::Table(pairs) => {
+                let table = lua_ctx.create_table()?;
+                for (key, value) in pairs {
+                    table.set(key.to_lua(lua_ctx)?, value.to_lua(lua_ctx)?)?;
+                }
+                Value::Table(table)
+            }
+        };
+        Ok(value)
+    }
+
+    /// Encodes the entity the way it is saved and sent to clients
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlua::Lua;
+
+    #[test]
+    fn test_entities_from_lua() {
+        Lua::new().context(|lua_ctx| {
+            let sign = lua_ctx
+                .load(r#"{ text = "Welcome", lines = { "a", "b" }, glowing = true, scale = 1.5 }"#)
+                .eval::<Value>()
+                .unwrap();
+            let entity = EntityValue::from_lua(sign).unwrap();
+
+            // Converting back gives the same table
+            let table = entity.to_lua(lua_ctx).unwrap();
+            let again = EntityValue::from_lua(table.clone()).unwrap();
+            let decoded: EntityValue = bincode::deserialize(&again.encode()).unwrap();
+            let rlua::Value::Table(table) = table else {
+                panic!("block entity is not a table");
+            };
+            assert_eq!(table.get::<_, String>("text").unwrap(), "Welcome");
+            assert_eq!(table.get::<_, f64>("scale").unwrap(), 1.5);
+            let lines: Vec<String> = table.get("lines").unwrap();
+            assert_eq!(lines, vec!["a", "b"]);
+            assert!(matches!(&decoded, EntityValue::Table(pairs) if pairs.len() == 4));
+
+            let invalid = [
+                "5",
+                "{ callback = function() end }",
+                "{ [true] = 1 }",
+                "{ { { { { { { { { { 1 } } } } } } } } } }",
+                "{ text = string.rep(\"a\", 20000) }",
+            ];
+            for source in invalid {
+                let value = lua_ctx.load(source).eval::<Value>().unwrap();
+                assert!(EntityValue::from_lua(value).is_err(), "{}", source);
+            }
+        });
+    }
+}
@@ -1,16 +1,86 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use crate::vector_types::{Vec2, Vec3};
+use crate::world::{EntityValue, WorldError};
 
+/// Width, height and depth of a chunk in blocks
+pub const CHUNK_SIZE: i32 = 16;
+/// Number of blocks in a single chunk
+pub const CHUNK_VOLUME: usize = 4096;
+/// Number of chunks stacked in every column of a default world
+pub const COLUMN_HEIGHT_CHUNKS: i32 = 16;
+/// Largest distance in chunks from y=0 a world may extend, bounds the memory of every column and
+/// the work of carving caves through it
+pub const MAX_CHUNK_Y_MAGNITUDE: i32 = 64;
+
+/// Vertical range of every column in a world, in chunks (both inclusive)
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub struct WorldHeight {
+    pub min_chunk_y: i32,
+    pub max_chunk_y: i32,
+}
+
+impl WorldHeight {
+    /// Creates a height range, or `None` if the range is empty or too large
+    pub fn new(min_chunk_y: i32, max_chunk_y: i32) -> Option<WorldHeight> {
+        let valid = min_chunk_y <= max_chunk_y
+            && min_chunk_y >= -MAX_CHUNK_Y_MAGNITUDE
+            && max_chunk_y <= MAX_CHUNK_Y_MAGNITUDE;
+        valid.then_some(WorldHeight {
+            min_chunk_y,
+            max_chunk_y,
+        })
+    }
+
+    /// Lowest block y that can hold a block
+    pub fn min_block_y(&self) -> i32 {
+        self.min_chunk_y * CHUNK_SIZE
+    }
+
+    /// Highest block y that can hold a block
+    pub fn max_block_y(&self) -> i32 {
+        self.max_chunk_y * CHUNK_SIZE + CHUNK_SIZE - 1
+    }
+
+    /// Returns whether blocks can exist at block height `y`
+    pub fn contains_block_y(&self, y: i32) -> bool {
+        (self.min_block_y()..=self.max_block_y()).contains(&y)
+    }
+
+    /// Returns whether chunk height `y` is part of every column
+    pub fn contains_chunk_y(&self, y: i32) -> bool {
+        (self.min_chunk_y..=self.max_chunk_y).contains(&y)
+    }
+
+    /// Number of chunks in every column
+    pub fn chunk_count(&self) -> usize {
+        (self.max_chunk_y - self.min_chunk_y + 1) as usize
+    }
+}
+
+impl Default for WorldHeight {
+    fn default() -> Self {
+        WorldHeight {
+            min_chunk_y: 0,
+            max_chunk_y: COLUMN_HEIGHT_CHUNKS - 1,
+        }
+    }
+}
+
+/// A run of `count` blocks with the same id and state
 #[derive(Serialize, Deserialize)]
 pub struct CompressedSet {
     pub id: i32,
     pub count: i32,
+    pub state: u16,
 }
 
 pub struct Chunk {
     pub position: Vec3<i32>,
-    blocks: [i32; 4096],
+    blocks: [i32; CHUNK_VOLUME],
+    /// State of every block, such as its orientation, see `ItemData::states`
+    states: [u16; CHUNK_VOLUME],
 }
 
 impl Chunk {
@@ -18,7 +88,8 @@ impl Chunk {
     pub fn new(position: Vec3<i32>, id: i32) -> Chunk {
         Chunk {
             position,
-            blocks: [id; 4096],
+            blocks: [id; CHUNK_VOLUME],
+            states: [0; CHUNK_VOLUME],
         }
     }
 
@@ -26,14 +97,20 @@ impl Chunk {
         256 * z as u16 + 16 * y as u16 + x as u16
     }
 
-    /// Sets the block at position `i` to `id`
-    pub fn set_block_i(&mut self, i: u16, id: i32) {
+    /// Sets the block at position `i` to `id` with `state`
+    pub fn set_block_i(&mut self, i: u16, id: i32, state: u16) {
         self.blocks[i as usize] = id;
+        self.states[i as usize] = state;
     }
 
-    /// Sets the block at position (`x`,`y`,`z`) to `id`
+    /// Sets the block at position (`x`,`y`,`z`) to `id` in its default state
     pub fn set_block(&mut self, x: u8, y: u8, z: u8, id: i32) {
-        self.blocks[Chunk::xyz_to_i(x, y, z) as usize] = id;
+        self.set_block_with_state(x, y, z, id, 0);
+    }
+
+    /// Sets the block at position (`x`,`y`,`z`) to `id` with `state`
+    pub fn set_block_with_state(&mut self, x: u8, y: u8, z: u8, id: i32, state: u16) {
+        self.set_block_i(Chunk::xyz_to_i(x, y, z), id, state);
     }
 
     /// Gets the block at position (`x`,`y`,`z`)
@@ -41,28 +118,43 @@ impl Chunk {
         self.blocks[Chunk::xyz_to_i(x, y, z) as usize]
     }
 
-    /// Compresses the chunk data using run-length encoding
+    /// Gets the state of the block at position (`x`,`y`,`z`)
+    pub fn get_state(&self, x: u8, y: u8, z: u8) -> u16 {
+        self.states[Chunk::xyz_to_i(x, y, z) as usize]
+    }
+
+    /// Compresses the chunk data using run-length encoding, a run ends where the id or state changes
     pub fn compress(&self) -> Vec<CompressedSet> {
         let mut set = Vec::<CompressedSet>::new();
 
         let mut number = 0;
         let mut id = -1;
+        let mut state = 0;
 
         for i in 0..4096 {
             let block = self.blocks[i];
-            if block == id {
+            if block == id && self.states[i] == state {
                 number += 1;
             } else {
                 if id != -1 {
-                    let new_set = CompressedSet { id, count: number };
+                    let new_set = CompressedSet {
+                        id,
+                        count: number,
+                        state,
+                    };
                     set.push(new_set);
                 }
                 id = block;
+                state = self.states[i];
                 number = 1;
             }
 
             if i == 4095 && id != -1 {
-                let new_set = CompressedSet { id, count: number };
+                let new_set = CompressedSet {
+                    id,
+                    count: number,
+                    state,
+                };
                 set.push(new_set);
             }
         }
@@ -73,14 +165,21 @@ impl Chunk {
 
 pub struct ChunkColumn {
     chunks: Vec<Chunk>,
+    height: WorldHeight,
+    /// Block entities by position (x and z relative to the column)
+    block_entities: HashMap<Vec3<i32>, EntityValue>,
 }
 
 impl ChunkColumn {
-    // Creates a new ChunkColumn filled with `id` (16 chunks tall)
-    pub fn new(position: &Vec2<i32>, id: i32) -> ChunkColumn {
-        let mut col = ChunkColumn { chunks: Vec::new() };
+    // Creates a new ChunkColumn filled with `id` spanning `height`
+    pub fn new(position: &Vec2<i32>, id: i32, height: WorldHeight) -> ChunkColumn {
+        let mut col = ChunkColumn {
+            chunks: Vec::with_capacity(height.chunk_count()),
+            height,
+            block_entities: HashMap::new(),
+        };
 
-        for y in 0..16 {
+        for y in height.min_chunk_y..=height.max_chunk_y {
             col.chunks
                 .push(Chunk::new(Vec3::new(position.x, y, position.y), id));
         }
@@ -92,41 +191,117 @@ impl ChunkColumn {
         &self.chunks
     }
 
-    pub fn get_chunk<'a>(&'a mut self, i: u8) -> &'a mut Chunk {
-        &mut self.chunks[i as usize]
+    /// Gets the chunk at chunk height `y` if it is inside the column
+    pub fn get_chunk(&mut self, y: i32) -> Option<&mut Chunk> {
+        if !self.height.contains_chunk_y(y) {
+            return None;
+        }
+        self.chunks.get_mut((y - self.height.min_chunk_y) as usize)
+    }
+
+    /// Returns whether `position` (x and z relative to the column) is inside the column
+    fn contains(&self, position: &Vec3<i32>) -> bool {
+        (0..CHUNK_SIZE).contains(&position.x)
+            && (0..CHUNK_SIZE).contains(&position.z)
+            && self.height.contains_block_y(position.y)
+    }
+
+    /// Gets the block at `position` (x and z relative to the column)
+    pub fn get_block(&self, position: &Vec3<i32>) -> Result<i32, WorldError> {
+        if !self.contains(position) {
+            return Err(WorldError::OutOfBounds(*position));
+        }
+
+        let chunk_index = position.y.div_euclid(CHUNK_SIZE) - self.height.min_chunk_y;
+        Ok(self.chunks[chunk_index as usize].get_block(
+            position.x as u8,
+            position.y.rem_euclid(CHUNK_SIZE) as u8,
+            position.z as u8,
+        ))
+    }
+
+    /// Gets the block entity at `position` (x and z relative to the column)
+    pub fn get_block_entity(&self, position: &Vec3<i32>) -> Option<&EntityValue> {
+        self.block_entities.get(position)
+    }
+
+    /// Sets the block entity at `position` (x and z relative to the column), None removes it
+    pub fn set_block_entity(
+        &mut self,
+        position: &Vec3<i32>,
+        entity: Option<EntityValue>,
+    ) -> Result<(), WorldError> {
+        if !self.contains(position) {
+            return Err(WorldError::OutOfBounds(*position));
+        }
+
+        match entity {
+            Some(entity) => self.block_entities.insert(*position, entity),
+            None => self.block_entities.remove(position),
+        };
+        Ok(())
+    }
+
+    /// Gets every block entity of the column by position (x and z relative to the column)
+    pub fn get_block_entities(&self) -> &HashMap<Vec3<i32>, EntityValue> {
+        &self.block_entities
     }
 
-    pub fn set_block(&mut self, position: &Vec3<i32>, id: i32) {
-        self.chunks
-            .get_mut((position.y / 16) as usize)
-            .unwrap()
+    /// Gets the height of the highest non air block at `x`,`z` (relative to the column)
+    pub fn get_height(&self, x: i32, z: i32) -> Option<i32> {
+        (self.height.min_block_y()..=self.height.max_block_y())
+            .rev()
+            .find(|y| self.get_block(&Vec3::new(x, *y, z)).is_ok_and(|id| id > 0))
+    }
+
+    /// Sets the block at `position` (x and z relative to the column) to `id`
+    pub fn set_block(&mut self, position: &Vec3<i32>, id: i32) -> Result<(), WorldError> {
+        if !self.contains(position) {
+            return Err(WorldError::OutOfBounds(*position));
+        }
+
+        self.get_chunk(position.y.div_euclid(CHUNK_SIZE))
+            .ok_or(WorldError::OutOfBounds(*position))?
             .set_block(
                 position.x as u8,
-                (position.y % 16) as u8,
+                position.y.rem_euclid(CHUNK_SIZE) as u8,
                 position.z as u8,
                 id,
             );
+
+        Ok(())
     }
 
-    pub fn set_layers(&mut self, lower: u32, upper: u32, id: i32) {
-        for y in lower..(upper + 1) {
+    /// Fills every layer from `lower` to `upper` (inclusive) with `id`
+    pub fn set_layers(&mut self, lower: i32, upper: i32, id: i32) -> Result<(), WorldError> {
+        if lower > upper {
+            return Ok(());
+        }
+        for y in [lower, upper] {
+            if !self.height.contains_block_y(y) {
+                return Err(WorldError::OutOfBounds(Vec3::new(0, y, 0)));
+            }
+        }
+
+        for y in lower..=upper {
+            let chunk = self
+                .get_chunk(y.div_euclid(CHUNK_SIZE))
+                .ok_or(WorldError::OutOfBounds(Vec3::new(0, y, 0)))?;
             for x in 0..16 {
                 for z in 0..16 {
-                    self.chunks.get_mut((y / 16) as usize).unwrap().set_block(
-                        x,
-                        (y % 16) as u8,
-                        z,
-                        id,
-                    );
+                    chunk.set_block(x, y.rem_euclid(CHUNK_SIZE) as u8, z, id);
                 }
             }
         }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_xyz_to_i() {
@@ -151,7 +326,7 @@ mod tests {
         assert_eq!(set[0].id, 0);
 
         // Single block
-        chunk.set_block_i(0, 1);
+        chunk.set_block_i(0, 1, 0);
         let set = chunk.compress();
         assert_eq!(set.len(), 2);
         assert_eq!(set[0].count, 1);
@@ -162,19 +337,107 @@ mod tests {
         // Alternating double
         for i in 0..4096 {
             if i % 4 == 0 || i % 4 == 1 {
-                chunk.set_block_i(i, 1);
+                chunk.set_block_i(i, 1, 0);
             }
         }
 
         let set = chunk.compress();
         assert_eq!(set.len(), 2048);
-        for i in 0..2048 {
-            assert_eq!(set[i].count, 2);
+        for (i, set) in set.iter().enumerate() {
+            assert_eq!(set.count, 2);
             if i % 2 == 1 {
-                assert_eq!(set[i].id, 0);
+                assert_eq!(set.id, 0);
+            } else {
+                assert_eq!(set.id, 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_states() {
+        let mut chunk = Chunk::new(Vec3::new(0, 0, 0), 1);
+        chunk.set_block_with_state(1, 0, 0, 1, 3);
+        assert_eq!(chunk.get_state(1, 0, 0), 3);
+        assert_eq!(chunk.get_state(2, 0, 0), 0);
+
+        // A different state splits a run of the same id
+        let set = chunk.compress();
+        let runs: Vec<_> = set
+            .iter()
+            .map(|set| (set.id, set.count, set.state))
+            .collect();
+        assert_eq!(runs, vec![(1, 1, 0), (1, 1, 3), (1, 4094, 0)]);
+
+        // Replacing the block resets its state
+        chunk.set_block(1, 0, 0, 2);
+        assert_eq!(chunk.get_state(1, 0, 0), 0);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_column_set_block_bounds(x in any::<i32>(), y in any::<i32>(), z in any::<i32>()) {
+            let height = WorldHeight::default();
+            let mut col = ChunkColumn::new(&Vec2::new(0, 0), 0, height);
+            let pos = Vec3::new(x, y, z);
+            let in_bounds = (0..CHUNK_SIZE).contains(&x)
+                && (0..CHUNK_SIZE).contains(&z)
+                && (0..COLUMN_HEIGHT_CHUNKS * CHUNK_SIZE).contains(&y);
+
+            match col.set_block(&pos, 2) {
+                Ok(()) => prop_assert!(in_bounds),
+                Err(e) => {
+                    prop_assert!(!in_bounds);
+                    prop_assert_eq!(e, WorldError::OutOfBounds(pos));
+                }
+            }
+        }
+
+        #[test]
+        fn prop_column_set_block_in_bounds(x in 0..CHUNK_SIZE, y in -64..192, z in 0..CHUNK_SIZE) {
+            let height = WorldHeight::new(-4, 11).unwrap();
+            let mut col = ChunkColumn::new(&Vec2::new(0, 0), 0, height);
+            prop_assert_eq!(col.set_block(&Vec3::new(x, y, z), 2), Ok(()));
+            let chunk = col.get_chunk(y.div_euclid(CHUNK_SIZE)).unwrap();
+            prop_assert_eq!(chunk.position.y, y.div_euclid(CHUNK_SIZE));
+            prop_assert_eq!(chunk.get_block(x as u8, y.rem_euclid(CHUNK_SIZE) as u8, z as u8), 2);
+        }
+
+        #[test]
+        fn prop_column_get_height(x in 0..CHUNK_SIZE, y in -64..192, z in 0..CHUNK_SIZE) {
+            let height = WorldHeight::new(-4, 11).unwrap();
+            let mut col = ChunkColumn::new(&Vec2::new(0, 0), 0, height);
+            prop_assert_eq!(col.get_height(x, z), None);
+
+            col.set_layers(-64, -60, 1).unwrap();
+            col.set_block(&Vec3::new(x, y, z), 2).unwrap();
+            prop_assert_eq!(col.get_block(&Vec3::new(x, y, z)), Ok(2));
+            prop_assert_eq!(col.get_height(x, z), Some(y.max(-60)));
+            prop_assert_eq!(col.get_height(CHUNK_SIZE, z), None);
+        }
+
+        #[test]
+        fn prop_column_set_layers_bounds(lower in any::<i32>(), upper in any::<i32>()) {
+            let height = WorldHeight::new(-4, 11).unwrap();
+            let mut col = ChunkColumn::new(&Vec2::new(0, 0), 0, height);
+            let result = col.set_layers(lower, upper, 2);
+            let in_bounds = height.contains_block_y(lower) && height.contains_block_y(upper);
+            prop_assert_eq!(result.is_ok(), lower > upper || in_bounds);
+        }
+
+        #[test]
+        fn prop_world_height_limits(min in any::<i32>(), max in any::<i32>()) {
+            if let Some(height) = WorldHeight::new(min, max) {
+                prop_assert!(height.min_block_y() <= height.max_block_y());
+                prop_assert_eq!(height.chunk_count() as i64, max as i64 - min as i64 + 1);
             } else {
-                assert_eq!(set[i].id, 1);
+                prop_assert!(min > max || min < -MAX_CHUNK_Y_MAGNITUDE || max > MAX_CHUNK_Y_MAGNITUDE);
             }
         }
+
+        #[test]
+        fn prop_world_height_is_capped(magnitude in (MAX_CHUNK_Y_MAGNITUDE + 1)..i32::MAX) {
+            prop_assert!(WorldHeight::new(-magnitude, 0).is_none());
+            prop_assert!(WorldHeight::new(0, magnitude).is_none());
+        }
     }
 }
@@ -0,0 +1,293 @@
+use anyhow::{bail, Result};
+
+use crate::world::chunk_column::{CompressedSet, CHUNK_VOLUME};
+
+/// Byte layout used when sending or saving the output of `Chunk::compress`
+///
+/// Encodings are ordered from the oldest to the newest
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ChunkEncoding {
+    /// Fixed 8 byte (`id`, `count`) sets terminated by an id of -1, understood by older clients
+    Fixed,
+    /// Number of sets followed by varint (`id`, `count`) pairs
+    Varint,
+    /// Number of sets followed by varint (`id`, `count`, `state`) triples
+    ///
+    /// The other encodings leave out block states, their sets decode with state 0
+    States,
+}
+
+impl ChunkEncoding {
+    /// Gets the newest encoding understood by clients speaking protocol `version`
+    ///
+    /// Clients that send no version are older than protocol versions and only understand `Fixed`
+    pub fn for_protocol(version: Option<u8>) -> ChunkEncoding {
+        match version {
+            None | Some(0) => ChunkEncoding::Fixed,
+            Some(1) => ChunkEncoding::Varint,
+            Some(_) => ChunkEncoding::States,
+        }
+    }
+}
+
+/// Appends `value` to `out` as an unsigned LEB128 varint
+pub fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads an unsigned LEB128 varint from `data` starting at `*cursor` and advances the cursor
+pub fn read_varint(data: &[u8], cursor: &mut usize) -> Result<u32> {
+    let mut value: u32 = 0;
+    for shift in (0..35).step_by(7) {
+        let byte = match data.get(*cursor) {
+            Some(byte) => *byte,
+            None => bail!("Unexpected end of data while reading varint"),
+        };
+        *cursor += 1;
+
+        let bits = (byte & 0x7f) as u32;
+        if shift == 28 && bits > 0x0f {
+            bail!("Varint does not fit in 32 bits");
+        }
+        value |= bits << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Varint is longer than 5 bytes")
+}
+
+/// Checks that `sets` describe exactly one chunk worth of blocks
+pub fn validate_sets(sets: &[CompressedSet]) -> Result<()> {
+    let mut total: i64 = 0;
+    for set in sets {
+        if set.id < 0 {
+            bail!("Compressed set has invalid id {}", set.id);
+        }
+        if set.count < 1 {
+            bail!("Compressed set has invalid count {}", set.count);
+        }
+        total += set.count as i64;
+    }
+
+    if total != CHUNK_VOLUME as i64 {
+        bail!(
+            "Compressed sets contain {} blocks, expected {}",
+            total,
+            CHUNK_VOLUME
+        );
+    }
+
+    Ok(())
+}
+
+/// Encodes `sets` using `encoding`
+pub fn encode_sets(sets: &[CompressedSet], encoding: ChunkEncoding) -> Vec<u8> {
+    let mut data = Vec::<u8>::new();
+
+    match encoding {
+        ChunkEncoding::Fixed => {
+            for set in sets {
+                data.extend_from_slice(&set.id.to_le_bytes());
+                data.extend_from_slice(&set.count.to_le_bytes());
+            }
+            data.extend_from_slice(&(-1i32).to_le_bytes());
+        }
+        ChunkEncoding::Varint | ChunkEncoding::States => {
+            write_varint(&mut data, sets.len() as u32);
+            for set in sets {
+                write_varint(&mut data, set.id as u32);
+                write_varint(&mut data, set.count as u32);
+                if encoding == ChunkEncoding::States {
+                    write_varint(&mut data, set.state as u32);
+                }
+            }
+        }
+    }
+
+    data
+}
+
+/// Decodes one chunk worth of sets from the start of `data`
+///
+/// Returns the sets and the number of bytes consumed
+pub fn decode_sets(data: &[u8], encoding: ChunkEncoding) -> Result<(Vec<CompressedSet>, usize)> {
+    let mut sets = Vec::<CompressedSet>::new();
+    let mut cursor = 0;
+
+    match encoding {
+        ChunkEncoding::Fixed => loop {
+            let id = read_i32(data, &mut cursor)?;
+            if id == -1 {
+                break;
+            }
+            let count = read_i32(data, &mut cursor)?;
+            sets.push(CompressedSet {
+                id,
+                count,
+                state: 0,
+            });
+            if sets.len() > CHUNK_VOLUME {
+                bail!("Too many compressed sets for a single chunk");
+            }
+        },
+        ChunkEncoding::Varint | ChunkEncoding::States => {
+            let num_sets = read_varint(data, &mut cursor)? as usize;
+            if num_sets > CHUNK_VOLUME {
+                bail!("Too many compressed sets for a single chunk ({})", num_sets);
+            }
+            sets.reserve(num_sets);
+            for _ in 0..num_sets {
+                let id = read_varint(data, &mut cursor)?;
+                let count = read_varint(data, &mut cursor)?;
+                let state = match encoding {
+                    ChunkEncoding::States => read_varint(data, &mut cursor)?,
+                    _ => 0,
+                };
+                if id > i32::MAX as u32 || count > i32::MAX as u32 || state > u16::MAX as u32 {
+                    bail!("Compressed set value out of range");
+                }
+                sets.push(CompressedSet {
+                    id: id as i32,
+                    count: count as i32,
+                    state: state as u16,
+                });
+            }
+        }
+    }
+
+    validate_sets(&sets)?;
+
+    Ok((sets, cursor))
+}
+
+fn read_i32(data: &[u8], cursor: &mut usize) -> Result<i32> {
+    match data.get(*cursor..*cursor + 4) {
+        Some(bytes) => {
+            *cursor += 4;
+            Ok(i32::from_le_bytes(bytes.try_into()?))
+        }
+        None => bail!("Unexpected end of data while reading compressed set"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_types::Vec3;
+    use crate::world::Chunk;
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 4096, 16383, 16384, u32::MAX] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut cursor = 0;
+            assert_eq!(read_varint(&data, &mut cursor).unwrap(), value);
+            assert_eq!(cursor, data.len());
+        }
+
+        let mut cursor = 0;
+        assert!(read_varint(&[0x80, 0x80], &mut cursor).is_err());
+        let mut cursor = 0;
+        assert!(read_varint(&[0xff, 0xff, 0xff, 0xff, 0x7f], &mut cursor).is_err());
+    }
+
+    #[test]
+    fn test_set_encoding_round_trip() {
+        let mut chunk = Chunk::new(Vec3::new(0, 0, 0), 0);
+        for i in 0..4096 {
+            if i % 3 == 0 {
+                chunk.set_block_i(i, 7, i % 2);
+            }
+        }
+        let sets = chunk.compress();
+
+        for encoding in [
+            ChunkEncoding::Fixed,
+            ChunkEncoding::Varint,
+            ChunkEncoding::States,
+        ] {
+            let data = encode_sets(&sets, encoding);
+            let (decoded, consumed) = decode_sets(&data, encoding).unwrap();
+            assert_eq!(consumed, data.len());
+            assert_eq!(decoded.len(), sets.len());
+            for (a, b) in decoded.iter().zip(sets.iter()) {
+                assert_eq!(a.id, b.id);
+                assert_eq!(a.count, b.count);
+                // Only the stateful encoding keeps the states
+                let state = if encoding == ChunkEncoding::States {
+                    b.state
+                } else {
+                    0
+                };
+                assert_eq!(a.state, state);
+            }
+        }
+
+        // Single run of air is 4 bytes instead of 12
+        let air = Chunk::new(Vec3::new(0, 0, 0), 0).compress();
+        assert_eq!(encode_sets(&air, ChunkEncoding::Varint).len(), 4);
+        assert_eq!(encode_sets(&air, ChunkEncoding::Fixed).len(), 12);
+    }
+
+    #[test]
+    fn test_encoding_for_protocol() {
+        assert_eq!(ChunkEncoding::for_protocol(None), ChunkEncoding::Fixed);
+        assert_eq!(ChunkEncoding::for_protocol(Some(1)), ChunkEncoding::Varint);
+        assert_eq!(ChunkEncoding::for_protocol(Some(2)), ChunkEncoding::States);
+        assert_eq!(ChunkEncoding::for_protocol(Some(9)), ChunkEncoding::States);
+        // The server options cap what newer clients get
+        assert_eq!(
+            ChunkEncoding::for_protocol(Some(2)).min(ChunkEncoding::Varint),
+            ChunkEncoding::Varint
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_totals() {
+        let short = vec![CompressedSet {
+            id: 1,
+            count: 4095,
+            state: 0,
+        }];
+        for encoding in [ChunkEncoding::Fixed, ChunkEncoding::Varint] {
+            let data = encode_sets(&short, encoding);
+            assert!(decode_sets(&data, encoding).is_err());
+        }
+
+        let long = vec![
+            CompressedSet {
+                id: 1,
+                count: 4096,
+                state: 0,
+            },
+            CompressedSet {
+                id: 2,
+                count: 1,
+                state: 0,
+            },
+        ];
+        assert!(decode_sets(
+            &encode_sets(&long, ChunkEncoding::Varint),
+            ChunkEncoding::Varint
+        )
+        .is_err());
+
+        // Truncated data
+        let data = encode_sets(
+            &Chunk::new(Vec3::new(0, 0, 0), 2).compress(),
+            ChunkEncoding::Varint,
+        );
+        assert!(decode_sets(&data[..data.len() - 1], ChunkEncoding::Varint).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+use crate::vector_types::Vec3;
+
+/// Errors returned by the world mutation APIs
+#[derive(Debug, PartialEq, Eq)]
+pub enum WorldError {
+    /// The position lies outside of the column or world height
+    OutOfBounds(Vec3<i32>),
+    /// No item is registered with the id
+    UnknownBlock(i32),
+    /// The block with the id has no such state
+    InvalidState(i32, u16),
+    /// Block entities cannot be attached to air
+    EmptyBlock(Vec3<i32>),
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorldError::OutOfBounds(pos) => {
+                write!(f, "position {},{},{} is out of bounds", pos.x, pos.y, pos.z)
+            }
+            WorldError::UnknownBlock(id) => write!(f, "unknown block id {}", id),
+            WorldError::InvalidState(id, state) => {
+                write!(f, "block id {} has no state {}", id, state)
+            }
+            WorldError::EmptyBlock(pos) => {
+                write!(f, "there is no block at {},{},{}", pos.x, pos.y, pos.z)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::script_generator::ScriptGenerator;
+use super::{BiomeMap, ChunkColumn, World, WorldHeight, CHUNK_SIZE};
+use crate::items::ItemManager;
+use crate::scripting::{ScriptError, ScriptLimits};
+use crate::vector_types::{Vec2, Vec3};
+
+/// A block placed by a generator outside of the column it was generating, in world coordinates
+pub struct SpilledBlock {
+    pub position: Vec3<i32>,
+    pub id: i32,
+}
+
+/// The parts of the world a generator may read while filling a column
+pub struct GenerationContext<'a> {
+    /// Position of the column being generated
+    pub position: Vec2<i32>,
+    pub seed: i32,
+    pub height: WorldHeight,
+    pub items: &'a ItemManager,
+    pub biomes: &'a BiomeMap,
+    columns: &'a BTreeMap<i32, BTreeMap<i32, ChunkColumn>>,
+}
+
+impl<'a> GenerationContext<'a> {
+    pub fn new(
+        position: Vec2<i32>,
+        seed: i32,
+        height: WorldHeight,
+        items: &'a ItemManager,
+        biomes: &'a BiomeMap,
+        columns: &'a BTreeMap<i32, BTreeMap<i32, ChunkColumn>>,
+    ) -> GenerationContext<'a> {
+        GenerationContext {
+            position,
+            seed,
+            height,
+            items,
+            biomes,
+            columns,
+        }
+    }
+
+    /// Translates `x`,`z` relative to the column being generated to world coordinates
+    ///
+    /// Returns None if the position is outside of the `i32` range
+    pub fn to_world(&self, x: i32, z: i32) -> Option<(i32, i32)> {
+        let world_x = self.position.x.checked_mul(CHUNK_SIZE)?.checked_add(x)?;
+        let world_z = self.position.y.checked_mul(CHUNK_SIZE)?.checked_add(z)?;
+        Some((world_x, world_z))
+    }
+
+    /// Finds the generated column containing `x`,`z` relative to the column being generated
+    ///
+    /// Returns the column and the position relative to it, never generates a column
+    pub fn find_neighbour(&self, x: i32, z: i32) -> Option<(&'a ChunkColumn, i32, i32)> {
+        let (world_x, world_z) = self.to_world(x, z)?;
+        let col_position = World::world_to_column_position(&Vec2::new(world_x, world_z));
+
+        let col = self.columns.get(&col_position.x)?.get(&col_position.y)?;
+        Some((
+            col,
+            world_x.rem_euclid(CHUNK_SIZE),
+            world_z.rem_euclid(CHUNK_SIZE),
+        ))
+    }
+
+    /// Sets the block at `position` relative to the column being generated
+    ///
+    /// Blocks above or below the column are clipped, blocks beside it are added to `spilled`
+    pub fn place_block(
+        &self,
+        column: &mut ChunkColumn,
+        spilled: &mut Vec<SpilledBlock>,
+        position: Vec3<i32>,
+        id: i32,
+    ) {
+        if (0..CHUNK_SIZE).contains(&position.x) && (0..CHUNK_SIZE).contains(&position.z) {
+            column.set_block(&position, id).ok();
+        } else if let Some((world_x, world_z)) = self.to_world(position.x, position.z) {
+            spilled.push(SpilledBlock {
+                position: Vec3::new(world_x, position.y, world_z),
+                id,
+            });
+        }
+    }
+
+    fn item_id(&self, name: &str) -> Option<i32> {
+        self.items.get_id_by_name(name.to_string())
+    }
+}
+
+/// Seeds the random placement of the feature named `name` at `position`, a column or a cell of
+/// columns, the same for every generation
+pub fn feature_seed(world_seed: i32, name: &str, position: &Vec2<i32>) -> u64 {
+    // FNV-1a keeps the seeds stable between builds, unlike the std hashers
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+    let bytes = name
+        .bytes()
+        .chain(world_seed.to_le_bytes())
+        .chain(position.x.to_le_bytes())
+        .chain(position.y.to_le_bytes());
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+    hash
+}
+
+/// Fills newly created columns with blocks
+pub trait WorldGenerator {
+    /// Fills `column`, which is empty and at `context.position`
+    ///
+    /// Blocks placed outside of the column are returned to be placed once the column is in the world
+    fn generate_column(
+        &self,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+    ) -> Result<Vec<SpilledBlock>, ScriptError>;
+}
+
+/// The generators a world can use, stored in the save
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GeneratorKind {
+    /// Runs the world's column generation script
+    #[default]
+    Script,
+    Flat,
+    /// Generates only air
+    Void,
+    /// Biome based terrain with flowers, like the default script
+    Terrain,
+}
+
+impl GeneratorKind {
+    const ALL: [GeneratorKind; 4] = [
+        GeneratorKind::Script,
+        GeneratorKind::Flat,
+        GeneratorKind::Void,
+        GeneratorKind::Terrain,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GeneratorKind::Script => "script",
+            GeneratorKind::Flat => "flat",
+            GeneratorKind::Void => "void",
+            GeneratorKind::Terrain => "terrain",
+        }
+    }
+
+    /// Gets the id the generator is saved as
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<GeneratorKind> {
+        GeneratorKind::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    /// Creates the generator, the script generator runs the script at `column_script`
+    pub fn create(
+        self,
+        column_script: &str,
+        seed: i32,
+        limits: ScriptLimits,
+    ) -> Result<Box<dyn WorldGenerator>, ScriptError> {
+        Ok(match self {
+            GeneratorKind::Script => Box::new(ScriptGenerator::load(column_script, seed, limits)?),
+            GeneratorKind::Flat => Box::new(FlatGenerator),
+            GeneratorKind::Void => Box::new(VoidGenerator),
+            GeneratorKind::Terrain => Box::new(TerrainGenerator),
+        })
+    }
+}
+
+impl FromStr for GeneratorKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        GeneratorKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = GeneratorKind::ALL.iter().map(|kind| kind.name()).collect();
+                format!(
+                    "unknown generator {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for GeneratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Bedrock, stone and dirt topped with grass at height 64, also used when a script fails
+pub struct FlatGenerator;
+
+impl WorldGenerator for FlatGenerator {
+    fn generate_column(
+        &self,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+    ) -> Result<Vec<SpilledBlock>, ScriptError> {
+        let bottom = context.height.min_block_y();
+        let surface = 64.clamp(bottom + 4, context.height.max_block_y());
+        let layers = [
+            ("Bedrock", bottom, bottom),
+            ("Stone", bottom + 1, surface - 4),
+            ("Dirt", surface - 3, surface - 1),
+            ("Grass", surface, surface),
+        ];
+        for (name, lower, upper) in layers {
+            if let Some(id) = context.item_id(name) {
+                column.set_layers(lower, upper, id).ok();
+            }
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+pub struct VoidGenerator;
+
+impl WorldGenerator for VoidGenerator {
+    fn generate_column(
+        &self,
+        _context: &GenerationContext,
+        _column: &mut ChunkColumn,
+    ) -> Result<Vec<SpilledBlock>, ScriptError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Native version of the default generation script, shaped by the biomes of the world
+pub struct TerrainGenerator;
+
+impl TerrainGenerator {
+    /// Creates the random number generator for flora, the same for every generation of a column
+    fn column_rng(seed: i32, position: &Vec2<i32>) -> StdRng {
+        let seed = (seed as u32 as u64)
+            ^ (position.x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (position.y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        StdRng::seed_from_u64(seed)
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn generate_column(
+        &self,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+    ) -> Result<Vec<SpilledBlock>, ScriptError> {
+        let id = |name| context.item_id(name).unwrap_or(0);
+        let stone = id("Stone");
+
+        // Everything below the lowest surface of any biome is stone
+        let bottom = context.height.min_block_y();
+        let lowest_surface = context
+            .biomes
+            .get_biomes()
+            .iter()
+            .map(|biome| biome.min_height);
+        let stone_top = (lowest_surface.min().unwrap_or(0) - 10).min(context.height.max_block_y());
+        column.set_layers(bottom, bottom, id("Bedrock")).ok();
+        column.set_layers(bottom + 1, stone_top, stone).ok();
+
+        let mut rng = TerrainGenerator::column_rng(context.seed, &context.position);
+        let mut spilled = Vec::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let (world_x, world_z) = match context.to_world(x, z) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                let biome = context.biomes.get_biome(world_x, world_z);
+                let surface = context.biomes.surface_height(world_x, world_z);
+                for y in (stone_top + 1)..=surface {
+                    let id = match surface - y {
+                        0 => biome.surface_block,
+                        depth if depth <= biome.filler_depth => biome.filler_block,
+                        _ => stone,
+                    };
+                    context.place_block(column, &mut spilled, Vec3::new(x, y, z), id);
+                }
+
+                if !biome.flowers.is_empty() && rng.gen_bool(biome.flower_density) {
+                    let flower = biome.flowers[rng.gen_range(0..biome.flowers.len())];
+                    let position = Vec3::new(x, surface + 1, z);
+                    context.place_block(column, &mut spilled, position, flower);
+                }
+            }
+        }
+
+        Ok(spilled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_seeds_are_stable() {
+        let cell = Vec2::new(3, -7);
+        assert_eq!(
+            feature_seed(1, "tree", &cell),
+            feature_seed(1, "tree", &cell)
+        );
+        assert_ne!(
+            feature_seed(1, "tree", &cell),
+            feature_seed(2, "tree", &cell)
+        );
+        assert_ne!(
+            feature_seed(1, "tree", &cell),
+            feature_seed(1, "ruin", &cell)
+        );
+        assert_ne!(
+            feature_seed(1, "tree", &cell),
+            feature_seed(1, "tree", &Vec2::new(3, -6))
+        );
+    }
+}
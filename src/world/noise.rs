@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use fast_noise_lite_rs::{DomainWarpType, FastNoiseLite, FractalType, NoiseType};
+use rlua::Table;
+
+/// Names of the noise types, also the names of the noise functions every world starts with
+const NOISE_TYPES: [&str; 6] = [
+    "OpenSimplex2",
+    "OpenSimplex2S",
+    "Cellular",
+    "Perlin",
+    "ValueCubic",
+    "Value",
+];
+/// Most octaves a fractal noise may have
+const MAX_OCTAVES: usize = 16;
+
+/// A noise generator, optionally sampled at domain warped coordinates
+pub struct NamedNoise {
+    noise: FastNoiseLite,
+    warp: Option<FastNoiseLite>,
+}
+
+impl NamedNoise {
+    /// Creates noise with the default settings of `noise_type`
+    fn new(seed: i32, noise_type: NoiseType) -> NamedNoise {
+        let mut noise = FastNoiseLite::new(seed);
+        noise.set_noise_type(noise_type);
+        NamedNoise { noise, warp: None }
+    }
+
+    /// Creates noise from a generator script's settings table
+    ///
+    /// Every field is optional: `type`, `seed_offset`, `frequency`, `fractal`, `octaves`,
+    /// `lacunarity`, `gain` and `warp`, a table with `type`, `amplitude`, `seed_offset`,
+    /// `frequency`, `fractal`, `octaves`, `lacunarity` and `gain`
+    pub fn from_table(world_seed: i32, settings: &Table) -> rlua::Result<NamedNoise> {
+        let noise_type: Option<String> = settings.get("type")?;
+        let noise_type = match noise_type {
+            Some(name) => parse_noise_type(&name)?,
+            None => NoiseType::OpenSimplex2,
+        };
+        let mut noise = NamedNoise::new(world_seed, noise_type);
+        configure(&mut noise.noise, world_seed, settings, parse_fractal_type)?;
+
+        let warp: Option<Table> = settings.get("warp")?;
+        if let Some(warp_settings) = warp {
+            let mut warp = FastNoiseLite::new(world_seed);
+            let warp_type: Option<String> = warp_settings.get("type")?;
+            if let Some(name) = warp_type {
+                warp.set_domain_warp_type(parse_domain_warp_type(&name)?);
+            }
+            let amplitude: Option<f32> = warp_settings.get("amplitude")?;
+            warp.set_domain_warp_amp(amplitude.unwrap_or(1.0));
+            configure(
+                &mut warp,
+                world_seed,
+                &warp_settings,
+                parse_warp_fractal_type,
+            )?;
+            noise.warp = Some(warp);
+        }
+
+        Ok(noise)
+    }
+
+    pub fn get_2d(&self, mut x: f32, mut y: f32) -> f32 {
+        if let Some(warp) = &self.warp {
+            warp.domain_warp_2d(&mut x, &mut y);
+        }
+        self.noise.get_noise_2d(x, y)
+    }
+
+    pub fn get_3d(&self, mut x: f32, mut y: f32, mut z: f32) -> f32 {
+        if let Some(warp) = &self.warp {
+            warp.domain_warp_3d(&mut x, &mut y, &mut z);
+        }
+        self.noise.get_noise_3d(x, y, z)
+    }
+}
+
+/// Creates the noise functions every world starts with, one per noise type with default settings
+pub fn default_noise_functions(world_seed: i32) -> HashMap<String, NamedNoise> {
+    NOISE_TYPES
+        .iter()
+        .map(|name| {
+            let noise_type = parse_noise_type(name).unwrap();
+            (name.to_string(), NamedNoise::new(world_seed, noise_type))
+        })
+        .collect()
+}
+
+/// Applies the seed, frequency and fractal settings shared by noise and domain warps
+fn configure(
+    noise: &mut FastNoiseLite,
+    world_seed: i32,
+    settings: &Table,
+    parse_fractal: fn(&str) -> rlua::Result<FractalType>,
+) -> rlua::Result<()> {
+    let seed_offset: Option<i32> = settings.get("seed_offset")?;
+    noise.set_seed(world_seed.wrapping_add(seed_offset.unwrap_or(0)));
+
+    let frequency: Option<f32> = settings.get("frequency")?;
+    if let Some(frequency) = frequency {
+        if !frequency.is_finite() {
+            return Err(invalid_setting("frequency must be a finite number"));
+        }
+        noise.set_frequency(frequency);
+    }
+
+    let fractal: Option<String> = settings.get("fractal")?;
+    if let Some(name) = fractal {
+        noise.set_fractal_type(parse_fractal(&name)?);
+    }
+    let octaves: Option<usize> = settings.get("octaves")?;
+    if let Some(octaves) = octaves {
+        if !(1..=MAX_OCTAVES).contains(&octaves) {
+            return Err(invalid_setting(&format!(
+                "octaves must be between 1 and {}",
+                MAX_OCTAVES
+            )));
+        }
+        noise.set_fractal_octaves(octaves);
+    }
+    let lacunarity: Option<f32> = settings.get("lacunarity")?;
+    if let Some(lacunarity) = lacunarity {
+        noise.set_fractal_lacunarity(lacunarity);
+    }
+    let gain: Option<f32> = settings.get("gain")?;
+    if let Some(gain) = gain {
+        noise.set_fractal_gain(gain);
+    }
+
+    Ok(())
+}
+
+fn invalid_setting(message: &str) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("invalid noise settings: {}", message))
+}
+
+fn parse_noise_type(name: &str) -> rlua::Result<NoiseType> {
+    match name {
+        "OpenSimplex2" => Ok(NoiseType::OpenSimplex2),
+        "OpenSimplex2S" => Ok(NoiseType::OpenSimplex2S),
+        "Cellular" => Ok(NoiseType::Cellular),
+        "Perlin" => Ok(NoiseType::Perlin),
+        "ValueCubic" => Ok(NoiseType::ValueCubic),
+        "Value" => Ok(NoiseType::Value),
+        _ => Err(invalid_setting(&format!(
+            "unknown noise type {}, expected one of {}",
+            name,
+            NOISE_TYPES.join(", ")
+        ))),
+    }
+}
+
+fn parse_fractal_type(name: &str) -> rlua::Result<FractalType> {
+    match name {
+        "None" => Ok(FractalType::None),
+        "FBm" => Ok(FractalType::FBm),
+        "Ridged" => Ok(FractalType::Ridged),
+        "PingPong" => Ok(FractalType::PingPong),
+        _ => Err(invalid_setting(&format!(
+            "unknown fractal type {}, expected None, FBm, Ridged or PingPong",
+            name
+        ))),
+    }
+}
+
+fn parse_warp_fractal_type(name: &str) -> rlua::Result<FractalType> {
+    match name {
+        "None" => Ok(FractalType::None),
+        "DomainWarpProgressive" => Ok(FractalType::DomainWarpProgressive),
+        "DomainWarpIndependent" => Ok(FractalType::DomainWarpIndependent),
+        _ => Err(invalid_setting(&format!(
+            "unknown warp fractal type {}, expected None, DomainWarpProgressive or DomainWarpIndependent",
+            name
+        ))),
+    }
+}
+
+fn parse_domain_warp_type(name: &str) -> rlua::Result<DomainWarpType> {
+    match name {
+        "OpenSimplex2" => Ok(DomainWarpType::OpenSimplex2),
+        "OpenSimplex2Reduced" => Ok(DomainWarpType::OpenSimplex2Reduced),
+        "BasicGrid" => Ok(DomainWarpType::BasicGrid),
+        _ => Err(invalid_setting(&format!(
+            "unknown warp type {}, expected OpenSimplex2, OpenSimplex2Reduced or BasicGrid",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlua::Lua;
+
+    fn noise_from_source(seed: i32, source: &str) -> rlua::Result<NamedNoise> {
+        let lua = Lua::new();
+        lua.context(|lua_ctx| {
+            let settings: Table = lua_ctx.load(source).eval()?;
+            NamedNoise::from_table(seed, &settings)
+        })
+    }
+
+    #[test]
+    fn test_noise_settings() {
+        let defaults = default_noise_functions(7);
+        assert_eq!(defaults.len(), NOISE_TYPES.len());
+
+        // No settings gives the same noise as the default OpenSimplex2
+        let noise = noise_from_source(7, "return {}").unwrap();
+        assert_eq!(
+            noise.get_2d(12.5, -3.0),
+            defaults["OpenSimplex2"].get_2d(12.5, -3.0)
+        );
+
+        let offset = noise_from_source(7, "return { seed_offset = 1 }").unwrap();
+        let next_seed = noise_from_source(8, "return {}").unwrap();
+        assert_eq!(
+            offset.get_3d(1.0, 2.0, 3.0),
+            next_seed.get_3d(1.0, 2.0, 3.0)
+        );
+
+        let layered = noise_from_source(
+            7,
+            r#"return {
+                type = "Perlin", frequency = 0.05, fractal = "FBm", octaves = 5,
+                lacunarity = 2.5, gain = 0.4,
+                warp = { type = "BasicGrid", amplitude = 20, fractal = "DomainWarpProgressive" }
+            }"#,
+        )
+        .unwrap();
+        for i in 0..100 {
+            let value = layered.get_3d(i as f32 * 3.1, i as f32, -(i as f32));
+            assert!((-1.5..=1.5).contains(&value));
+        }
+    }
+
+    #[test]
+    fn test_invalid_noise_settings() {
+        for source in [
+            "return { type = \"Simplex\" }",
+            "return { fractal = \"DomainWarpProgressive\" }",
+            "return { octaves = 0 }",
+            "return { octaves = 100 }",
+            "return { frequency = 1 / 0 }",
+            "return { warp = { type = \"Perlin\" } }",
+            "return { warp = { fractal = \"FBm\" } }",
+            "return { frequency = \"high\" }",
+        ] {
+            assert!(noise_from_source(0, source).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rlua::{Lua, Table};
+
+use super::generator::{feature_seed, GenerationContext, SpilledBlock, WorldGenerator};
+use super::noise::{default_noise_functions, NamedNoise};
+use super::{ChunkColumn, CHUNK_SIZE};
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+use crate::vector_types::Vec3;
+
+/// Generates columns by running a Lua script for every column
+pub struct ScriptGenerator {
+    lua: Lua,
+    limits: ScriptLimits,
+    script_path: String,
+    script: String,
+    /// Noise available to the script, extended by the script with `create_noise`
+    noise_functions: RefCell<HashMap<String, NamedNoise>>,
+}
+
+impl ScriptGenerator {
+    /// Reads and compiles the script at `path`
+    pub fn load(
+        path: &str,
+        seed: i32,
+        limits: ScriptLimits,
+    ) -> Result<ScriptGenerator, ScriptError> {
+        let script = fs::read_to_string(path)
+            .map_err(|e| ScriptError::new(path, format!("unable to read script: {}", e)))?;
+        ScriptGenerator::from_source(path, script, seed, limits)
+    }
+
+    /// Compiles `script`, errors are reported as coming from `path`
+    pub fn from_source(
+        path: &str,
+        script: String,
+        seed: i32,
+        limits: ScriptLimits,
+    ) -> Result<ScriptGenerator, ScriptError> {
+        // Compile up front so a syntax error is reported before any column is generated
+        let lua = new_sandboxed_lua(&limits);
+        lua.context(|lua_ctx| {
+            lua_ctx
+                .load(&script)
+                .set_name(&chunk_name(path))?
+                .into_function()
+                .map(|_| ())
+        })
+        .map_err(|e| ScriptError::from_lua(path, &e))?;
+
+        Ok(ScriptGenerator {
+            lua,
+            limits,
+            script_path: path.to_string(),
+            script,
+            noise_functions: RefCell::new(default_noise_functions(seed)),
+        })
+    }
+
+    fn unknown_noise(name: &str) -> rlua::Error {
+        rlua::Error::RuntimeError(format!("Noise function {} does not exist", name))
+    }
+}
+
+impl WorldGenerator for ScriptGenerator {
+    fn generate_column(
+        &self,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+    ) -> Result<Vec<SpilledBlock>, ScriptError> {
+        let pos = context.position;
+        let world_height = context.height;
+        let col_ptr = column as *mut ChunkColumn;
+
+        let mut spilled = Vec::<SpilledBlock>::new();
+        let spilled_ptr = &mut spilled as *mut Vec<SpilledBlock>;
+        // Seeded per column so generating a column again gives the same result
+        let rng = RefCell::new(StdRng::seed_from_u64(feature_seed(
+            context.seed,
+            "script",
+            &pos,
+        )));
+
+        run_limited(&self.lua, &self.limits, |lua_ctx| {
+            let globals = lua_ctx.globals(); // Get globals from lua
+
+            lua_ctx.scope(|scope| {
+                lua_ctx
+                    .load(&format!(
+                        r#"
+                        column_x = {}
+                        column_z = {}
+                        min_build_height = {}
+                        max_build_height = {}
+                    "#,
+                        pos.x,
+                        pos.y,
+                        world_height.min_block_y(),
+                        world_height.max_block_y() + 1
+                    ))
+                    .set_name("Generate column variables")?
+                    .exec()?;
+
+                let rust_random =
+                    scope.create_function(|_, (): ()| Ok(rng.borrow_mut().gen::<i32>()))?;
+                globals.set("random", rust_random)?;
+
+                let get_id_by_name = scope.create_function_mut(|_, item_name: String| {
+                    let id = context.items.get_id_by_name(item_name);
+                    Ok(id)
+                })?;
+                globals.set("get_id_by_name", get_id_by_name)?;
+
+                let create_noise =
+                    scope.create_function(|_, (name, settings): (String, Table)| {
+                        let noise = NamedNoise::from_table(context.seed, &settings)?;
+                        self.noise_functions.borrow_mut().insert(name, noise);
+                        Ok(())
+                    })?;
+                globals.set("create_noise", create_noise)?;
+
+                let get_noise_2d =
+                    scope.create_function(|_, (name, x, y): (String, f32, f32)| {
+                        match self.noise_functions.borrow().get(name.as_str()) {
+                            Some(noise) => Ok(noise.get_2d(x, y)),
+                            None => Err(ScriptGenerator::unknown_noise(&name)),
+                        }
+                    })?;
+                globals.set("get_noise_2d", get_noise_2d)?;
+
+                let get_noise_3d = scope.create_function(
+                    |_, (name, x, y, z): (String, f32, f32, f32)| match self
+                        .noise_functions
+                        .borrow()
+                        .get(name.as_str())
+                    {
+                        Some(noise) => Ok(noise.get_3d(x, y, z)),
+                        None => Err(ScriptGenerator::unknown_noise(&name)),
+                    },
+                )?;
+                globals.set("get_noise_3d", get_noise_3d)?;
+
+                let set_block =
+                    scope.create_function(|_, (x, y, z, id): (i32, i32, i32, i32)| {
+                        unsafe {
+                            context.place_block(
+                                &mut *col_ptr,
+                                &mut *spilled_ptr,
+                                Vec3::new(x, y, z),
+                                id,
+                            );
+                        }
+                        Ok(())
+                    })?;
+                globals.set("set_block", set_block)?;
+
+                // Reads see the column being generated and neighbours that were already generated
+                let get_block = scope.create_function(|_, (x, y, z): (i32, i32, i32)| {
+                    if (0..CHUNK_SIZE).contains(&x) && (0..CHUNK_SIZE).contains(&z) {
+                        Ok(unsafe { (*col_ptr).get_block(&Vec3::new(x, y, z)) }.ok())
+                    } else {
+                        Ok(context
+                            .find_neighbour(x, z)
+                            .and_then(|(col, x, z)| col.get_block(&Vec3::new(x, y, z)).ok()))
+                    }
+                })?;
+                globals.set("get_block", get_block)?;
+
+                let get_height = scope.create_function(|_, (x, z): (i32, i32)| {
+                    if (0..CHUNK_SIZE).contains(&x) && (0..CHUNK_SIZE).contains(&z) {
+                        Ok(unsafe { (*col_ptr).get_height(x, z) })
+                    } else {
+                        Ok(context
+                            .find_neighbour(x, z)
+                            .and_then(|(col, x, z)| col.get_height(x, z)))
+                    }
+                })?;
+                globals.set("get_height", get_height)?;
+
+                let get_biome =
+                    scope.create_function(|lua_ctx, (x, z): (i32, i32)| {
+                        match context.to_world(x, z) {
+                            Some((x, z)) => {
+                                context.biomes.get_biome(x, z).to_table(lua_ctx).map(Some)
+                            }
+                            None => Ok(None),
+                        }
+                    })?;
+                globals.set("get_biome", get_biome)?;
+
+                let get_surface_height = scope.create_function(|_, (x, z): (i32, i32)| {
+                    Ok(context
+                        .to_world(x, z)
+                        .map(|(x, z)| context.biomes.surface_height(x, z)))
+                })?;
+                globals.set("get_surface_height", get_surface_height)?;
+
+                let set_layers =
+                    scope.create_function(|_, (lower, upper, id): (i32, i32, i32)| {
+                        unsafe { (*col_ptr).set_layers(lower, upper, id) }
+                            .map_err(rlua::Error::external)
+                    })?;
+                globals.set("set_layers", set_layers)?;
+
+                lua_ctx
+                    .load(&self.script)
+                    .set_name(&chunk_name(&self.script_path))?
+                    .exec()
+            })
+        })
+        .map_err(|e| ScriptError::from_lua(&self.script_path, &e))?;
+
+        Ok(spilled)
+    }
+}
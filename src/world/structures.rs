@@ -0,0 +1,467 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rlua::{Function, Lua, RegistryKey, Table};
+
+use super::generator::{feature_seed, GenerationContext, SpilledBlock};
+use super::{ChunkColumn, CHUNK_SIZE};
+use crate::items::ItemManager;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+use crate::vector_types::{Vec2, Vec3};
+
+/// Widest a structure may reach from its origin, in blocks
+const MAX_STRUCTURE_RADIUS: i32 = 32;
+
+/// How a structure is built
+enum StructureShape {
+    /// Blocks relative to the origin of the structure
+    Template(Vec<(Vec3<i32>, i32)>),
+    /// A Lua function building the structure with `structure.place`
+    Script(RegistryKey),
+}
+
+/// A structure registered by a structure script with `register_structure`
+struct Structure {
+    name: String,
+    /// Path of the script that registered the structure
+    script: String,
+    shape: StructureShape,
+    /// Furthest block from the origin along x or z
+    radius: i32,
+    /// Size in blocks of the square cells the world is divided into, each holds at most one instance
+    spacing: i32,
+    /// Chance of a cell holding an instance
+    chance: f64,
+    /// Biomes the structure is placed in, every biome if empty
+    biomes: Vec<String>,
+    /// Blocks the structure is placed on, any block if empty
+    surfaces: Vec<i32>,
+}
+
+/// Where an instance of a structure is placed, computed from the world seed and its cell alone
+struct StructureInstance {
+    /// World position of the origin, placed on top of the highest block
+    origin: Vec2<i32>,
+    seed: u64,
+}
+
+/// Structures placed on top of generated columns in registration order
+///
+/// Instances never overlap instances of the same structure or of structures registered before
+pub struct Structures {
+    lua: Lua,
+    limits: ScriptLimits,
+    structures: Vec<Structure>,
+}
+
+impl Structures {
+    /// Runs the structure scripts at `paths` in order, a structure registered again replaces the earlier one
+    pub fn load(
+        paths: &[String],
+        items: &ItemManager,
+        limits: &ScriptLimits,
+    ) -> Result<Structures, ScriptError> {
+        let lua = new_sandboxed_lua(limits);
+        let ids: HashMap<String, i32> = (0..)
+            .map_while(|id| items.get_item_by_id(id).map(|item| (item.name.clone(), id)))
+            .collect();
+
+        lua.context(|lua_ctx| {
+            let get_id_by_name =
+                lua_ctx.create_function(move |_, name: String| Ok(ids.get(&name).copied()))?;
+            lua_ctx.globals().set("get_id_by_name", get_id_by_name)
+        })
+        .map_err(|e| ScriptError::new("structure scripts", e.to_string()))?;
+
+        let mut structures: Vec<Structure> = Vec::new();
+        for path in paths {
+            let script = fs::read_to_string(path)
+                .map_err(|e| ScriptError::new(path, format!("unable to read script: {}", e)))?;
+
+            run_limited(&lua, limits, |lua_ctx| {
+                lua_ctx.scope(|scope| {
+                    let register_structure =
+                        scope.create_function_mut(|lua_ctx, table: Table| {
+                            let structure = Structure::from_table(lua_ctx, &table, path, items)?;
+                            match structures
+                                .iter_mut()
+                                .find(|other| other.name == structure.name)
+                            {
+                                Some(other) => *other = structure,
+                                None => structures.push(structure),
+                            }
+                            Ok(())
+                        })?;
+                    lua_ctx
+                        .globals()
+                        .set("register_structure", register_structure)?;
+
+                    lua_ctx.load(&script).set_name(&chunk_name(path))?.exec()
+                })
+            })
+            .map_err(|e| ScriptError::from_lua(path, &e))?;
+        }
+
+        Ok(Structures {
+            lua,
+            limits: *limits,
+            structures,
+        })
+    }
+
+    /// Places the instances whose origin is in `column`, which was just generated
+    ///
+    /// Blocks outside of the column are returned, structures that fail are reported and skipped
+    pub fn place(
+        &self,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+    ) -> Vec<SpilledBlock> {
+        let mut spilled = Vec::new();
+        let (column_x, column_z) = match context.to_world(0, 0) {
+            Some(position) => position,
+            None => return spilled,
+        };
+
+        for (index, structure) in self.structures.iter().enumerate() {
+            let first_cell = Vec2::new(
+                column_x.div_euclid(structure.spacing),
+                column_z.div_euclid(structure.spacing),
+            );
+            let last_cell = Vec2::new(
+                (column_x + CHUNK_SIZE - 1).div_euclid(structure.spacing),
+                (column_z + CHUNK_SIZE - 1).div_euclid(structure.spacing),
+            );
+            for cell_x in first_cell.x..=last_cell.x {
+                for cell_z in first_cell.y..=last_cell.y {
+                    let instance = match self.instance(index, context, Vec2::new(cell_x, cell_z)) {
+                        Some(instance) => instance,
+                        None => continue,
+                    };
+                    let x = instance.origin.x - column_x;
+                    let z = instance.origin.y - column_z;
+                    let in_column = (0..CHUNK_SIZE).contains(&x) && (0..CHUNK_SIZE).contains(&z);
+                    if !in_column || self.overlaps_earlier(index, context, &instance) {
+                        continue;
+                    }
+                    let height = match column.get_height(x, z) {
+                        Some(height) => height,
+                        None => continue,
+                    };
+                    let surface = column.get_block(&Vec3::new(x, height, z)).unwrap_or(0);
+                    if !structure.surfaces.is_empty() && !structure.surfaces.contains(&surface) {
+                        continue;
+                    }
+
+                    let origin = Vec3::new(x, height + 1, z);
+                    if let Err(e) = self.build(
+                        structure,
+                        context,
+                        column,
+                        &mut spilled,
+                        origin,
+                        instance.seed,
+                    ) {
+                        eprintln!(
+                            "Structure {} failed at {},{}: {}",
+                            structure.name, instance.origin.x, instance.origin.y, e
+                        );
+                    }
+                }
+            }
+        }
+
+        spilled
+    }
+
+    /// Gets the instance of the structure at `index` in `cell`, if the cell holds one
+    ///
+    /// Overlap with other structures is not checked
+    fn instance(
+        &self,
+        index: usize,
+        context: &GenerationContext,
+        cell: Vec2<i32>,
+    ) -> Option<StructureInstance> {
+        let structure = &self.structures[index];
+        let mut rng = StdRng::seed_from_u64(feature_seed(context.seed, &structure.name, &cell));
+        if !rng.gen_bool(structure.chance) {
+            return None;
+        }
+
+        // The footprint of the instance stays inside its cell
+        let offset_range = structure.radius..(structure.spacing - structure.radius);
+        let origin = Vec2::new(
+            (cell.x * structure.spacing).checked_add(rng.gen_range(offset_range.clone()))?,
+            (cell.y * structure.spacing).checked_add(rng.gen_range(offset_range))?,
+        );
+        if !structure.biomes.is_empty() {
+            let biome = context.biomes.get_biome(origin.x, origin.y);
+            if !structure.biomes.contains(&biome.name) {
+                return None;
+            }
+        }
+
+        Some(StructureInstance {
+            origin,
+            seed: rng.gen(),
+        })
+    }
+
+    /// Returns whether `instance` of the structure at `index` overlaps a structure registered before
+    fn overlaps_earlier(
+        &self,
+        index: usize,
+        context: &GenerationContext,
+        instance: &StructureInstance,
+    ) -> bool {
+        let radius = self.structures[index].radius;
+        for (other_index, other) in self.structures[..index].iter().enumerate() {
+            let reach = radius + other.radius;
+            let first_cell = Vec2::new(
+                instance
+                    .origin
+                    .x
+                    .saturating_sub(reach)
+                    .div_euclid(other.spacing),
+                instance
+                    .origin
+                    .y
+                    .saturating_sub(reach)
+                    .div_euclid(other.spacing),
+            );
+            let last_cell = Vec2::new(
+                instance
+                    .origin
+                    .x
+                    .saturating_add(reach)
+                    .div_euclid(other.spacing),
+                instance
+                    .origin
+                    .y
+                    .saturating_add(reach)
+                    .div_euclid(other.spacing),
+            );
+            for cell_x in first_cell.x..=last_cell.x {
+                for cell_z in first_cell.y..=last_cell.y {
+                    let overlaps = self
+                        .instance(other_index, context, Vec2::new(cell_x, cell_z))
+                        .is_some_and(|other| {
+                            (other.origin.x - instance.origin.x).abs() <= reach
+                                && (other.origin.y - instance.origin.y).abs() <= reach
+                        });
+                    if overlaps {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Places the blocks of `structure` around `origin`, relative to the column being generated
+    fn build(
+        &self,
+        structure: &Structure,
+        context: &GenerationContext,
+        column: &mut ChunkColumn,
+        spilled: &mut Vec<SpilledBlock>,
+        origin: Vec3<i32>,
+        seed: u64,
+    ) -> Result<(), ScriptError> {
+        let key = match &structure.shape {
+            StructureShape::Template(blocks) => {
+                for (offset, id) in blocks {
+                    let position = Vec3::new(
+                        origin.x + offset.x,
+                        origin.y.saturating_add(offset.y),
+                        origin.z + offset.z,
+                    );
+                    context.place_block(column, spilled, position, *id);
+                }
+                return Ok(());
+            }
+            StructureShape::Script(key) => key,
+        };
+
+        let column = RefCell::new(column);
+        let spilled = RefCell::new(spilled);
+        let rng = RefCell::new(StdRng::seed_from_u64(seed));
+        run_limited(&self.lua, &self.limits, |lua_ctx| {
+            lua_ctx.scope(|scope| {
+                let api = lua_ctx.create_table()?;
+                api.set("x", origin.x)?;
+                api.set("y", origin.y)?;
+                api.set("z", origin.z)?;
+                api.set(
+                    "place",
+                    scope.create_function(|_, (x, y, z, id): (i32, i32, i32, i32)| {
+                        if x.abs() > structure.radius || z.abs() > structure.radius {
+                            return Err(rlua::Error::RuntimeError(format!(
+                                "block {},{} is outside of the structure radius {}",
+                                x, z, structure.radius
+                            )));
+                        }
+                        let position =
+                            Vec3::new(origin.x + x, origin.y.saturating_add(y), origin.z + z);
+                        context.place_block(
+                            &mut column.borrow_mut(),
+                            &mut spilled.borrow_mut(),
+                            position,
+                            id,
+                        );
+                        Ok(())
+                    })?,
+                )?;
+                api.set(
+                    "random",
+                    scope.create_function(|_, (min, max): (i32, i32)| {
+                        if min > max {
+                            return Err(rlua::Error::RuntimeError(
+                                "random range is empty".to_string(),
+                            ));
+                        }
+                        Ok(rng.borrow_mut().gen_range(min..=max))
+                    })?,
+                )?;
+
+                let generate: Function = lua_ctx.registry_value(key)?;
+                generate.call::<_, ()>(api)
+            })
+        })
+        .map_err(|e| ScriptError::from_lua(&structure.script, &e))
+    }
+}
+
+impl Structure {
+    /// Reads a structure from the table passed to `register_structure`
+    fn from_table<'lua>(
+        lua_ctx: rlua::Context<'lua>,
+        table: &Table<'lua>,
+        script: &str,
+        items: &ItemManager,
+    ) -> rlua::Result<Structure> {
+        let name: String = table.get("name")?;
+        let invalid = |message: &str| {
+            rlua::Error::RuntimeError(format!("invalid structure {}: {}", name, message))
+        };
+
+        let blocks: Option<Vec<Table>> = table.get("blocks")?;
+        let generate: Option<Function> = table.get("generate")?;
+        let (shape, radius) = match (blocks, generate) {
+            (Some(blocks), None) => {
+                let mut template = Vec::new();
+                for block in blocks {
+                    let (x, y, z, block_name): (i32, i32, i32, String) =
+                        (block.get(1)?, block.get(2)?, block.get(3)?, block.get(4)?);
+                    let id = items
+                        .get_id_by_name(block_name.clone())
+                        .ok_or_else(|| invalid(&format!("unknown block {}", block_name)))?;
+                    template.push((Vec3::new(x, y, z), id));
+                }
+                let radius = template
+                    .iter()
+                    .map(|(offset, _)| offset.x.abs().max(offset.z.abs()))
+                    .max()
+                    .unwrap_or(0);
+                (StructureShape::Template(template), radius)
+            }
+            (None, Some(generate)) => {
+                let radius: Option<i32> = table.get("radius")?;
+                let key = lua_ctx.create_registry_value(generate)?;
+                (StructureShape::Script(key), radius.unwrap_or(0))
+            }
+            _ => return Err(invalid("needs either blocks or a generate function")),
+        };
+        if !(0..=MAX_STRUCTURE_RADIUS).contains(&radius) {
+            return Err(invalid(&format!(
+                "radius must be between 0 and {}",
+                MAX_STRUCTURE_RADIUS
+            )));
+        }
+
+        let spacing: Option<i32> = table.get("spacing")?;
+        let spacing = spacing.unwrap_or(radius * 2 + 1);
+        if spacing < radius * 2 + 1 {
+            return Err(invalid(
+                "spacing must be larger than the width of the structure",
+            ));
+        }
+        let chance: Option<f64> = table.get("chance")?;
+        let chance = chance.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&chance) {
+            return Err(invalid("chance must be between 0 and 1"));
+        }
+        let biomes: Option<Vec<String>> = table.get("biomes")?;
+        let surfaces: Option<Vec<String>> = table.get("surfaces")?;
+        let surfaces = surfaces
+            .unwrap_or_default()
+            .into_iter()
+            .map(|block| {
+                items
+                    .get_id_by_name(block.clone())
+                    .ok_or_else(|| invalid(&format!("unknown block {}", block)))
+            })
+            .collect::<rlua::Result<_>>()?;
+
+        Ok(Structure {
+            name: name.clone(),
+            script: script.to_string(),
+            shape,
+            radius,
+            spacing,
+            chance,
+            biomes: biomes.unwrap_or_default(),
+            surfaces,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::default_items;
+    use crate::scripting::with_script_file;
+
+    fn load_structures(script: &str) -> Result<Structures, ScriptError> {
+        let items = default_items();
+        with_script_file(script, |paths| {
+            Structures::load(paths, &items, &ScriptLimits::default())
+        })
+    }
+
+    #[test]
+    fn test_default_structures_load() {
+        let script = fs::read_to_string("./default_scripts/structures.lua").unwrap();
+        let structures = load_structures(&script).unwrap();
+        assert_eq!(structures.structures.len(), 4);
+
+        let replaced = format!(
+            "{}\nregister_structure({{ name = \"tree\", blocks = {{ {{ 0, 0, 0, \"Log\" }} }} }})",
+            script
+        );
+        let structures = load_structures(&replaced).unwrap();
+        assert_eq!(structures.structures.len(), 4);
+        assert_eq!(structures.structures[2].radius, 0);
+    }
+
+    #[test]
+    fn test_invalid_structures() {
+        for structure in [
+            "{ name = \"a\" }",
+            "{ name = \"a\", blocks = { { 0, 0, 0, \"Nope\" } } }",
+            "{ name = \"a\", blocks = {}, generate = function() end }",
+            "{ name = \"a\", blocks = { { 40, 0, 0, \"Stone\" } } }",
+            "{ name = \"a\", blocks = { { 2, 0, 0, \"Stone\" } }, spacing = 4 }",
+            "{ name = \"a\", generate = function() end, radius = -1 }",
+            "{ name = \"a\", generate = function() end, chance = 2 }",
+            "{ name = \"a\", generate = function() end, surfaces = { \"Nope\" } }",
+        ] {
+            let error = load_structures(&format!("register_structure({})", structure));
+            assert!(error.is_err(), "{}", structure);
+        }
+    }
+}
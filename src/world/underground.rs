@@ -0,0 +1,358 @@
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rlua::Table;
+
+use super::generator::{feature_seed, GenerationContext};
+use super::noise::NamedNoise;
+use super::{ChunkColumn, CHUNK_SIZE};
+use crate::items::ItemManager;
+use crate::scripting::{chunk_name, new_sandboxed_lua, run_limited, ScriptError, ScriptLimits};
+use crate::vector_types::Vec3;
+
+/// Distance in blocks between the cave noise samples, the blocks between them are interpolated
+const CAVE_SAMPLE_STEP: i32 = 4;
+/// Most blocks a single ore vein may have
+const MAX_VEIN_SIZE: i32 = 64;
+/// Most ore veins of one kind in a column on average
+const MAX_VEINS_PER_COLUMN: f64 = 64.0;
+
+/// Caves carved where 3D noise is above a threshold, set by `set_caves`
+struct Caves {
+    noise: NamedNoise,
+    threshold: f32,
+    min_height: i32,
+    max_height: i32,
+    /// Blocks the caves are carved into
+    replaces: Vec<i32>,
+}
+
+/// An ore registered by an underground script with `register_ore`
+struct Ore {
+    name: String,
+    block: i32,
+    min_height: i32,
+    max_height: i32,
+    /// Blocks in a vein, blocks that would replace anything but `replaces` are skipped
+    vein_size: i32,
+    /// Veins in a column on average
+    frequency: f64,
+    replaces: Vec<i32>,
+}
+
+/// Generation stage filling the stone of newly generated columns with caves and ore veins
+///
+/// Caves are carved first, then the ores are placed in registration order
+pub struct Underground {
+    caves: Option<Caves>,
+    ores: Vec<Ore>,
+}
+
+impl Underground {
+    /// Runs the underground scripts at `paths` in order
+    ///
+    /// The last `set_caves` call is used, an ore registered again replaces the earlier one
+    pub fn load(
+        paths: &[String],
+        items: &ItemManager,
+        seed: i32,
+        limits: &ScriptLimits,
+    ) -> Result<Underground, ScriptError> {
+        let mut caves = None;
+        let mut ores: Vec<Ore> = Vec::new();
+        for path in paths {
+            let script = fs::read_to_string(path)
+                .map_err(|e| ScriptError::new(path, format!("unable to read script: {}", e)))?;
+
+            let lua = new_sandboxed_lua(limits);
+            run_limited(&lua, limits, |lua_ctx| {
+                lua_ctx.scope(|scope| {
+                    let set_caves = scope.create_function_mut(|_, table: Option<Table>| {
+                        caves = table
+                            .map(|table| Caves::from_table(&table, items, seed))
+                            .transpose()?;
+                        Ok(())
+                    })?;
+                    lua_ctx.globals().set("set_caves", set_caves)?;
+
+                    let register_ore = scope.create_function_mut(|_, table: Table| {
+                        let ore = Ore::from_table(&table, items)?;
+                        match ores.iter_mut().find(|other| other.name == ore.name) {
+                            Some(other) => *other = ore,
+                            None => ores.push(ore),
+                        }
+                        Ok(())
+                    })?;
+                    lua_ctx.globals().set("register_ore", register_ore)?;
+
+                    lua_ctx.load(&script).set_name(&chunk_name(path))?.exec()
+                })
+            })
+            .map_err(|e| ScriptError::from_lua(path, &e))?;
+        }
+
+        Ok(Underground { caves, ores })
+    }
+
+    /// Carves the caves and places the ores of `column`, which was just generated
+    pub fn generate(&self, context: &GenerationContext, column: &mut ChunkColumn) {
+        if let Some(caves) = &self.caves {
+            caves.carve(context, column);
+        }
+        for ore in &self.ores {
+            ore.place(context, column);
+        }
+    }
+}
+
+impl Caves {
+    /// Reads the caves from the table passed to `set_caves`
+    fn from_table(table: &Table, items: &ItemManager, seed: i32) -> rlua::Result<Caves> {
+        let invalid =
+            |message: &str| rlua::Error::RuntimeError(format!("invalid caves: {}", message));
+
+        let noise: Option<Table> = table.get("noise")?;
+        let noise = match noise {
+            Some(settings) => NamedNoise::from_table(seed, &settings)?,
+            None => return Err(invalid("noise settings are missing")),
+        };
+        let threshold: Option<f32> = table.get("threshold")?;
+        let min_height: Option<i32> = table.get("min_height")?;
+        let max_height: Option<i32> = table.get("max_height")?;
+        let replaces: Option<Vec<String>> = table.get("replaces")?;
+
+        let caves = Caves {
+            noise,
+            threshold: threshold.unwrap_or(0.5),
+            min_height: min_height.unwrap_or(i32::MIN),
+            max_height: max_height.unwrap_or(i32::MAX),
+            replaces: block_ids(replaces, items).map_err(|e| invalid(&e))?,
+        };
+        if !caves.threshold.is_finite() {
+            return Err(invalid("threshold must be a finite number"));
+        }
+        if caves.min_height > caves.max_height {
+            return Err(invalid("min_height is above max_height"));
+        }
+
+        Ok(caves)
+    }
+
+    /// Replaces the blocks of `column` where the noise is above the threshold with air
+    fn carve(&self, context: &GenerationContext, column: &mut ChunkColumn) {
+        let min_y = self.min_height.max(context.height.min_block_y());
+        let max_y = self.max_height.min(context.height.max_block_y());
+        if min_y > max_y {
+            return;
+        }
+
+        // Sampling every block is too slow, the noise is sampled on a coarse grid and interpolated
+        let step = CAVE_SAMPLE_STEP;
+        let base_y = min_y.div_euclid(step) * step;
+        let samples_xz = (CHUNK_SIZE / step + 1) as usize;
+        let samples_y = ((max_y - base_y) / step + 2) as usize;
+        let origin_x = context.position.x as f32 * CHUNK_SIZE as f32;
+        let origin_z = context.position.y as f32 * CHUNK_SIZE as f32;
+        let index = |x: usize, y: usize, z: usize| (x * samples_xz + z) * samples_y + y;
+
+        let mut samples = vec![0.0; samples_xz * samples_xz * samples_y];
+        for x in 0..samples_xz {
+            for z in 0..samples_xz {
+                for y in 0..samples_y {
+                    samples[index(x, y, z)] = self.noise.get_3d(
+                        origin_x + (x as i32 * step) as f32,
+                        (base_y + y as i32 * step) as f32,
+                        origin_z + (z as i32 * step) as f32,
+                    );
+                }
+            }
+        }
+
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in min_y..=max_y {
+                    let position = Vec3::new(x, y, z);
+                    let replaceable = column
+                        .get_block(&position)
+                        .is_ok_and(|id| self.replaces.contains(&id));
+                    if !replaceable {
+                        continue;
+                    }
+
+                    let (sample_x, weight_x) =
+                        ((x / step) as usize, (x % step) as f32 / step as f32);
+                    let (sample_z, weight_z) =
+                        ((z / step) as usize, (z % step) as f32 / step as f32);
+                    let offset_y = y - base_y;
+                    let (sample_y, weight_y) = (
+                        (offset_y / step) as usize,
+                        (offset_y % step) as f32 / step as f32,
+                    );
+
+                    let mut value = 0.0;
+                    for (corner_x, factor_x) in [(0, 1.0 - weight_x), (1, weight_x)] {
+                        for (corner_z, factor_z) in [(0, 1.0 - weight_z), (1, weight_z)] {
+                            for (corner_y, factor_y) in [(0, 1.0 - weight_y), (1, weight_y)] {
+                                let sample = samples[index(
+                                    sample_x + corner_x,
+                                    sample_y + corner_y,
+                                    sample_z + corner_z,
+                                )];
+                                value += sample * factor_x * factor_y * factor_z;
+                            }
+                        }
+                    }
+
+                    if value > self.threshold {
+                        column.set_block(&position, 0).ok();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Ore {
+    /// Reads an ore from the table passed to `register_ore`
+    fn from_table(table: &Table, items: &ItemManager) -> rlua::Result<Ore> {
+        let name: String = table.get("name")?;
+        let invalid =
+            |message: &str| rlua::Error::RuntimeError(format!("invalid ore {}: {}", name, message));
+
+        let block: String = table.get("block")?;
+        let min_height: Option<i32> = table.get("min_height")?;
+        let max_height: Option<i32> = table.get("max_height")?;
+        let vein_size: Option<i32> = table.get("vein_size")?;
+        let frequency: Option<f64> = table.get("frequency")?;
+        let replaces: Option<Vec<String>> = table.get("replaces")?;
+
+        let ore = Ore {
+            block: items
+                .get_id_by_name(block.clone())
+                .ok_or_else(|| invalid(&format!("unknown block {}", block)))?,
+            min_height: min_height.unwrap_or(i32::MIN),
+            max_height: max_height.unwrap_or(i32::MAX),
+            vein_size: vein_size.unwrap_or(8),
+            frequency: frequency.unwrap_or(1.0),
+            replaces: block_ids(replaces, items).map_err(|e| invalid(&e))?,
+            name: name.clone(),
+        };
+        if ore.min_height > ore.max_height {
+            return Err(invalid("min_height is above max_height"));
+        }
+        if !(1..=MAX_VEIN_SIZE).contains(&ore.vein_size) {
+            return Err(invalid(&format!(
+                "vein_size must be between 1 and {}",
+                MAX_VEIN_SIZE
+            )));
+        }
+        if !(0.0..=MAX_VEINS_PER_COLUMN).contains(&ore.frequency) {
+            return Err(invalid(&format!(
+                "frequency must be between 0 and {}",
+                MAX_VEINS_PER_COLUMN
+            )));
+        }
+
+        Ok(ore)
+    }
+
+    /// Places the veins of `column`, the same for every generation of the column
+    fn place(&self, context: &GenerationContext, column: &mut ChunkColumn) {
+        let min_y = self.min_height.max(context.height.min_block_y());
+        let max_y = self.max_height.min(context.height.max_block_y());
+        if min_y > max_y {
+            return;
+        }
+
+        let mut rng =
+            StdRng::seed_from_u64(feature_seed(context.seed, &self.name, &context.position));
+        let veins = self.frequency as i32 + rng.gen_bool(self.frequency.fract()) as i32;
+        for _ in 0..veins {
+            let mut position = Vec3::new(
+                rng.gen_range(0..CHUNK_SIZE),
+                rng.gen_range(min_y..=max_y),
+                rng.gen_range(0..CHUNK_SIZE),
+            );
+
+            // Veins wander from their start, the parts leaving the column or the height range are cut off
+            for _ in 0..self.vein_size {
+                let replaceable = (min_y..=max_y).contains(&position.y)
+                    && column
+                        .get_block(&position)
+                        .is_ok_and(|id| self.replaces.contains(&id));
+                if replaceable {
+                    column.set_block(&position, self.block).ok();
+                }
+
+                let direction = if rng.gen() { 1 } else { -1 };
+                match rng.gen_range(0..3) {
+                    0 => position.x += direction,
+                    1 => position.y = position.y.saturating_add(direction),
+                    _ => position.z += direction,
+                }
+            }
+        }
+    }
+}
+
+/// Looks up the blocks named in `replaces`, only stone if there are none
+fn block_ids(replaces: Option<Vec<String>>, items: &ItemManager) -> Result<Vec<i32>, String> {
+    replaces
+        .unwrap_or_else(|| vec!["Stone".to_string()])
+        .into_iter()
+        .map(|block| {
+            items
+                .get_id_by_name(block.clone())
+                .ok_or(format!("unknown block {}", block))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::default_items;
+    use crate::scripting::with_script_file;
+
+    fn load_underground(script: &str) -> Result<Underground, ScriptError> {
+        let items = default_items();
+        with_script_file(script, |paths| {
+            Underground::load(paths, &items, 42, &ScriptLimits::default())
+        })
+    }
+
+    #[test]
+    fn test_underground_registration() {
+        let script = fs::read_to_string("./default_scripts/underground.lua").unwrap();
+        let underground = load_underground(&script).unwrap();
+        assert!(underground.caves.is_some());
+        assert_eq!(underground.ores.len(), 2);
+
+        let underground = load_underground(&format!(
+            "{}\nset_caves(nil)\nregister_ore({{ name = \"coal\", block = \"Dirt\" }})",
+            script
+        ))
+        .unwrap();
+        assert!(underground.caves.is_none());
+        assert_eq!(underground.ores.len(), 2);
+        assert_eq!(underground.ores[0].vein_size, 8);
+    }
+
+    #[test]
+    fn test_invalid_underground() {
+        for call in [
+            "set_caves({})",
+            "set_caves({ noise = { octaves = 99 } })",
+            "set_caves({ noise = {}, min_height = 9, max_height = 1 })",
+            "set_caves({ noise = {}, replaces = { \"Nope\" } })",
+            "register_ore({ name = \"a\" })",
+            "register_ore({ name = \"a\", block = \"Nope\" })",
+            "register_ore({ name = \"a\", block = \"Dirt\", vein_size = 0 })",
+            "register_ore({ name = \"a\", block = \"Dirt\", frequency = 100 })",
+            "register_ore({ name = \"a\", block = \"Dirt\", min_height = 9, max_height = 1 })",
+        ] {
+            assert!(load_underground(call).is_err(), "{}", call);
+        }
+    }
+}